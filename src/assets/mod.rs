@@ -1,6 +1,6 @@
 use crate::{
-    character::{state::StateMachine, CharacterAsset},
-    r#match::stage::StageAsset,
+    character::CharacterAsset,
+    r#match::{stage::StageAsset, MatchConfig},
     AppState,
 };
 use bevy::{
@@ -72,8 +72,13 @@ fn start_loading(mut commands: Commands, asset_server: Res<AssetServer>) {
 fn check_loading(
     metadata: Res<FcMetadata>,
     asset_server: Res<AssetServer>,
+    config: Res<MatchConfig>,
     mut app_state: ResMut<State<AppState>>,
 ) {
+    // Some matches (i.e. spectated ones) are configured after startup.
+    if config.validate().is_err() {
+        return;
+    }
     let ids = metadata
         .characters
         .iter()
@@ -94,6 +99,9 @@ fn check_loading(
 fn cleanup_loading(characters: Res<Assets<CharacterAsset>>, stages: Res<Assets<StageAsset>>) {
     for (id, character) in characters.iter() {
         info!("Loaded character: {} ({:?})", character.short_name, id);
+        for error in character.validate() {
+            error!("Invalid character {}: {}", character.short_name, error);
+        }
     }
     for (id, stage) in stages.iter() {
         info!("Loaded stage: {} ({:?})", stage.name, id);
@@ -109,7 +117,6 @@ impl Plugin for FcAssetsPlugin {
         builder
             .add_asset::<CharacterAsset>()
             .add_asset::<StageAsset>()
-            .add_asset::<StateMachine>()
             .add_asset_loader(FcAssetLoader::<CharacterAsset>::new(&["chr"]))
             .add_asset_loader(FcAssetLoader::<StageAsset>::new(&["stage"]))
             .add_system_set(
@@ -0,0 +1,30 @@
+use bevy::math::Vec2;
+use serde::{Deserialize, Serialize};
+
+/// An action run by the simulation when a player enters a frame. Actions on the same frame
+/// run in order.
+///
+/// Horizontal values point in the direction the player is facing.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FrameAction {
+    SetVelocity(Vec2),
+    AddVelocity(Vec2),
+    /// Restores all of the player's jumps.
+    ResetJumps,
+    /// Scales the character's gravity until the player changes states.
+    SetGravityMultiplier(f32),
+    /// Starts fast falling, if the player is airborne.
+    FastFall,
+    /// Sets one of the character's variables, which transitions can check.
+    SetVariable {
+        variable: usize,
+        value: i32,
+    },
+    /// Plays a sound by name.
+    PlaySound(String),
+    /// Plays a visual effect by name, relative to the player.
+    PlayVfx {
+        effect: String,
+        offset: Vec2,
+    },
+}
@@ -40,7 +40,7 @@ impl PartialOrd for HitboxPriority {
     }
 }
 
-#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, PartialEq, Deserialize, Serialize)]
 pub struct Hitbox {
     pub flags: HitboxFlags,
     pub radius: f32,
@@ -29,7 +29,7 @@ impl HurtboxType {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Hurtbox {
     pub id: u8,
     pub player: PlayerId,
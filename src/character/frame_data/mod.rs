@@ -2,6 +2,7 @@ use bevy::math::Vec2;
 use serde::{Deserialize, Serialize};
 use std::ops::{Add, Sub};
 
+pub mod action;
 pub mod hitbox;
 pub mod hurtbox;
 
@@ -24,7 +25,7 @@ bitflags! {
     }
 }
 
-#[derive(Default, Clone, Debug, Serialize, Deserialize)]
+#[derive(Default, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CharacterFrame {
     pub flags: CharacterFrameFlags,
     /// Bitfield where 1s demark an active hitbox.
@@ -38,6 +39,9 @@ pub struct CharacterFrame {
     /// A flat amount of knockback force subtracted from all knockback dealt to the player
     /// during this frame. If infinite, the player has super armor.
     pub knockback_resistance: f32,
+    /// Actions run in order when the frame is entered.
+    #[serde(default)]
+    pub actions: Vec<action::FrameAction>,
 }
 
 impl CharacterFrame {
@@ -67,7 +71,7 @@ impl StateFrameData {
     }
 }
 
-#[derive(Debug, Default, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct ScalableValue {
     pub base: f32,
     pub growth: f32,
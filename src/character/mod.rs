@@ -1,15 +1,69 @@
+use anyhow::Context;
 use bevy::reflect::TypeUuid;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
 
 pub mod frame_data;
 pub mod state;
 
+use self::state::{StateMachine, StateMachineValidationError};
+
 #[derive(Serialize, Deserialize, Debug, TypeUuid)]
 #[uuid = "230e1b7b-5d32-4159-91c1-45e162e7b3fc"]
 pub struct CharacterAsset {
+    /// The ID players select the character by. Must be unique between characters.
+    pub id: u32,
     pub short_name: String,
     pub long_name: String,
     pub palletes: Vec<CharacterPallette>,
+    #[serde(default)]
+    pub movement: CharacterMovement,
+    /// The character's states along with their transitions and frame data.
+    #[serde(default)]
+    pub state_machine: StateMachine,
+}
+
+impl CharacterAsset {
+    /// Reads a character from a .chr file.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to open character {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("failed to read character {}", path.display()))
+    }
+
+    /// Checks the character for problems that would keep it from being played, returning
+    /// every one found.
+    pub fn validate(&self) -> Vec<StateMachineValidationError> {
+        state::validate(&self.state_machine)
+    }
+}
+
+/// The stats that govern how a character moves.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CharacterMovement {
+    pub weight: f32,
+    pub gravity: f32,
+    /// The vertical velocity given by each of the character's jumps, in order.
+    pub jump_power: Vec<f32>,
+    pub short_jump_power: f32,
+    pub max_fall_speed: f32,
+    pub fast_fall_speed: f32,
+}
+
+impl Default for CharacterMovement {
+    fn default() -> Self {
+        Self {
+            weight: 0.0,
+            gravity: 1.0,
+            jump_power: vec![2.5, 1.5],
+            short_jump_power: 0.9,
+            max_fall_speed: 2.0,
+            fast_fall_speed: 5.0,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
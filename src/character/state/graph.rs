@@ -0,0 +1,193 @@
+use super::{state::StateId, StateMachine, StateTransition};
+use anyhow::anyhow;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// A text format to export a state machine's graph to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz DOT.
+    Dot,
+    Mermaid,
+}
+
+impl Default for GraphFormat {
+    fn default() -> Self {
+        Self::Dot
+    }
+}
+
+impl FromStr for GraphFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "dot" => Ok(Self::Dot),
+            "mermaid" => Ok(Self::Mermaid),
+            _ => Err(anyhow!("expected one of dot or mermaid")),
+        }
+    }
+}
+
+impl GraphFormat {
+    /// Renders a state machine as a graph with a node for each state and an edge for each
+    /// transition.
+    pub fn export(self, machine: &StateMachine) -> String {
+        match self {
+            Self::Dot => to_dot(machine),
+            Self::Mermaid => to_mermaid(machine),
+        }
+    }
+}
+
+/// Gets the states of a state machine sorted by ID, so that exports are stable.
+fn sorted_ids(machine: &StateMachine) -> Vec<StateId> {
+    let mut ids: Vec<StateId> = machine.iter().map(|(id, _)| *id).collect();
+    ids.sort_unstable();
+    ids
+}
+
+/// Gets the lines of a transition's label: its name, if it has one, followed by its
+/// conditions.
+fn transition_label(transition: &StateTransition) -> Vec<String> {
+    let conditions: Vec<String> = transition
+        .transition
+        .iter()
+        .map(|condition| condition.to_string())
+        .collect();
+    transition
+        .name
+        .iter()
+        .cloned()
+        .chain(std::iter::once(conditions.join(" and ")))
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// Renders a state machine as a Graphviz DOT digraph.
+pub fn to_dot(machine: &StateMachine) -> String {
+    let escape = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+    let mut dot = String::from("digraph {\n");
+    for id in sorted_ids(machine) {
+        let state = machine.get_state(id).unwrap();
+        writeln!(
+            dot,
+            "    s{} [label=\"{}\\n{} frames\"];",
+            id,
+            escape(&state.name),
+            state.frame_data.frames.len()
+        )
+        .unwrap();
+        for transition in state.transitions.iter() {
+            let label: Vec<String> = transition_label(transition)
+                .iter()
+                .map(|line| escape(line))
+                .collect();
+            writeln!(
+                dot,
+                "    s{} -> s{} [label=\"{}\"];",
+                id,
+                transition.target_state,
+                label.join("\\n")
+            )
+            .unwrap();
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Renders a state machine as a Mermaid flowchart.
+pub fn to_mermaid(machine: &StateMachine) -> String {
+    let escape = |text: &str| text.replace('"', "#quot;");
+    let mut mermaid = String::from("flowchart TD\n");
+    for id in sorted_ids(machine) {
+        let state = machine.get_state(id).unwrap();
+        writeln!(
+            mermaid,
+            "    s{}[\"{}<br/>{} frames\"]",
+            id,
+            escape(&state.name),
+            state.frame_data.frames.len()
+        )
+        .unwrap();
+        for transition in state.transitions.iter() {
+            let label: Vec<String> = transition_label(transition)
+                .iter()
+                .map(|line| escape(line))
+                .collect();
+            if label.is_empty() {
+                writeln!(mermaid, "    s{} --> s{}", id, transition.target_state).unwrap();
+            } else {
+                writeln!(
+                    mermaid,
+                    "    s{} -->|\"{}\"| s{}",
+                    id,
+                    label.join("<br/>"),
+                    transition.target_state
+                )
+                .unwrap();
+            }
+        }
+    }
+    mermaid
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::character::frame_data::{CharacterFrame, StateFrameData};
+    use crate::character::state::{State, TransitionCondition};
+    use crate::r#match::input::Buttons;
+
+    fn machine() -> StateMachine {
+        let mut machine = StateMachine::default();
+        for (name, frames) in [("Action_Wait", 10), ("Action_\"Jab\"", 4)].iter() {
+            machine.add_state(State {
+                name: name.to_string(),
+                transitions: Vec::new(),
+                frame_data: StateFrameData {
+                    hitboxes: Vec::new(),
+                    frames: vec![CharacterFrame::default(); *frames],
+                },
+            });
+        }
+        let jab = machine.create_transition(0, 1).unwrap();
+        jab.name = Some("jab".to_string());
+        jab.transition = vec![
+            TransitionCondition::Grounded,
+            TransitionCondition::ButtonTapped(Buttons::ATTACK),
+        ];
+        machine
+            .create_transition(1, 0)
+            .unwrap()
+            .transition
+            .push(TransitionCondition::StateEnd);
+        machine
+    }
+
+    #[test]
+    pub fn test_export_dot() {
+        assert_eq!(
+            to_dot(&machine()),
+            "digraph {\n\
+             \x20   s0 [label=\"Action_Wait\\n10 frames\"];\n\
+             \x20   s0 -> s1 [label=\"jab\\ngrounded and tapped ATTACK\"];\n\
+             \x20   s1 [label=\"Action_\\\"Jab\\\"\\n4 frames\"];\n\
+             \x20   s1 -> s0 [label=\"state end\"];\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    pub fn test_export_mermaid() {
+        assert_eq!(
+            to_mermaid(&machine()),
+            "flowchart TD\n\
+             \x20   s0[\"Action_Wait<br/>10 frames\"]\n\
+             \x20   s0 -->|\"jab<br/>grounded and tapped ATTACK\"| s1\n\
+             \x20   s1[\"Action_#quot;Jab#quot;<br/>4 frames\"]\n\
+             \x20   s1 -->|\"state end\"| s0\n"
+        );
+    }
+}
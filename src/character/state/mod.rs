@@ -1,18 +1,33 @@
+mod graph;
 mod state;
 mod transition;
+mod validation;
 
 use crate::character::frame_data::CharacterFrame;
-use bevy::reflect::TypeUuid;
+use crate::r#match::input::PlayerInput;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+pub use self::graph::*;
 pub use self::state::*;
 pub use self::transition::*;
+pub use self::validation::*;
 
-#[derive(Debug, Default)]
+/// The number of variables each character can set through frame actions.
+pub const CHARACTER_VARIABLE_COUNT: usize = 8;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub struct PlayerState {
     pub state_id: StateId,
+    /// The next frame of the state to sample. Equals the state's length once its last frame
+    /// has been sampled.
     pub frame: usize,
+    /// Set if the player was grounded when its state was last updated.
+    pub grounded: bool,
+    /// Set if the player has been hit since its state was last updated.
+    pub hit: bool,
+    /// Values set by the character's frame actions.
+    pub variables: [i32; CHARACTER_VARIABLE_COUNT],
 }
 
 impl PlayerState {
@@ -21,8 +36,7 @@ impl PlayerState {
     }
 }
 
-#[derive(Default, Debug, Serialize, Deserialize, TypeUuid)]
-#[uuid = "14fed4e9-98dc-444d-9791-dcd7f561f714"]
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct StateMachine(HashMap<StateId, State>);
 
 impl StateMachine {
@@ -34,6 +48,23 @@ impl StateMachine {
         state.frame_data.get_frame(player_state.frame)
     }
 
+    /// Finds the state a player should transition to. Outgoing transitions of the player's
+    /// current state are checked in order and the target of the first satisfied one is
+    /// returned. Returns None if no transition is satisfied or the state does not exist.
+    pub fn next_state(
+        &self,
+        player_state: &PlayerState,
+        input: &PlayerInput,
+        context: &TransitionContext,
+    ) -> Option<StateId> {
+        let state = self.get_state(player_state.state_id)?;
+        state
+            .transitions
+            .iter()
+            .find(|transition| transition.is_satisfied(state, player_state, input, context))
+            .map(|transition| transition.target_state)
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&StateId, &State)> {
         self.0.iter()
     }
@@ -94,21 +125,95 @@ impl StateMachine {
     }
 }
 
-pub enum StateMachineValidationError {
-    InvalidTransitionTarget { from: StateId, to: StateId },
-}
-
-/// Validates whether a state machine has entirely correct construction.
-pub fn validate(machine: StateMachine) -> Result<(), StateMachineValidationError> {
-    for (id, state) in machine.iter() {
-        for transition in state.transitions.iter() {
-            if !machine.contains_state(transition.target_state) {
-                return Err(StateMachineValidationError::InvalidTransitionTarget {
-                    from: *id,
-                    to: transition.target_state,
-                });
-            }
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::character::frame_data::StateFrameData;
+    use crate::r#match::input::{Buttons, PlayerInputFrame, SmashDetection};
+    use bevy::math::Vec2;
+
+    fn state(name: &str, frames: usize) -> State {
+        State {
+            name: name.to_string(),
+            transitions: Vec::new(),
+            frame_data: StateFrameData {
+                hitboxes: Vec::new(),
+                frames: vec![CharacterFrame::default(); frames],
+            },
         }
     }
-    Ok(())
+
+    #[test]
+    pub fn test_next_state_picks_first_satisfied_transition() {
+        let mut machine = StateMachine::default();
+        let idle = machine.add_state(state("idle", 10));
+        let jump = machine.add_state(state("jump", 5));
+        let attack = machine.add_state(state("attack", 5));
+        machine
+            .create_transition(idle, jump)
+            .unwrap()
+            .transition
+            .push(TransitionCondition::ButtonTapped(Buttons::JUMP));
+        machine
+            .create_transition(idle, attack)
+            .unwrap()
+            .transition
+            .push(TransitionCondition::ButtonHeld(Buttons::ATTACK));
+        machine.create_transition(jump, idle).unwrap();
+        machine
+            .create_transition(jump, idle)
+            .unwrap()
+            .transition
+            .push(TransitionCondition::StateEnd);
+
+        let mut input = PlayerInput::default();
+        let mut player = PlayerState {
+            state_id: idle,
+            frame: 3,
+            ..Default::default()
+        };
+        let context = TransitionContext::default();
+        assert_eq!(machine.next_state(&player, &input, &context), None);
+
+        input.push(
+            PlayerInputFrame {
+                buttons: Buttons::JUMP | Buttons::ATTACK,
+                ..Default::default()
+            },
+            &SmashDetection::default(),
+        );
+        assert_eq!(machine.next_state(&player, &input, &context), Some(jump));
+
+        // The transition without conditions is skipped.
+        player.state_id = jump;
+        assert_eq!(machine.next_state(&player, &input, &context), None);
+        player.frame = 5;
+        assert_eq!(machine.next_state(&player, &input, &context), Some(idle));
+    }
+
+    #[test]
+    pub fn test_conditions_combine() {
+        let idle = state("idle", 10);
+        let player = PlayerState::default();
+        let input = PlayerInput::default();
+        let context = TransitionContext {
+            velocity: Vec2::new(0.0, -1.0),
+            jumps_remaining: 1,
+            ..Default::default()
+        };
+        let evaluate =
+            |condition: TransitionCondition| condition.evaluate(&idle, &player, &input, &context);
+        assert!(evaluate(TransitionCondition::All(vec![
+            TransitionCondition::Airborne,
+            TransitionCondition::Falling,
+            TransitionCondition::JumpsRemaining(1),
+        ])));
+        assert!(!evaluate(TransitionCondition::JumpsRemaining(2)));
+        assert!(evaluate(TransitionCondition::Any(vec![
+            TransitionCondition::Grounded,
+            TransitionCondition::Not(Box::new(TransitionCondition::WasHit)),
+        ])));
+        assert!(!evaluate(TransitionCondition::Any(Vec::new())));
+        assert!(evaluate(TransitionCondition::All(Vec::new())));
+    }
 }
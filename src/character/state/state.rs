@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 
 pub type StateId = usize;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct State {
     /// A debug name for the state.
     pub name: String,
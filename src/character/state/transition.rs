@@ -1,8 +1,16 @@
-use super::state::StateId;
-use crate::r#match::input::Buttons;
+use super::{
+    state::{State, StateId},
+    PlayerState,
+};
+use crate::r#match::input::{Buttons, PlayerInput, Stick, StickDirection};
+use bevy::math::Vec2;
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
-#[derive(Default, Debug, Serialize, Deserialize)]
+/// How far the smash stick must be pushed for it to count as a smash input.
+const SMASH_INPUT_THRESHOLD: f32 = 0.5;
+
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct StateTransition {
     /// Optional debug name for the transition.
     pub name: Option<String>,
@@ -13,11 +21,46 @@ pub struct StateTransition {
     pub transition: Vec<TransitionCondition>,
 }
 
+impl StateTransition {
+    /// Checks if every condition of the transition is satisfied. Transitions without any
+    /// conditions never fire.
+    pub fn is_satisfied(
+        &self,
+        state: &State,
+        player: &PlayerState,
+        input: &PlayerInput,
+        context: &TransitionContext,
+    ) -> bool {
+        !self.transition.is_empty()
+            && self
+                .transition
+                .iter()
+                .all(|condition| condition.evaluate(state, player, input, context))
+    }
+}
+
+/// The parts of a player's simulation state that transition conditions can check,
+/// gathered once per frame before transitions are evaluated.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TransitionContext {
+    pub grounded: bool,
+    /// Set if the player became grounded on the current frame.
+    pub just_landed: bool,
+    pub velocity: Vec2,
+    pub jumps_remaining: usize,
+    /// Set if the player was hit on the previous frame.
+    pub was_hit: bool,
+    /// Set if any of the player's hurtboxes is a shield.
+    pub shielding: bool,
+    /// Set if the player is airborne and close enough to grab a ledge.
+    pub ledge_nearby: bool,
+}
+
 /// A singular condition for a state transition.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum TransitionCondition {
     /// Fires true when the state's animation has completed. Functionally the same as
-    /// using PassedFrame with the length of the state.
+    /// using PassedFrame with the last frame of the state.
     StateEnd,
     /// Fires true when the state has passed a specific frame. Good for creating early
     /// cancellations to states.
@@ -28,4 +71,168 @@ pub enum TransitionCondition {
     /// should only used with one button, as players would need to have frame perfect
     /// presses on multiple buttons.
     ButtonTapped(Buttons),
+    /// Fires true when one or more buttons were pressed simultaneously on the current
+    /// frame or any of the given number of frames before it. Useful for buffering inputs
+    /// so that players do not need frame perfect timing.
+    ButtonPressedWithin { buttons: Buttons, frames: usize },
+    /// Fires true when one or more buttons have been held simultaneously for at least
+    /// the given number of frames.
+    ButtonHeldFor { buttons: Buttons, frames: usize },
+    /// Fires true when a stick was pushed past the threshold in the given direction on the
+    /// current frame or any of the given number of frames before it. The threshold ranges
+    /// from 0.0 to 1.0.
+    StickCrossedWithin {
+        stick: Stick,
+        direction: StickDirection,
+        threshold: f32,
+        frames: usize,
+    },
+    /// Fires true when the stick is currently pushed between `min` and `max` in the given
+    /// direction, inclusive. Both range from 0.0 to 1.0.
+    StickZone {
+        stick: Stick,
+        direction: StickDirection,
+        min: f32,
+        max: f32,
+    },
+    /// Fires true on the frame the smash stick is pushed in the given direction, whether
+    /// from the smash stick itself or a flick of the movement stick.
+    SmashInput(StickDirection),
+    /// Fires true when the player is standing on the stage.
+    Grounded,
+    /// Fires true when the player is in the air.
+    Airborne,
+    /// Fires true on the frame the player lands on the stage.
+    JustLanded,
+    /// Fires true when the player is moving upwards.
+    Rising,
+    /// Fires true when the player is moving downwards.
+    Falling,
+    /// Fires true when the player has at least the given number of jumps left.
+    JumpsRemaining(usize),
+    /// Fires true on the frame after the player was hit.
+    WasHit,
+    /// Fires true when the player has a shield up.
+    Shielding,
+    /// Fires true when the player is in the air and within reach of a grabbable ledge.
+    LedgeNearby,
+    /// Fires true when one of the character's variables has the given value.
+    Variable { variable: usize, value: i32 },
+    /// Fires true when the inner condition does not.
+    Not(Box<TransitionCondition>),
+    /// Fires true when at least one of the inner conditions does.
+    Any(Vec<TransitionCondition>),
+    /// Fires true when all of the inner conditions do.
+    All(Vec<TransitionCondition>),
+}
+
+impl TransitionCondition {
+    /// Checks if the condition is satisfied for a player in the given state.
+    pub fn evaluate(
+        &self,
+        state: &State,
+        player: &PlayerState,
+        input: &PlayerInput,
+        context: &TransitionContext,
+    ) -> bool {
+        let evaluate = |condition: &Self| condition.evaluate(state, player, input, context);
+        match self {
+            Self::StateEnd => player.frame >= state.frame_data.frames.len(),
+            Self::PassedFrame(frame) => player.frame > *frame,
+            Self::ButtonHeld(buttons) => input.current.buttons.contains(*buttons),
+            Self::ButtonTapped(buttons) => input.history.pressed_within(*buttons, 0),
+            Self::ButtonPressedWithin { buttons, frames } => {
+                input.history.pressed_within(*buttons, *frames)
+            }
+            Self::ButtonHeldFor { buttons, frames } => input.history.held_for(*buttons, *frames),
+            Self::StickCrossedWithin {
+                stick,
+                direction,
+                threshold,
+                frames,
+            } => input
+                .history
+                .stick_crossed_within(*stick, *direction, *threshold, *frames),
+            Self::StickZone {
+                stick,
+                direction,
+                min,
+                max,
+            } => input.history.stick_within(*stick, *direction, *min, *max),
+            Self::SmashInput(direction) => input.history.stick_crossed_within(
+                Stick::Smash,
+                *direction,
+                SMASH_INPUT_THRESHOLD,
+                0,
+            ),
+            Self::Grounded => context.grounded,
+            Self::Airborne => !context.grounded,
+            Self::JustLanded => context.just_landed,
+            Self::Rising => context.velocity.y > 0.0,
+            Self::Falling => context.velocity.y < 0.0,
+            Self::JumpsRemaining(jumps) => context.jumps_remaining >= *jumps,
+            Self::WasHit => context.was_hit,
+            Self::Shielding => context.shielding,
+            Self::LedgeNearby => context.ledge_nearby,
+            Self::Variable { variable, value } => player.variables.get(*variable) == Some(value),
+            Self::Not(condition) => !evaluate(condition),
+            Self::Any(conditions) => conditions.iter().any(evaluate),
+            Self::All(conditions) => conditions.iter().all(evaluate),
+        }
+    }
+}
+
+impl fmt::Display for TransitionCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |conditions: &[Self], separator: &str| {
+            let conditions: Vec<String> = conditions.iter().map(Self::to_string).collect();
+            format!("({})", conditions.join(separator))
+        };
+        match self {
+            Self::StateEnd => write!(f, "state end"),
+            Self::PassedFrame(frame) => write!(f, "after frame {}", frame),
+            Self::ButtonHeld(buttons) => write!(f, "held {:?}", buttons),
+            Self::ButtonTapped(buttons) => write!(f, "tapped {:?}", buttons),
+            Self::ButtonPressedWithin { buttons, frames } => {
+                write!(f, "pressed {:?} within {} frames", buttons, frames)
+            }
+            Self::ButtonHeldFor { buttons, frames } => {
+                write!(f, "held {:?} for {} frames", buttons, frames)
+            }
+            Self::StickCrossedWithin {
+                stick,
+                direction,
+                threshold,
+                frames,
+            } => write!(
+                f,
+                "{:?} stick {:?} past {} within {} frames",
+                stick, direction, threshold, frames
+            ),
+            Self::StickZone {
+                stick,
+                direction,
+                min,
+                max,
+            } => write!(
+                f,
+                "{:?} stick {:?} between {} and {}",
+                stick, direction, min, max
+            ),
+            Self::SmashInput(direction) => write!(f, "smash {:?}", direction),
+            Self::Grounded => write!(f, "grounded"),
+            Self::Airborne => write!(f, "airborne"),
+            Self::JustLanded => write!(f, "just landed"),
+            Self::Rising => write!(f, "rising"),
+            Self::Falling => write!(f, "falling"),
+            Self::JumpsRemaining(jumps) => write!(f, "{} jumps remaining", jumps),
+            Self::WasHit => write!(f, "was hit"),
+            Self::Shielding => write!(f, "shielding"),
+            Self::LedgeNearby => write!(f, "ledge nearby"),
+            Self::Variable { variable, value } => write!(f, "variable {} is {}", variable, value),
+            Self::Not(condition) => write!(f, "not {}", condition),
+            Self::Any(conditions) => write!(f, "{}", join(conditions, " or ")),
+            Self::All(conditions) => write!(f, "{}", join(conditions, " and ")),
+        }
+    }
 }
@@ -0,0 +1,278 @@
+use super::{state::StateId, StateMachine};
+use crate::character::frame_data::{CharacterFrameFlags, CHARACTER_HITBOX_COUNT};
+use std::collections::HashSet;
+use std::fmt;
+
+/// The states every character must have, as listed in docs/character-pipeline.md.
+pub const RESERVED_STATES: &[&str] = &[
+    "Action_Wait",
+    "Action_Walk",
+    "Action_Dash",
+    "Action_Run",
+    "Action_RunBrake",
+    "Action_Fall",
+    "Action_FallHelpless",
+    "Action_Grab",
+    "Action_Grabbed",
+    "Action_LedgeGrab",
+    "Action_LedgeHang",
+    "Action_LedgeClimb",
+    "Action_LedgeTeeter",
+    "Action_Turn",
+    "Action_TurnDash",
+    "Action_TurnRun",
+    "Action_TurnRunBrake",
+    "Action_JumpSquat",
+    "Action_JumpForward",
+    "Action_JumpBackward",
+    "Action_JumpAerialForwards",
+    "Action_Squat",
+    "Action_SquatEnter",
+    "Action_SquatExit",
+    "Action_LandHeavy",
+    "Action_LandLight",
+    "Action_LandAerial",
+    "Action_LandSpecial",
+    "Action_ShieldEnter",
+    "Action_Shield",
+    "Action_ShieldExit",
+    "Action_ShieldStun",
+    "Action_Escape",
+    "Action_EscapeForward",
+    "Action_EscapeBackward",
+    "Action_EscapeAir",
+    "Action_Rebound",
+    "Action_DamageFall",
+];
+
+/// The state players start a match in.
+const INITIAL_STATE: StateId = 0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateMachineValidationError {
+    InvalidTransitionTarget {
+        from: StateId,
+        to: StateId,
+    },
+    MissingReservedState(&'static str),
+    /// The state cannot be reached from the initial state or any of the reserved states.
+    UnreachableState(StateId),
+    EmptyState(StateId),
+    /// The state has more hitboxes than a character can have active at once.
+    TooManyHitboxes {
+        state: StateId,
+        hitboxes: usize,
+    },
+    /// The frame activates hitboxes that the state does not have.
+    InvalidActiveHitboxes {
+        state: StateId,
+        frame: usize,
+    },
+    /// The frame is flagged to face both left and right.
+    ConflictingFacing {
+        state: StateId,
+        frame: usize,
+    },
+    /// The hitbox's radius is NaN, infinite or negative.
+    InvalidHitboxRadius {
+        state: StateId,
+        hitbox: usize,
+    },
+    NegativeHitboxDamage {
+        state: StateId,
+        hitbox: usize,
+    },
+}
+
+impl fmt::Display for StateMachineValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidTransitionTarget { from, to } => write!(
+                f,
+                "state {} transitions to state {}, which does not exist",
+                from, to
+            ),
+            Self::MissingReservedState(name) => write!(f, "missing reserved state {}", name),
+            Self::UnreachableState(state) => write!(f, "state {} is unreachable", state),
+            Self::EmptyState(state) => write!(f, "state {} has no frames", state),
+            Self::TooManyHitboxes { state, hitboxes } => write!(
+                f,
+                "state {} has {} hitboxes, more than the limit of {}",
+                state, hitboxes, CHARACTER_HITBOX_COUNT
+            ),
+            Self::InvalidActiveHitboxes { state, frame } => write!(
+                f,
+                "frame {} of state {} activates hitboxes that do not exist",
+                frame, state
+            ),
+            Self::ConflictingFacing { state, frame } => write!(
+                f,
+                "frame {} of state {} faces both left and right",
+                frame, state
+            ),
+            Self::InvalidHitboxRadius { state, hitbox } => write!(
+                f,
+                "hitbox {} of state {} has an invalid radius",
+                hitbox, state
+            ),
+            Self::NegativeHitboxDamage { state, hitbox } => write!(
+                f,
+                "hitbox {} of state {} deals negative damage",
+                hitbox, state
+            ),
+        }
+    }
+}
+
+/// Validates whether a state machine has entirely correct construction. Returns every
+/// problem found, ordered by state.
+pub fn validate(machine: &StateMachine) -> Vec<StateMachineValidationError> {
+    let mut errors = Vec::new();
+    let mut ids: Vec<StateId> = machine.iter().map(|(id, _)| *id).collect();
+    ids.sort_unstable();
+
+    for name in RESERVED_STATES {
+        if !machine.states().any(|state| state.name == *name) {
+            errors.push(StateMachineValidationError::MissingReservedState(name));
+        }
+    }
+
+    // Reserved states are entered by the game itself, so they count as reachable.
+    let mut reachable = HashSet::new();
+    let mut pending: Vec<StateId> = machine
+        .iter()
+        .filter(|(id, state)| **id == INITIAL_STATE || RESERVED_STATES.contains(&&*state.name))
+        .map(|(id, _)| *id)
+        .collect();
+    while let Some(id) = pending.pop() {
+        if !reachable.insert(id) {
+            continue;
+        }
+        if let Some(state) = machine.get_state(id) {
+            pending.extend(state.transitions.iter().map(|t| t.target_state));
+        }
+    }
+
+    for id in ids {
+        let state = machine.get_state(id).unwrap();
+        for transition in state.transitions.iter() {
+            if !machine.contains_state(transition.target_state) {
+                errors.push(StateMachineValidationError::InvalidTransitionTarget {
+                    from: id,
+                    to: transition.target_state,
+                });
+            }
+        }
+        if !reachable.contains(&id) {
+            errors.push(StateMachineValidationError::UnreachableState(id));
+        }
+
+        let frame_data = &state.frame_data;
+        if frame_data.frames.is_empty() {
+            errors.push(StateMachineValidationError::EmptyState(id));
+        }
+        if frame_data.hitboxes.len() > CHARACTER_HITBOX_COUNT {
+            errors.push(StateMachineValidationError::TooManyHitboxes {
+                state: id,
+                hitboxes: frame_data.hitboxes.len(),
+            });
+        }
+        let hitbox_count = frame_data.hitboxes.len().min(CHARACTER_HITBOX_COUNT);
+        for (frame, data) in frame_data.frames.iter().enumerate() {
+            if hitbox_count < CHARACTER_HITBOX_COUNT && data.active_hitboxes >> hitbox_count != 0 {
+                errors
+                    .push(StateMachineValidationError::InvalidActiveHitboxes { state: id, frame });
+            }
+            if data
+                .flags
+                .contains(CharacterFrameFlags::FACE_LEFT | CharacterFrameFlags::FACE_RIGHT)
+            {
+                errors.push(StateMachineValidationError::ConflictingFacing { state: id, frame });
+            }
+        }
+        for (hitbox, data) in frame_data.hitboxes.iter().enumerate() {
+            if !data.radius.is_finite() || data.radius < 0.0 {
+                errors.push(StateMachineValidationError::InvalidHitboxRadius { state: id, hitbox });
+            }
+            if data.damage < 0.0 {
+                errors
+                    .push(StateMachineValidationError::NegativeHitboxDamage { state: id, hitbox });
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::character::frame_data::{hitbox::Hitbox, CharacterFrame, StateFrameData};
+    use crate::character::state::State;
+
+    fn state(name: &str, frames: Vec<CharacterFrame>, hitboxes: Vec<Hitbox>) -> State {
+        State {
+            name: name.to_string(),
+            transitions: Vec::new(),
+            frame_data: StateFrameData { hitboxes, frames },
+        }
+    }
+
+    #[test]
+    pub fn test_validate_reports_every_error() {
+        let mut machine = StateMachine::default();
+        for name in RESERVED_STATES.iter().skip(1) {
+            machine.add_state(state(name, vec![CharacterFrame::default()], Vec::new()));
+        }
+        let frame = CharacterFrame {
+            flags: CharacterFrameFlags::FACE_LEFT | CharacterFrameFlags::FACE_RIGHT,
+            active_hitboxes: 0b100,
+            ..Default::default()
+        };
+        let hitboxes = vec![
+            Hitbox {
+                radius: f32::NAN,
+                ..Default::default()
+            },
+            Hitbox {
+                radius: 1.0,
+                damage: -1.0,
+                ..Default::default()
+            },
+        ];
+        let broken = machine.add_state(state("broken", vec![frame], hitboxes));
+        let empty = machine.add_state(state("empty", Vec::new(), Vec::new()));
+        machine
+            .get_state_mut(0)
+            .unwrap()
+            .add_transition()
+            .target_state = 100;
+
+        let errors = validate(&machine);
+        assert_eq!(
+            errors,
+            vec![
+                StateMachineValidationError::MissingReservedState(RESERVED_STATES[0]),
+                StateMachineValidationError::InvalidTransitionTarget { from: 0, to: 100 },
+                StateMachineValidationError::UnreachableState(broken),
+                StateMachineValidationError::InvalidActiveHitboxes {
+                    state: broken,
+                    frame: 0
+                },
+                StateMachineValidationError::ConflictingFacing {
+                    state: broken,
+                    frame: 0
+                },
+                StateMachineValidationError::InvalidHitboxRadius {
+                    state: broken,
+                    hitbox: 0
+                },
+                StateMachineValidationError::NegativeHitboxDamage {
+                    state: broken,
+                    hitbox: 1
+                },
+                StateMachineValidationError::UnreachableState(empty),
+                StateMachineValidationError::EmptyState(empty),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1088 @@
+use crate::character::state::GraphFormat;
+use crate::input::profile::ControlProfiles;
+use crate::lobby::{
+    protocol::{MatchStart, DEFAULT_LOBBY_PORT, ROOM_SLOTS},
+    LobbyRequest,
+};
+use crate::r#match::{
+    backroll::SessionConfig, cpu::CpuLevel, disconnect::DisconnectPolicy, input::*,
+    player::PlayerConfig, rule::MatchRule, MatchConfig, MAX_PLAYERS_PER_MATCH,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use bevy::input::gamepad::Gamepad;
+use bevy_backroll::backroll;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The default port netplay sessions are bound to.
+pub const DEFAULT_PORT: u16 = 4001;
+
+/// The default number of frames rolled back every tick in sync test mode.
+pub const DEFAULT_SYNC_TEST_CHECK_DISTANCE: usize = 7;
+
+/// The default number of frames spectators buffer before playing back a match.
+pub const DEFAULT_SPECTATOR_DELAY: usize = 30;
+
+/// The default number of frames a headless match can run for: 8 minutes at 60 FPS.
+pub const DEFAULT_HEADLESS_MAX_FRAMES: u32 = 8 * 60 * 60;
+
+/// Offset from the session's port used to exchange state checksums between peers.
+const CHECKSUM_PORT_OFFSET: u16 = 1000;
+
+/// Offset from the session's port used to stream the match to spectators.
+const SPECTATOR_PORT_OFFSET: u16 = 2000;
+
+pub const USAGE: &str = "\
+USAGE:
+    fc [OPTIONS]
+    fc graph [--format <FORMAT>] <PATH>
+
+OPTIONS:
+    --local                     Play a local versus match on this machine (default)
+    --players <COUNT>           Number of local players in a local match (default: 2)
+    --join                      Let local players claim slots by pressing a button on
+                                their keyboard or gamepad before the match starts
+    --host <PORT>               Port to bind the netplay session to (default: 4001)
+    --connect <ADDR>            Address of the remote peer to play a netplay match with
+    --lobby <ADDR>              Address of a lobby server to find a netplay match through
+    --room <CODE>               Code of the lobby room to join, creates a new room if unset
+    --lobby-players <COUNT>     Players required in the lobby room before readying up
+                                (default: 2)
+    --player <SLOT>             The local player's slot in a netplay or lobby match
+                                (default: 0)
+    --character <ID>[,<ID>..]   Character IDs for each player slot, in order
+    --palette <ID>[,<ID>..]     Palettes for each player slot, in order
+    --controls <NAME>[,<NAME>..]
+                                Control profiles for each local player, in order
+                                (default: keyboard-left, keyboard-right, then gamepad).
+                                Use cpu or cpu:<easy|normal|hard> for CPU players
+                                outside of netplay
+    --input-delay <FRAMES>      Frames of input delay for local players (default: 0)
+    --sync-test [<FRAMES>]      Run a local match that rolls back every tick (default: 7)
+    --spectator <ADDR>          Address of a spectator allowed to watch the match (repeatable)
+    --spectate <ADDR>           Address of a match host to spectate
+    --spectate-delay <FRAMES>   Frames buffered before playing back a spectated match (default: 30)
+    --on-disconnect <POLICY>    What to do when a remote player disconnects: no-contest,
+                                cpu or wait:<SECONDS> (default: no-contest)
+    --record <PATH>             Save a replay of the match to the given path on exit
+    --replay <PATH>             Play back a recorded replay
+    --headless                  Run the match without a window, as fast as possible, and
+                                print its result as JSON. Requires --replay or --script
+    --script <PATH>             Drive a headless match with the inputs in a JSON script
+    --max-frames <FRAMES>       Frames to simulate before ending a headless match
+                                (default: 28800)
+    --output <PATH>             Write the result of a headless match to a file
+    --configure-controls        Open the controls menu to rebind the control profiles
+    --help                      Print this message
+
+SUBCOMMANDS:
+    graph <PATH>                Print the state machine of a .chr character as a graph
+                                of its states and transitions. Use --format to pick dot
+                                or mermaid (default: dot)
+";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionMode {
+    /// Every player is on the local machine.
+    Local,
+    /// Every player is on the local machine, claiming a slot by pressing a button on
+    /// their device before the match starts.
+    Join,
+    /// A match against remote peers, each playing in the given slot.
+    Netplay {
+        bind: SocketAddr,
+        remotes: Vec<(usize, SocketAddr)>,
+        local_player: usize,
+    },
+    /// A netplay match that has yet to be set up through a lobby server.
+    Lobby {
+        bind: SocketAddr,
+        request: LobbyRequest,
+    },
+    /// A local match run under a sync test that rolls back the given number of
+    /// frames every tick.
+    SyncTest { check_distance: usize },
+    /// Watches a match hosted by another machine, playing back its inputs the given
+    /// number of frames behind the host.
+    Spectate {
+        bind: SocketAddr,
+        host: SocketAddr,
+        delay: usize,
+    },
+    /// Plays back a replay file.
+    Replay { path: PathBuf },
+    /// A local match driven by the inputs of a script.
+    Script { path: PathBuf },
+}
+
+/// Options for running a match without a window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct HeadlessOptions {
+    pub max_frames: u32,
+    /// Where to write the result of the match. Printed to stdout if not set.
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub mode: SessionMode,
+    pub player_count: usize,
+    pub characters: Vec<u32>,
+    pub palletes: Vec<u8>,
+    /// The names of the control profiles picked by each local player.
+    pub controls: Vec<String>,
+    pub input_delay: usize,
+    pub disconnect_policy: DisconnectPolicy,
+    /// Addresses of the spectators allowed to watch the match.
+    pub spectators: Vec<SocketAddr>,
+    /// Where to save a replay of the match, if one should be recorded.
+    pub record: Option<PathBuf>,
+    /// Set if the match should be run without a window.
+    pub headless: Option<HeadlessOptions>,
+}
+
+/// The result of parsing the command line.
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Run(Options),
+    /// Open the controls menu instead of starting a match.
+    Controls,
+    /// Print the state machine of a character as a graph.
+    Graph {
+        path: PathBuf,
+        format: GraphFormat,
+    },
+    Help,
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let value = value.ok_or_else(|| anyhow!("{} requires a value", flag))?;
+    value
+        .parse()
+        .with_context(|| format!("invalid value for {}: '{}'", flag, value))
+}
+
+/// Parses a lobby server address, which may omit the port.
+fn parse_lobby_addr(flag: &str, value: Option<String>) -> Result<SocketAddr> {
+    let value = value.ok_or_else(|| anyhow!("{} requires a value", flag))?;
+    value
+        .parse()
+        .or_else(|_| {
+            value
+                .parse::<IpAddr>()
+                .map(|ip| SocketAddr::new(ip, DEFAULT_LOBBY_PORT))
+        })
+        .with_context(|| format!("invalid value for {}: '{}'", flag, value))
+}
+
+fn parse_list<T: FromStr>(flag: &str, value: Option<String>) -> Result<Vec<T>>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    let value = value.ok_or_else(|| anyhow!("{} requires a value", flag))?;
+    value
+        .split(',')
+        .map(|item| {
+            item.trim()
+                .parse()
+                .with_context(|| format!("invalid value for {}: '{}'", flag, item))
+        })
+        .collect()
+}
+
+/// Parses the arguments of the graph subcommand.
+fn parse_graph(args: impl Iterator<Item = String>) -> Result<Command> {
+    let mut args = args.peekable();
+    let mut path = None;
+    let mut format = GraphFormat::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(Command::Help),
+            "--format" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow!("{} requires a value", arg))?;
+                format = value
+                    .parse()
+                    .with_context(|| format!("invalid value for {}: '{}'", arg, value))?;
+            }
+            other if other.starts_with('-') => bail!("unrecognized argument: '{}'", other),
+            _ if path.is_some() => bail!("graph takes a single character path"),
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+    let path = path.ok_or_else(|| anyhow!("graph requires the path to a character"))?;
+    Ok(Command::Graph { path, format })
+}
+
+fn offset_addr(addr: SocketAddr, offset: u16, purpose: &str) -> Result<SocketAddr> {
+    match addr.port().checked_add(offset) {
+        Some(port) => Ok(SocketAddr::new(addr.ip(), port)),
+        None => bail!(
+            "port {} is too high: the {} port is {} above it and must be at most {}",
+            addr.port(),
+            purpose,
+            offset,
+            u16::MAX
+        ),
+    }
+}
+
+/// Gets the address used to exchange state checksums for a session address.
+pub fn checksum_addr(addr: SocketAddr) -> Result<SocketAddr> {
+    offset_addr(addr, CHECKSUM_PORT_OFFSET, "checksum")
+}
+
+/// Gets the address used to stream the match to spectators for a session address.
+pub fn spectator_addr(addr: SocketAddr) -> Result<SocketAddr> {
+    offset_addr(addr, SPECTATOR_PORT_OFFSET, "spectator")
+}
+
+/// Parses the command line arguments, excluding the program name.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let command = parse_command(args)?;
+    if let Command::Run(ref options) = command {
+        options.check_ports()?;
+    }
+    Ok(command)
+}
+
+fn parse_command(args: impl IntoIterator<Item = String>) -> Result<Command> {
+    let mut args = args.into_iter().peekable();
+    if args.peek().map(String::as_str) == Some("graph") {
+        args.next();
+        return parse_graph(args);
+    }
+    let mut local = false;
+    let mut player_count = None;
+    let mut port = None;
+    let mut remote = None;
+    let mut local_player = None;
+    let mut characters = Vec::new();
+    let mut palletes = Vec::new();
+    let mut controls = Vec::new();
+    let mut input_delay = 0;
+    let mut sync_test = None;
+    let mut spectators = Vec::new();
+    let mut spectate = None;
+    let mut spectate_delay = None;
+    let mut record = None;
+    let mut disconnect_policy = DisconnectPolicy::default();
+    let mut replay = None;
+    let mut flag_count = 0;
+    let mut lobby = None;
+    let mut room = None;
+    let mut lobby_players = None;
+    let mut headless = false;
+    let mut script = None;
+    let mut max_frames = None;
+    let mut output = None;
+    let mut configure_controls = false;
+    let mut join = false;
+
+    while let Some(arg) = args.next() {
+        flag_count += 1;
+        match arg.as_str() {
+            "--help" | "-h" => return Ok(Command::Help),
+            "--local" => local = true,
+            "--join" => join = true,
+            "--players" => player_count = Some(parse_value::<usize>(&arg, args.next())?),
+            "--host" => port = Some(parse_value::<u16>(&arg, args.next())?),
+            "--connect" => remote = Some(parse_value::<SocketAddr>(&arg, args.next())?),
+            "--lobby" => lobby = Some(parse_lobby_addr(&arg, args.next())?),
+            "--room" => room = Some(parse_value::<String>(&arg, args.next())?),
+            "--lobby-players" => lobby_players = Some(parse_value::<usize>(&arg, args.next())?),
+            "--player" => local_player = Some(parse_value::<usize>(&arg, args.next())?),
+            "--character" => characters = parse_list(&arg, args.next())?,
+            "--palette" => palletes = parse_list(&arg, args.next())?,
+            "--controls" => controls = parse_list(&arg, args.next())?,
+            "--input-delay" => input_delay = parse_value(&arg, args.next())?,
+            "--sync-test" => {
+                let distance = match args.peek() {
+                    Some(next) if !next.starts_with("--") => parse_value(&arg, args.next())?,
+                    _ => DEFAULT_SYNC_TEST_CHECK_DISTANCE,
+                };
+                if distance == 0 {
+                    bail!("--sync-test must roll back at least one frame");
+                }
+                sync_test = Some(distance);
+            }
+            "--spectator" => spectators.push(parse_value::<SocketAddr>(&arg, args.next())?),
+            "--spectate" => spectate = Some(parse_value::<SocketAddr>(&arg, args.next())?),
+            "--spectate-delay" => spectate_delay = Some(parse_value(&arg, args.next())?),
+            "--on-disconnect" => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| anyhow!("{} requires a value", arg))?;
+                disconnect_policy = value
+                    .parse()
+                    .with_context(|| format!("invalid value for {}: '{}'", arg, value))?;
+            }
+            "--record" => record = Some(parse_value::<PathBuf>(&arg, args.next())?),
+            "--replay" => replay = Some(parse_value::<PathBuf>(&arg, args.next())?),
+            "--headless" => headless = true,
+            "--script" => script = Some(parse_value::<PathBuf>(&arg, args.next())?),
+            "--max-frames" => max_frames = Some(parse_value::<u32>(&arg, args.next())?),
+            "--output" => output = Some(parse_value::<PathBuf>(&arg, args.next())?),
+            "--configure-controls" => configure_controls = true,
+            other => bail!("unrecognized argument: '{}'", other),
+        }
+    }
+
+    if configure_controls {
+        if flag_count > 1 {
+            bail!("--configure-controls cannot be combined with other options");
+        }
+        return Ok(Command::Controls);
+    }
+
+    let headless_flags =
+        headless as usize + max_frames.is_some() as usize + output.is_some() as usize;
+    let headless = if headless {
+        if replay.is_none() && script.is_none() {
+            bail!("--headless requires --replay or --script");
+        }
+        if !spectators.is_empty() {
+            bail!("headless matches cannot be spectated");
+        }
+        Some(HeadlessOptions {
+            max_frames: max_frames.unwrap_or(DEFAULT_HEADLESS_MAX_FRAMES),
+            output,
+        })
+    } else {
+        if script.is_some() || max_frames.is_some() || output.is_some() {
+            bail!("--script, --max-frames and --output require --headless");
+        }
+        None
+    };
+
+    if let Some(path) = replay {
+        if flag_count > 1 + headless_flags {
+            bail!("--replay can only be combined with headless options");
+        }
+        return Ok(Command::Run(Options {
+            mode: SessionMode::Replay { path },
+            player_count: 0,
+            characters,
+            palletes,
+            controls,
+            input_delay,
+            disconnect_policy,
+            spectators,
+            record,
+            headless,
+        }));
+    }
+
+    if let Some(host) = spectate {
+        if local
+            || sync_test.is_some()
+            || remote.is_some()
+            || local_player.is_some()
+            || player_count.is_some()
+            || !characters.is_empty()
+            || !palletes.is_empty()
+            || !spectators.is_empty()
+        {
+            bail!("--spectate can only be combined with --host and --spectate-delay");
+        }
+        let bind = SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port.unwrap_or(DEFAULT_PORT),
+        );
+        return Ok(Command::Run(Options {
+            mode: SessionMode::Spectate {
+                bind,
+                host,
+                delay: spectate_delay.unwrap_or(DEFAULT_SPECTATOR_DELAY),
+            },
+            player_count: 0,
+            characters,
+            palletes,
+            controls,
+            input_delay,
+            disconnect_policy,
+            spectators,
+            record,
+            headless,
+        }));
+    }
+    if spectate_delay.is_some() {
+        bail!("--spectate-delay requires --spectate");
+    }
+    if sync_test.is_some() && !spectators.is_empty() {
+        bail!("sync tests cannot be spectated");
+    }
+
+    if let Some(server) = lobby {
+        if local || sync_test.is_some() || remote.is_some() || player_count.is_some() {
+            bail!("--lobby cannot be combined with --local, --sync-test, --connect or --players");
+        }
+        if characters.len() > 1 || palletes.len() > 1 {
+            bail!("only one --character and --palette can be picked in a lobby");
+        }
+        let min_players = lobby_players.unwrap_or(2);
+        if !(2..=ROOM_SLOTS).contains(&min_players) {
+            bail!("--lobby-players must be between 2 and {}", ROOM_SLOTS);
+        }
+        let bind = SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port.unwrap_or(DEFAULT_PORT),
+        );
+        let request = LobbyRequest {
+            server,
+            room,
+            slot: local_player,
+            character_id: characters.first().cloned().unwrap_or(0),
+            pallete: palletes.first().cloned().unwrap_or(0),
+            min_players,
+        };
+        return Ok(Command::Run(Options {
+            mode: SessionMode::Lobby { bind, request },
+            player_count: 0,
+            characters,
+            palletes,
+            controls,
+            input_delay,
+            disconnect_policy,
+            spectators,
+            record,
+            headless,
+        }));
+    }
+    if room.is_some() || lobby_players.is_some() {
+        bail!("--room and --lobby-players require --lobby");
+    }
+
+    let netplay = remote.is_some() || port.is_some() || local_player.is_some();
+    if netplay && (local || sync_test.is_some()) {
+        bail!("netplay options cannot be combined with --local or --sync-test");
+    }
+    if script.is_some() && (netplay || local || sync_test.is_some()) {
+        bail!("--script cannot be combined with --local, --sync-test or netplay options");
+    }
+    if netplay && player_count.is_some() {
+        bail!("--players is only supported in local matches");
+    }
+    if join
+        && (netplay
+            || sync_test.is_some()
+            || script.is_some()
+            || player_count.is_some()
+            || !controls.is_empty())
+    {
+        bail!("--join cannot be combined with --players, --controls, --sync-test, --script or netplay options");
+    }
+
+    let (mode, player_count) = if netplay {
+        let remote = remote.ok_or_else(|| anyhow!("netplay matches require --connect"))?;
+        let local_player = local_player.unwrap_or(0);
+        if local_player > 1 {
+            bail!("--player must be 0 or 1 in a netplay match");
+        }
+        let bind = SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port.unwrap_or(DEFAULT_PORT),
+        );
+        let mode = SessionMode::Netplay {
+            bind,
+            remotes: vec![(1 - local_player, remote)],
+            local_player,
+        };
+        (mode, 2)
+    } else {
+        // Slots are claimed as players join, so any of them may be used.
+        let default_count = if join { MAX_PLAYERS_PER_MATCH } else { 2 };
+        let player_count = player_count.unwrap_or(default_count);
+        if !(2..=MAX_PLAYERS_PER_MATCH).contains(&player_count) {
+            bail!("--players must be between 2 and {}", MAX_PLAYERS_PER_MATCH);
+        }
+        let mode = match (sync_test, script) {
+            (Some(check_distance), _) => SessionMode::SyncTest { check_distance },
+            (None, Some(path)) => SessionMode::Script { path },
+            (None, None) if join => SessionMode::Join,
+            (None, None) => SessionMode::Local,
+        };
+        (mode, player_count)
+    };
+
+    if characters.len() > player_count {
+        bail!("--character has more entries than there are players");
+    }
+    if palletes.len() > player_count {
+        bail!("--palette has more entries than there are players");
+    }
+    if controls.len() > player_count {
+        bail!("--controls has more entries than there are players");
+    }
+
+    Ok(Command::Run(Options {
+        mode,
+        player_count,
+        characters,
+        palletes,
+        controls,
+        input_delay,
+        disconnect_policy,
+        spectators,
+        record,
+        headless,
+    }))
+}
+
+impl Options {
+    /// Checks if the player in a given slot is controlled from this machine.
+    pub fn is_local_player(&self, slot: usize) -> bool {
+        match self.mode {
+            SessionMode::Netplay { local_player, .. } => slot == local_player,
+            // Players are added to the match as they join.
+            SessionMode::Join
+            | SessionMode::Lobby { .. }
+            | SessionMode::Spectate { .. }
+            | SessionMode::Replay { .. }
+            | SessionMode::Script { .. } => false,
+            _ => slot < self.player_count,
+        }
+    }
+
+    pub fn session_config(&self) -> SessionConfig {
+        SessionConfig {
+            input_delay: self.input_delay,
+            disconnect_policy: self.disconnect_policy.clone(),
+        }
+    }
+
+    /// Gets the address the session is bound to, if the match is hosted on this machine.
+    pub fn session_bind(&self) -> Option<SocketAddr> {
+        match self.mode {
+            SessionMode::Local | SessionMode::Join => Some(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                DEFAULT_PORT,
+            )),
+            SessionMode::Netplay { bind, .. } => Some(bind),
+            SessionMode::Lobby { .. } => None,
+            SessionMode::SyncTest { .. }
+            | SessionMode::Spectate { .. }
+            | SessionMode::Replay { .. }
+            | SessionMode::Script { .. } => None,
+        }
+    }
+
+    /// Creates the options for a netplay match set up through a lobby server.
+    pub fn with_lobby_match(&self, start: &MatchStart) -> Result<Options> {
+        let bind = match self.mode {
+            SessionMode::Lobby { bind, .. } => bind,
+            _ => bail!("not in a lobby"),
+        };
+        if !(2..=MAX_PLAYERS_PER_MATCH).contains(&start.players.len()) {
+            bail!("lobby started a match with {} players", start.players.len());
+        }
+        let remotes = start
+            .players
+            .iter()
+            .enumerate()
+            .filter(|(slot, _)| *slot != start.local_player)
+            .map(|(slot, player)| (slot, player.addr))
+            .collect();
+        let options = Options {
+            mode: SessionMode::Netplay {
+                bind,
+                remotes,
+                local_player: start.local_player,
+            },
+            player_count: start.players.len(),
+            characters: start.players.iter().map(|p| p.character_id).collect(),
+            palletes: start.players.iter().map(|p| p.pallete).collect(),
+            ..self.clone()
+        };
+        options.check_ports()?;
+        Ok(options)
+    }
+
+    /// Checks that the ports offset from the session's addresses fit in a port number.
+    pub fn check_ports(&self) -> Result<()> {
+        match self.mode {
+            SessionMode::Netplay {
+                bind, ref remotes, ..
+            } => {
+                checksum_addr(bind)?;
+                for (_, remote) in remotes.iter() {
+                    checksum_addr(*remote)?;
+                }
+            }
+            SessionMode::Spectate { host, .. } => {
+                spectator_addr(host)?;
+            }
+            _ => {}
+        }
+        if let Some(bind) = self.session_bind() {
+            if !self.spectators.is_empty() {
+                spectator_addr(bind)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Builds the configuration for the match. Remote players are marked as local until
+    /// the session's sockets are bound. Spectated matches are left empty until the
+    /// configuration is received from the host, and matches players join are left empty
+    /// until they have joined.
+    pub fn match_config(&self, profiles: &ControlProfiles) -> Result<MatchConfig> {
+        let mut config = MatchConfig {
+            rule: MatchRule::Stock(3),
+            time: None,
+            players: Default::default(),
+            smash_detection: Default::default(),
+        };
+        if self.mode == SessionMode::Join {
+            return Ok(config);
+        }
+        let mut local_players = 0;
+        let mut gamepads = 0;
+        for slot in 0..self.player_count {
+            let input = if self.is_local_player(slot) {
+                let name = self
+                    .controls
+                    .get(local_players)
+                    .map(String::as_str)
+                    .unwrap_or_else(|| ControlProfiles::default_name(local_players));
+                local_players += 1;
+                if let Some(level) = CpuLevel::from_control_name(name) {
+                    let level = level.with_context(|| format!("invalid CPU level: '{}'", name))?;
+                    // Remote peers cannot see the CPU's decisions, only the inputs sampled
+                    // through the session.
+                    if let SessionMode::Netplay { .. } = self.mode {
+                        bail!("CPU players are not supported in netplay matches");
+                    }
+                    InputSource::CPU(level)
+                } else {
+                    let mut input = profiles
+                        .get(name)
+                        .cloned()
+                        .ok_or_else(|| anyhow!("unknown control profile: '{}'", name))?;
+                    // Each local player on a gamepad is handed the next one. Gamepads that
+                    // are not connected are swapped for connected ones when the match starts.
+                    if let InputSource::Gamepad {
+                        ref mut gamepad, ..
+                    } = input
+                    {
+                        *gamepad = Gamepad(gamepads);
+                        gamepads += 1;
+                    }
+                    input
+                }
+            } else {
+                InputSource::None
+            };
+            config.players[slot] = Some(PlayerConfig {
+                player: backroll::Player::Local,
+                character_id: self.characters.get(slot).cloned().unwrap_or(0),
+                pallete: self.palletes.get(slot).cloned().unwrap_or(0),
+                default_damage: 0.0,
+                input,
+            });
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lobby::protocol::MatchPlayer;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn profiles() -> ControlProfiles {
+        ControlProfiles::default()
+    }
+
+    fn options(input: &[&str]) -> Options {
+        match parse(args(input)).unwrap() {
+            Command::Run(options) => options,
+            _ => panic!("Expected options"),
+        }
+    }
+
+    #[test]
+    pub fn test_parse_defaults_to_local_versus() {
+        let options = options(&[]);
+        assert_eq!(options.mode, SessionMode::Local);
+        assert_eq!(options.player_count, 2);
+        assert_eq!(options.input_delay, 0);
+    }
+
+    #[test]
+    pub fn test_parse_netplay() {
+        let options = options(&[
+            "--host",
+            "4002",
+            "--connect",
+            "127.0.0.1:4001",
+            "--player",
+            "1",
+            "--character",
+            "0,2",
+            "--input-delay",
+            "2",
+            "--on-disconnect",
+            "wait:10",
+        ]);
+        assert_eq!(
+            options.mode,
+            SessionMode::Netplay {
+                bind: "0.0.0.0:4002".parse().unwrap(),
+                remotes: vec![(0, "127.0.0.1:4001".parse().unwrap())],
+                local_player: 1,
+            }
+        );
+        assert_eq!(options.characters, vec![0, 2]);
+        assert_eq!(options.input_delay, 2);
+        assert_eq!(
+            options.disconnect_policy,
+            DisconnectPolicy::ReconnectGrace(std::time::Duration::from_secs(10))
+        );
+        assert!(!options.is_local_player(0));
+        assert!(options.is_local_player(1));
+    }
+
+    #[test]
+    pub fn test_parse_sync_test() {
+        assert_eq!(
+            options(&["--sync-test"]).mode,
+            SessionMode::SyncTest {
+                check_distance: DEFAULT_SYNC_TEST_CHECK_DISTANCE
+            }
+        );
+        assert_eq!(
+            options(&["--sync-test", "3", "--players", "4"]).mode,
+            SessionMode::SyncTest { check_distance: 3 }
+        );
+    }
+
+    #[test]
+    pub fn test_parse_spectators() {
+        let host = options(&[
+            "--spectator",
+            "10.0.0.2:4001",
+            "--spectator",
+            "10.0.0.3:4001",
+        ]);
+        assert_eq!(host.mode, SessionMode::Local);
+        assert_eq!(host.spectators.len(), 2);
+
+        let spectator = options(&["--spectate", "10.0.0.1:4001", "--spectate-delay", "10"]);
+        assert_eq!(
+            spectator.mode,
+            SessionMode::Spectate {
+                bind: "0.0.0.0:4001".parse().unwrap(),
+                host: "10.0.0.1:4001".parse().unwrap(),
+                delay: 10,
+            }
+        );
+        assert_eq!(
+            spectator
+                .match_config(&profiles())
+                .unwrap()
+                .active_player_count(),
+            0
+        );
+    }
+
+    #[test]
+    pub fn test_parse_replays() {
+        let recorded = options(&["--players", "3", "--record", "match.fcr"]);
+        assert_eq!(recorded.record, Some(PathBuf::from("match.fcr")));
+        assert_eq!(
+            options(&["--replay", "match.fcr"]).mode,
+            SessionMode::Replay {
+                path: PathBuf::from("match.fcr")
+            }
+        );
+    }
+
+    #[test]
+    pub fn test_parse_headless() {
+        let scripted = options(&[
+            "--headless",
+            "--script",
+            "inputs.json",
+            "--players",
+            "3",
+            "--output",
+            "result.json",
+        ]);
+        assert_eq!(
+            scripted.mode,
+            SessionMode::Script {
+                path: PathBuf::from("inputs.json")
+            }
+        );
+        assert_eq!(
+            scripted.headless,
+            Some(HeadlessOptions {
+                max_frames: DEFAULT_HEADLESS_MAX_FRAMES,
+                output: Some(PathBuf::from("result.json")),
+            })
+        );
+        assert!(!scripted.is_local_player(0));
+        assert_eq!(
+            scripted
+                .match_config(&profiles())
+                .unwrap()
+                .active_player_count(),
+            3
+        );
+
+        let replayed = options(&["--replay", "match.fcr", "--headless", "--max-frames", "600"]);
+        assert_eq!(
+            replayed.mode,
+            SessionMode::Replay {
+                path: PathBuf::from("match.fcr")
+            }
+        );
+        assert_eq!(replayed.headless.unwrap().max_frames, 600);
+        assert_eq!(options(&["--replay", "match.fcr"]).headless, None);
+    }
+
+    #[test]
+    pub fn test_lobby_match() {
+        let lobby = options(&[
+            "--lobby",
+            "10.0.0.1",
+            "--room",
+            "ABC123",
+            "--character",
+            "2",
+        ]);
+        assert_eq!(
+            lobby.mode,
+            SessionMode::Lobby {
+                bind: "0.0.0.0:4001".parse().unwrap(),
+                request: LobbyRequest {
+                    server: "10.0.0.1:4000".parse().unwrap(),
+                    room: Some("ABC123".to_string()),
+                    slot: None,
+                    character_id: 2,
+                    pallete: 0,
+                    min_players: 2,
+                },
+            }
+        );
+
+        let player = |addr: &str, character_id| MatchPlayer {
+            addr: addr.parse().unwrap(),
+            character_id,
+            pallete: 0,
+        };
+        let start = MatchStart {
+            local_player: 1,
+            players: vec![
+                player("10.0.0.2:4001", 1),
+                player("10.0.0.3:4001", 2),
+                player("10.0.0.4:4001", 3),
+            ],
+        };
+        let options = lobby.with_lobby_match(&start).unwrap();
+        assert_eq!(
+            options.mode,
+            SessionMode::Netplay {
+                bind: "0.0.0.0:4001".parse().unwrap(),
+                remotes: vec![
+                    (0, "10.0.0.2:4001".parse().unwrap()),
+                    (2, "10.0.0.4:4001".parse().unwrap()),
+                ],
+                local_player: 1,
+            }
+        );
+        assert_eq!(options.characters, vec![1, 2, 3]);
+        assert!(options.is_local_player(1));
+        assert!(!options.is_local_player(2));
+    }
+
+    #[test]
+    pub fn test_parse_join() {
+        let options = options(&["--join", "--character", "1,2"]);
+        assert_eq!(options.mode, SessionMode::Join);
+        assert_eq!(options.player_count, MAX_PLAYERS_PER_MATCH);
+        assert!(options.match_config(&profiles()).unwrap().players[0].is_none());
+        assert!(parse(args(&["--join", "--players", "3"])).is_err());
+        assert!(parse(args(&["--join", "--connect", "127.0.0.1:4001"])).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_configure_controls() {
+        assert_eq!(
+            parse(args(&["--configure-controls"])).unwrap(),
+            Command::Controls
+        );
+        assert!(parse(args(&["--configure-controls", "--players", "3"])).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_graph() {
+        assert_eq!(
+            parse(args(&["graph", "characters/marisa.chr"])).unwrap(),
+            Command::Graph {
+                path: PathBuf::from("characters/marisa.chr"),
+                format: GraphFormat::Dot,
+            }
+        );
+        assert_eq!(
+            parse(args(&["graph", "--format", "mermaid", "marisa.chr"])).unwrap(),
+            Command::Graph {
+                path: PathBuf::from("marisa.chr"),
+                format: GraphFormat::Mermaid,
+            }
+        );
+        assert!(parse(args(&["graph"])).is_err());
+        assert!(parse(args(&["graph", "a.chr", "b.chr"])).is_err());
+        assert!(parse(args(&["graph", "--format", "svg", "a.chr"])).is_err());
+        assert!(parse(args(&["graph", "a.chr", "--players", "2"])).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_rejects_bad_arguments() {
+        assert!(parse(args(&["--players", "five"])).is_err());
+        assert!(parse(args(&["--players", "5"])).is_err());
+        assert!(parse(args(&["--players", "1"])).is_err());
+        assert!(parse(args(&["--connect"])).is_err());
+        assert!(parse(args(&["--connect", "localhost"])).is_err());
+        assert!(parse(args(&["--host", "4001"])).is_err());
+        assert!(parse(args(&["--connect", "127.0.0.1:4001", "--player", "2"])).is_err());
+        assert!(parse(args(&["--connect", "127.0.0.1:4001", "--local"])).is_err());
+        assert!(parse(args(&["--character", "0,1,2"])).is_err());
+        assert!(parse(args(&["--sync-test", "0"])).is_err());
+        assert!(parse(args(&["--spectate", "127.0.0.1:4001", "--player", "1"])).is_err());
+        assert!(parse(args(&["--spectate-delay", "10"])).is_err());
+        assert!(parse(args(&["--sync-test", "--spectator", "127.0.0.1:4001"])).is_err());
+        assert!(parse(args(&["--replay", "match.fcr", "--players", "3"])).is_err());
+        assert!(parse(args(&["--on-disconnect", "wait"])).is_err());
+        assert!(parse(args(&["--on-disconnect", "wait:soon"])).is_err());
+        assert!(parse(args(&[
+            "--lobby",
+            "10.0.0.1",
+            "--connect",
+            "127.0.0.1:4001"
+        ]))
+        .is_err());
+        assert!(parse(args(&["--lobby", "10.0.0.1", "--lobby-players", "5"])).is_err());
+        assert!(parse(args(&["--room", "ABC123"])).is_err());
+        assert!(parse(args(&["--headless"])).is_err());
+        assert!(parse(args(&["--script", "inputs.json"])).is_err());
+        assert!(parse(args(&["--max-frames", "600"])).is_err());
+        assert!(parse(args(&[
+            "--headless",
+            "--script",
+            "inputs.json",
+            "--sync-test"
+        ]))
+        .is_err());
+        assert!(parse(args(&["--frobnicate"])).is_err());
+    }
+
+    #[test]
+    pub fn test_parse_rejects_ports_without_room_for_offsets() {
+        let netplay = |port: &str, remote: &str| {
+            parse(args(&[
+                "--host",
+                port,
+                "--connect",
+                remote,
+                "--player",
+                "1",
+            ]))
+        };
+        assert!(netplay("64535", "127.0.0.1:4001").is_ok());
+        assert!(netplay("64536", "127.0.0.1:4001").is_err());
+        assert!(netplay("4002", "127.0.0.1:65000").is_err());
+        assert!(parse(args(&["--spectate", "127.0.0.1:63536"])).is_err());
+        assert!(parse(args(&["--spectator", "127.0.0.1:4003"])).is_ok());
+        assert!(parse(args(&[
+            "--host",
+            "63536",
+            "--connect",
+            "127.0.0.1:4001",
+            "--spectator",
+            "127.0.0.1:4003"
+        ]))
+        .is_err());
+        assert_eq!(
+            checksum_addr("127.0.0.1:4001".parse().unwrap()).unwrap(),
+            "127.0.0.1:5001".parse().unwrap()
+        );
+    }
+
+    #[test]
+    pub fn test_match_config_assigns_local_inputs() {
+        let config = options(&["--players", "3", "--palette", "1,2"])
+            .match_config(&profiles())
+            .unwrap();
+        assert_eq!(config.active_player_count(), 3);
+        let players: Vec<&PlayerConfig> = config.players.iter().flatten().collect();
+        assert_eq!(players[0].pallete, 1);
+        assert_eq!(players[1].pallete, 2);
+        assert_eq!(players[2].pallete, 0);
+        assert!(matches!(players[0].input, InputSource::Keyboard { .. }));
+        assert!(matches!(players[2].input, InputSource::Gamepad { .. }));
+    }
+
+    #[test]
+    pub fn test_match_config_uses_picked_profiles() {
+        let config = options(&["--players", "3", "--controls", "gamepad,keyboard-left"])
+            .match_config(&profiles())
+            .unwrap();
+        let players: Vec<&PlayerConfig> = config.players.iter().flatten().collect();
+        assert!(matches!(
+            players[0].input,
+            InputSource::Gamepad {
+                gamepad: Gamepad(0),
+                ..
+            }
+        ));
+        assert!(matches!(players[1].input, InputSource::Keyboard { .. }));
+        assert!(matches!(
+            players[2].input,
+            InputSource::Gamepad {
+                gamepad: Gamepad(1),
+                ..
+            }
+        ));
+
+        assert!(options(&["--controls", "nonexistent"])
+            .match_config(&profiles())
+            .is_err());
+    }
+
+    #[test]
+    pub fn test_match_config_adds_cpu_players() {
+        let config = options(&["--controls", "keyboard-left,cpu:hard"])
+            .match_config(&profiles())
+            .unwrap();
+        assert!(matches!(
+            config.players[1].as_ref().unwrap().input,
+            InputSource::CPU(CpuLevel::Hard)
+        ));
+        assert!(options(&["--controls", "cpu:impossible"])
+            .match_config(&profiles())
+            .is_err());
+        assert!(options(&[
+            "--host",
+            "4002",
+            "--connect",
+            "127.0.0.1:4001",
+            "--player",
+            "1",
+            "--controls",
+            "cpu"
+        ])
+        .match_config(&profiles())
+        .is_err());
+    }
+}
@@ -1,8 +1,10 @@
 mod capsule;
 mod line;
+mod netplay;
 
 use self::capsule::{Capsule, CapsuleGenerator, DebugCapsulesPlugin};
 use self::line::{DebugLines, DebugLinesPlugin};
+use self::netplay::NetplayStatsPlugin;
 
 use crate::{
     character::frame_data::{hitbox::Hitbox, hurtbox::Hurtbox},
@@ -23,38 +25,45 @@ use bevy::{
 const CROSS_SIZE: f32 = 0.25;
 const HITBOX_ALPHA: f32 = 0.25;
 
+struct FpsCounter;
+
 fn start_debug(mut commands: Commands, asset_server: Res<AssetServer>) {
-    commands.spawn_bundle(TextBundle {
-        style: Style {
-            align_self: AlignSelf::FlexEnd,
-            ..Default::default()
-        },
-        text: Text {
-            sections: vec![
-                TextSection {
-                    value: "FPS: ".to_string(),
-                    style: TextStyle {
-                        font: asset_server.load("fonts/FiraSans-Bold.ttf"),
-                        font_size: 15.0,
-                        color: Color::WHITE,
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                align_self: AlignSelf::FlexEnd,
+                ..Default::default()
+            },
+            text: Text {
+                sections: vec![
+                    TextSection {
+                        value: "FPS: ".to_string(),
+                        style: TextStyle {
+                            font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                            font_size: 15.0,
+                            color: Color::WHITE,
+                        },
                     },
-                },
-                TextSection {
-                    value: "".to_string(),
-                    style: TextStyle {
-                        font: asset_server.load("fonts/FiraMono-Medium.ttf"),
-                        font_size: 15.0,
-                        color: Color::GOLD,
+                    TextSection {
+                        value: "".to_string(),
+                        style: TextStyle {
+                            font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+                            font_size: 15.0,
+                            color: Color::GOLD,
+                        },
                     },
-                },
-            ],
+                ],
+                ..Default::default()
+            },
             ..Default::default()
-        },
-        ..Default::default()
-    });
+        })
+        .insert(FpsCounter);
 }
 
-fn update_fps_counter(diagnostics: Res<Diagnostics>, mut texts: Query<&mut Text>) {
+fn update_fps_counter(
+    diagnostics: Res<Diagnostics>,
+    mut texts: Query<&mut Text, With<FpsCounter>>,
+) {
     let fps = diagnostics
         .get(FrameTimeDiagnosticsPlugin::FPS)
         .and_then(|fps| fps.average());
@@ -190,6 +199,7 @@ impl Plugin for FcDebugPlugin {
         app.add_plugin(FrameTimeDiagnosticsPlugin)
             .add_plugin(DebugLinesPlugin)
             .add_plugin(DebugCapsulesPlugin)
+            .add_plugin(NetplayStatsPlugin)
             .add_startup_system(start_debug.system())
             .add_system(update_fps_counter.system())
             .add_system(draw_player_debug.system())
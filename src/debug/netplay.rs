@@ -0,0 +1,210 @@
+use crate::{
+    player::Player,
+    r#match::{
+        backroll::{P2PSession, RollbackStats},
+        MatchConfig,
+    },
+};
+use bevy::prelude::*;
+use bevy_backroll::backroll::{self, PlayerHandle};
+use std::collections::{BTreeMap, VecDeque};
+
+/// How often, in seconds, each metric is sampled.
+const SAMPLE_INTERVAL: f32 = 0.5;
+/// The number of samples kept for each metric's graph.
+const HISTORY_SAMPLES: usize = 40;
+const GRAPH_CHARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// A rolling history of samples of a single metric.
+#[derive(Default)]
+struct Metric {
+    samples: VecDeque<f32>,
+}
+
+impl Metric {
+    fn push(&mut self, sample: f32) {
+        if self.samples.len() >= HISTORY_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    fn latest(&self) -> f32 {
+        self.samples.back().cloned().unwrap_or_default()
+    }
+
+    /// Renders the history as a graph scaled to fit the range of the samples.
+    fn graph(&self) -> String {
+        let min = self.samples.iter().cloned().fold(0.0, f32::min);
+        let max = self.samples.iter().cloned().fold(0.0, f32::max);
+        let range = max - min;
+        self.samples
+            .iter()
+            .map(|sample| {
+                if range <= 0.0 {
+                    return GRAPH_CHARS[0];
+                }
+                let scaled = (sample - min) / range * (GRAPH_CHARS.len() - 1) as f32;
+                GRAPH_CHARS[scaled.round() as usize]
+            })
+            .collect()
+    }
+
+    fn format(&self, name: &str, unit: &str) -> String {
+        format!(
+            "{:<20}{:>8.1}{:<4} {}\n",
+            name,
+            self.latest(),
+            unit,
+            self.graph()
+        )
+    }
+}
+
+#[derive(Default)]
+struct PeerStats {
+    ping: Metric,
+    advantage: Metric,
+}
+
+/// Netplay metrics tracked for the debug overlay.
+struct NetplayStats {
+    timer: Timer,
+    peers: BTreeMap<usize, PeerStats>,
+    rollbacks: Metric,
+    resimulated: Metric,
+    stalls: Metric,
+    last_rollbacks: RollbackStats,
+    pending_stalls: u32,
+}
+
+impl Default for NetplayStats {
+    fn default() -> Self {
+        Self {
+            timer: Timer::from_seconds(SAMPLE_INTERVAL, true),
+            peers: BTreeMap::new(),
+            rollbacks: Default::default(),
+            resimulated: Default::default(),
+            stalls: Default::default(),
+            last_rollbacks: Default::default(),
+            pending_stalls: 0,
+        }
+    }
+}
+
+struct NetplayOverlay;
+
+fn start_netplay_overlay(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(5.0),
+                    left: Val::Px(5.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraMono-Medium.ttf"),
+                    font_size: 15.0,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(NetplayOverlay);
+}
+
+fn count_time_sync_stalls(
+    mut events: EventReader<backroll::Event>,
+    mut stats: ResMut<NetplayStats>,
+) {
+    for event in events.iter() {
+        if let backroll::Event::TimeSync { .. } = event {
+            stats.pending_stalls += 1;
+        }
+    }
+}
+
+fn sample_netplay_stats(
+    time: Res<Time>,
+    session: Option<Res<P2PSession>>,
+    rollbacks: Res<RollbackStats>,
+    config: Res<MatchConfig>,
+    players: Query<(&Player, &PlayerHandle)>,
+    mut stats: ResMut<NetplayStats>,
+) {
+    if !stats.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let rollback_count = rollbacks.rollbacks - stats.last_rollbacks.rollbacks;
+    let resimulated = rollbacks.resimulated_frames - stats.last_rollbacks.resimulated_frames;
+    let stalls = std::mem::take(&mut stats.pending_stalls);
+    stats
+        .rollbacks
+        .push(rollback_count as f32 / SAMPLE_INTERVAL);
+    stats.resimulated.push(resimulated as f32 / SAMPLE_INTERVAL);
+    stats.stalls.push(stalls as f32 / SAMPLE_INTERVAL);
+    stats.last_rollbacks = (*rollbacks).clone();
+
+    let session = match session {
+        Some(session) => session,
+        None => return,
+    };
+    for (player, handle) in players.iter() {
+        let id = player.id as usize;
+        let is_remote = config.players[id]
+            .as_ref()
+            .map(|player| matches!(player.player, backroll::Player::Remote(_)))
+            .unwrap_or(false);
+        if !is_remote {
+            continue;
+        }
+        if let Ok(network) = session.get_network_stats(*handle) {
+            let peer = stats.peers.entry(id).or_default();
+            peer.ping.push(network.ping.as_secs_f32() * 1000.0);
+            peer.advantage.push(network.local_frames_behind as f32);
+        }
+    }
+}
+
+fn update_netplay_overlay(
+    stats: Res<NetplayStats>,
+    mut overlay: Query<&mut Text, With<NetplayOverlay>>,
+) {
+    let mut value = String::new();
+    for (id, peer) in stats.peers.iter() {
+        value.push_str(&format!("Player {}\n", id + 1));
+        value.push_str(&peer.ping.format("  Ping", "ms"));
+        value.push_str(&peer.advantage.format("  Remote advantage", "f"));
+    }
+    value.push_str(&stats.rollbacks.format("Rollbacks", "/s"));
+    value.push_str(&stats.resimulated.format("Re-simulated", "f/s"));
+    value.push_str(&stats.stalls.format("Time sync stalls", "/s"));
+    overlay.for_each_mut(|mut text| {
+        text.sections[0].value = value.clone();
+    });
+}
+
+/// Displays rollback and per-peer network statistics from the backroll session.
+pub struct NetplayStatsPlugin;
+
+impl Plugin for NetplayStatsPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<NetplayStats>()
+            .add_startup_system(start_netplay_overlay.system())
+            .add_system(count_time_sync_stalls.system())
+            .add_system(sample_netplay_stats.system().label("SAMPLE_NETPLAY_STATS"))
+            .add_system(
+                update_netplay_overlay
+                    .system()
+                    .after("SAMPLE_NETPLAY_STATS"),
+            );
+    }
+}
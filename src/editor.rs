@@ -1,7 +1,87 @@
-#![allow(clippy::float_cmp)]
-
 use bevy::prelude::*;
+use fc::character::CharacterAsset;
+use std::path::{Path, PathBuf};
+
+const DEFAULT_CHARACTER_FOLDER: &str = "assets/characters";
+
+/// The folder of characters to validate.
+struct CharacterFolder(PathBuf);
 
 fn main() {
-    App::build().add_plugins(DefaultPlugins).run()
+    let folder = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| DEFAULT_CHARACTER_FOLDER.to_string());
+    App::build()
+        .insert_resource(CharacterFolder(PathBuf::from(folder)))
+        .add_plugins(DefaultPlugins)
+        .add_startup_system(show_validation.system())
+        .run()
+}
+
+/// Validates every character in a folder, returning a report of the problems found.
+fn validate_characters(folder: &Path) -> String {
+    let mut paths: Vec<PathBuf> = match std::fs::read_dir(folder) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().map_or(false, |ext| ext == "chr"))
+            .collect(),
+        Err(err) => return format!("Failed to read {}: {}\n", folder.display(), err),
+    };
+    paths.sort();
+
+    let mut report = String::new();
+    for path in paths {
+        match CharacterAsset::load(&path) {
+            Ok(character) => {
+                let errors = character.validate();
+                report.push_str(&format!(
+                    "{} ({}): {} problem(s)\n",
+                    character.long_name,
+                    path.display(),
+                    errors.len()
+                ));
+                for error in errors {
+                    report.push_str(&format!("    {}\n", error));
+                }
+            }
+            Err(err) => {
+                report.push_str(&format!("{:#}\n", err));
+            }
+        }
+    }
+    report
+}
+
+fn show_validation(
+    folder: Res<CharacterFolder>,
+    asset_server: Res<AssetServer>,
+    mut commands: Commands,
+) {
+    let report = validate_characters(&folder.0);
+    for line in report.lines() {
+        info!("{}", line);
+    }
+    commands.spawn_bundle(UiCameraBundle::default());
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                top: Val::Px(10.0),
+                left: Val::Px(10.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::with_section(
+            report,
+            TextStyle {
+                font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                font_size: 16.0,
+                color: Color::WHITE,
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    });
 }
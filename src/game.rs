@@ -1,51 +1,24 @@
-#![allow(clippy::float_cmp)]
-
-#[macro_use]
-extern crate bitflags;
-
-use crate::{player, r#match::input::*};
 use backroll_transport_udp::*;
 #[windows_subsystem = "windows"]
 use bevy::prelude::*;
 use bevy::tasks::IoTaskPool;
 use bevy_backroll::backroll;
 use bevy_steamworks::{AppId, SteamworksPlugin};
-use std::collections::HashMap;
+#[cfg(debug_assertions)]
+use fc::debug;
+use fc::{
+    assets, character,
+    cli::{self, Command, Options, SessionMode},
+    input::{self, profile::ControlProfiles},
+    lobby,
+    r#match::{self, *},
+    AppState,
+};
 use std::net::SocketAddr;
 use std::ops::Deref;
 
-mod assets;
-mod character;
-#[cfg(debug_assertions)]
-mod debug;
-mod geo;
-mod input;
-mod r#match;
-mod time;
-
-use r#match::*;
-
 const STEAM_APP_ID: AppId = AppId(774701);
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
-pub enum AppState {
-    STARTUP,
-    MATCH,
-}
-
-fn create_input_source(arrow: ButtonAxis2D<KeyCode>, jump: KeyCode) -> InputSource {
-    let mut buttons: HashMap<Buttons, Vec<KeyCode>> = HashMap::new();
-    buttons.insert(Buttons::ATTACK, vec![KeyCode::F]);
-    buttons.insert(Buttons::SPECIAL, vec![KeyCode::D]);
-    buttons.insert(Buttons::JUMP, vec![jump]);
-    buttons.insert(Buttons::SHIELD, vec![KeyCode::Q, KeyCode::W]);
-    InputSource::Keyboard {
-        movement: arrow.clone(),
-        smash: arrow.clone(),
-        buttons: ButtonMapping::<KeyCode>(buttons),
-    }
-}
-
 fn main() {
     // Restart the game if need be through Steam, otherwise set the AppId
     // to ensure proper initialzation.
@@ -61,70 +34,120 @@ fn main() {
         std::env::set_var("SteamGameId", app_id);
     }
 
-    let mut args = std::env::args();
-    let base = args.next().unwrap();
-    if let Some(player_num) = args.next() {
-        start_app(player_num.parse().unwrap());
-    } else {
-        let mut child_1 = std::process::Command::new(base.clone())
-            .args(&["0"])
-            .spawn()
-            .unwrap();
-        let mut child_2 = std::process::Command::new(base)
-            .args(&["1"])
-            .spawn()
-            .unwrap();
-        child_1.wait();
-        child_2.wait();
+    match cli::parse(std::env::args().skip(1)) {
+        Ok(Command::Run(options)) if options.headless.is_some() => {
+            if let Err(err) = start_headless(options) {
+                eprintln!("error: {:#}", err);
+                std::process::exit(1);
+            }
+        }
+        Ok(Command::Run(options)) => match find_lobby_match(options) {
+            Ok(options) => start_app(options),
+            Err(err) => {
+                eprintln!("error: {:#}", err);
+                std::process::exit(1);
+            }
+        },
+        Ok(Command::Controls) => start_controls_menu(),
+        Ok(Command::Graph { path, format }) => match character::CharacterAsset::load(&path) {
+            Ok(character) => print!("{}", format.export(&character.state_machine)),
+            Err(err) => {
+                eprintln!("error: {:#}", err);
+                std::process::exit(1);
+            }
+        },
+        Ok(Command::Help) => print!("{}", cli::USAGE),
+        Err(err) => {
+            eprintln!("error: {:#}\n\n{}", err, cli::USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+/// Blocks until the lobby server has found a match, if the options require one.
+fn find_lobby_match(options: Options) -> anyhow::Result<Options> {
+    match options.mode {
+        SessionMode::Lobby { bind, ref request } => {
+            let start = lobby::join_match(bind, request)?;
+            options.with_lobby_match(&start)
+        }
+        _ => Ok(options),
     }
 }
 
 #[derive(Debug)]
 struct StartupConfig {
-    client: usize,
-    bind: SocketAddr,
-    remote: SocketAddr,
+    mode: SessionMode,
+    session_bind: Option<SocketAddr>,
+    spectators: Vec<SocketAddr>,
 }
 
-fn start_app(player_num: usize) {
-    let bind_addr = if player_num == 0 {
-        "127.0.0.1:4001".parse().unwrap()
-    } else {
-        "127.0.0.1:4002".parse().unwrap()
-    };
-
-    let remote_addr = if player_num == 0 {
-        "127.0.0.1:4002".parse().unwrap()
-    } else {
-        "127.0.0.1:4001".parse().unwrap()
+fn start_app(options: Options) {
+    let loaded = ControlProfiles::load().and_then(|profiles| {
+        let config = options.match_config(&profiles)?;
+        Ok((profiles, config))
+    });
+    let (profiles, config) = match loaded {
+        Ok(loaded) => loaded,
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            std::process::exit(1);
+        }
     };
 
     let mut app = App::build();
     app.insert_resource(StartupConfig {
-        client: player_num,
-        bind: bind_addr,
-        remote: remote_addr,
+        mode: options.mode.clone(),
+        session_bind: options.session_bind(),
+        spectators: options.spectators.clone(),
     })
+    .insert_resource(config)
+    .insert_resource(options.session_config())
     .insert_resource(WindowDescriptor {
         title: "Fantasy Crescendo".to_string(),
         vsync: true,
         ..Default::default()
     })
     .add_state(AppState::STARTUP)
-    .add_plugins_with(DefaultPlugins, |cfg| {
-        if player_num != 0 {
-            cfg.disable::<bevy::log::LogPlugin>();
-        }
-        cfg
-    })
+    .add_plugins(DefaultPlugins)
     .add_plugin(SteamworksPlugin)
     .add_plugin(input::FcInputPlugin)
     .add_plugin(assets::FcAssetsPlugin)
     .add_plugin(r#match::FcMatchPlugin)
+    .add_plugin(r#match::FcMatchViewPlugin)
     .insert_resource(Msaa { samples: 1 })
     .add_startup_system(setup.system())
     .add_system(events.system());
 
+    match options.mode {
+        SessionMode::SyncTest { check_distance } => {
+            app.add_plugin(sync_test::FcSyncTestPlugin { check_distance });
+        }
+        SessionMode::Spectate { delay, .. } => {
+            app.add_plugin(spectator::FcSpectatorPlugin { delay });
+        }
+        SessionMode::Join => {
+            app.add_plugin(input::join::FcJoinPlugin {
+                profiles,
+                characters: options.characters.clone(),
+                palletes: options.palletes.clone(),
+            });
+        }
+        SessionMode::Replay { ref path } => match replay::Replay::load(path) {
+            Ok(replay) => {
+                app.add_plugin(replay::FcReplayPlaybackPlugin::new(replay));
+            }
+            Err(err) => {
+                eprintln!("error: {:#}", err);
+                std::process::exit(1);
+            }
+        },
+        _ => {}
+    }
+    if let Some(path) = options.record {
+        app.add_plugin(replay::FcReplayRecordPlugin { path });
+    }
+
     // Optional Plugins
     #[cfg(debug_assertions)]
     app.add_plugin(debug::FcDebugPlugin);
@@ -132,6 +155,69 @@ fn start_app(player_num: usize) {
     app.run();
 }
 
+/// Opens the controls menu instead of starting a match.
+fn start_controls_menu() {
+    let profiles = match ControlProfiles::load() {
+        Ok(profiles) => profiles,
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            std::process::exit(1);
+        }
+    };
+    App::build()
+        .insert_resource(WindowDescriptor {
+            title: "Fantasy Crescendo - Controls".to_string(),
+            vsync: true,
+            ..Default::default()
+        })
+        .add_plugins(DefaultPlugins)
+        .add_plugin(input::FcInputPlugin)
+        .add_plugin(input::menu::FcControlsMenuPlugin { profiles })
+        .run();
+}
+
+/// Runs a match without a window or renderer, driven by the inputs of a replay or script.
+fn start_headless(options: Options) -> anyhow::Result<()> {
+    let headless = options.headless.clone().unwrap();
+    let (config, inputs) = match options.mode {
+        SessionMode::Replay { ref path } => {
+            let replay = replay::Replay::load(path)?;
+            (replay.header.config, replay.inputs)
+        }
+        SessionMode::Script { ref path } => {
+            let script = headless::InputScript::load(path)?;
+            // Scripted players are driven by the script, so their controls are unused.
+            let config = options.match_config(&ControlProfiles::default())?;
+            (config, script.frames()?)
+        }
+        _ => anyhow::bail!("headless matches must be driven by a replay or script"),
+    };
+
+    let mut app = App::build();
+    app.insert_resource(config)
+        .insert_resource(options.session_config())
+        .add_state(AppState::STARTUP)
+        .add_plugins(MinimalPlugins)
+        .add_plugin(bevy::log::LogPlugin)
+        .add_plugin(bevy::transform::TransformPlugin)
+        .add_plugin(bevy::input::InputPlugin)
+        .add_plugin(bevy::asset::AssetPlugin)
+        .add_plugin(input::FcInputPlugin)
+        .add_plugin(assets::FcAssetsPlugin)
+        .add_plugin(r#match::FcMatchPlugin)
+        .add_plugin(headless::FcHeadlessPlugin {
+            inputs,
+            max_frames: headless.max_frames,
+            output: headless.output,
+        })
+        .add_system(events.system());
+    if let Some(path) = options.record {
+        app.add_plugin(replay::FcReplayRecordPlugin { path });
+    }
+    app.run();
+    Ok(())
+}
+
 fn events(mut events: EventReader<backroll::Event>) {
     for event in events.iter() {
         info!("{:?}", event);
@@ -139,7 +225,12 @@ fn events(mut events: EventReader<backroll::Event>) {
 }
 
 /// set up a simple 3D scene
-fn setup(config: Res<StartupConfig>, pool: Res<IoTaskPool>, mut commands: Commands) {
+fn setup(
+    config: Res<StartupConfig>,
+    pool: Res<IoTaskPool>,
+    mut match_config: ResMut<MatchConfig>,
+    mut commands: Commands,
+) {
     // cameras
     commands.spawn_bundle(UiCameraBundle::default());
     commands.spawn_bundle(PerspectiveCameraBundle {
@@ -151,64 +242,60 @@ fn setup(config: Res<StartupConfig>, pool: Res<IoTaskPool>, mut commands: Comman
         ..Default::default()
     });
 
-    println!("{:?}", config);
-    let socket = UdpManager::bind(pool.deref().deref().clone(), config.bind).unwrap();
-    let peer = socket.connect(UdpConnectionConfig::unbounded(config.remote));
-    let remote = backroll::Player::Remote(peer);
-
-    commands.insert_resource(socket);
-    commands.insert_resource(MatchConfig {
-        rule: r#match::rule::MatchRule::Stock(3),
-        time: None,
-        players: [
-            Some(player::PlayerConfig {
-                player: if config.client == 0 {
-                    backroll::Player::Local
-                } else {
-                    remote.clone()
-                },
-                character_id: 0,
-                pallete: 0,
-                default_damage: 0.0,
-                input: create_input_source(
-                    ButtonAxis2D::<KeyCode> {
-                        horizontal: ButtonAxis1D::<KeyCode> {
-                            pos: KeyCode::D,
-                            neg: KeyCode::A,
-                        },
-                        vertical: ButtonAxis1D::<KeyCode> {
-                            pos: KeyCode::W,
-                            neg: KeyCode::S,
-                        },
-                    },
-                    KeyCode::W,
-                ),
-            }),
-            Some(player::PlayerConfig {
-                player: if config.client == 1 {
-                    backroll::Player::Local
-                } else {
-                    remote
-                },
-                character_id: 0,
-                pallete: 0,
-                default_damage: 0.0,
-                input: create_input_source(
-                    ButtonAxis2D::<KeyCode> {
-                        horizontal: ButtonAxis1D::<KeyCode> {
-                            pos: KeyCode::L,
-                            neg: KeyCode::J,
-                        },
-                        vertical: ButtonAxis1D::<KeyCode> {
-                            pos: KeyCode::I,
-                            neg: KeyCode::K,
-                        },
-                    },
-                    KeyCode::I,
-                ),
-            }),
-            None,
-            None,
-        ],
-    });
+    info!("{:?}", config);
+    if let SessionMode::Netplay {
+        bind,
+        ref remotes,
+        local_player,
+    } = config.mode
+    {
+        let socket = UdpManager::bind(pool.deref().deref().clone(), bind)
+            .unwrap_or_else(|err| panic!("Failed to bind to {}: {}", bind, err));
+        let checksum_socket = UdpManager::bind(
+            pool.deref().deref().clone(),
+            cli::checksum_addr(bind).expect("ports are checked with the options"),
+        )
+        .unwrap();
+
+        let mut checksum_peers = Vec::new();
+        for (slot, remote) in remotes.iter() {
+            let peer = socket.connect(UdpConnectionConfig::unbounded(*remote));
+            checksum_peers.push(checksum_socket.connect(UdpConnectionConfig::unbounded(
+                cli::checksum_addr(*remote).expect("ports are checked with the options"),
+            )));
+            if let Some(player) = match_config.players[*slot].as_mut() {
+                player.player = backroll::Player::Remote(peer);
+            }
+        }
+
+        commands.insert_resource(socket);
+        commands.insert_resource(desync::DesyncDetector::new(
+            format!("player-{}", local_player),
+            checksum_socket,
+            checksum_peers,
+        ));
+    }
+
+    if let SessionMode::Spectate { bind, host, .. } = config.mode {
+        let socket = UdpManager::bind(pool.deref().deref().clone(), bind)
+            .unwrap_or_else(|err| panic!("Failed to bind to {}: {}", bind, err));
+        let peer = socket.connect(UdpConnectionConfig::unbounded(
+            cli::spectator_addr(host).expect("ports are checked with the options"),
+        ));
+        commands.insert_resource(spectator::SpectatorClient::new(socket, peer));
+    }
+
+    if let Some(bind) = config.session_bind {
+        if !config.spectators.is_empty() {
+            let bind = cli::spectator_addr(bind).expect("ports are checked with the options");
+            let socket = UdpManager::bind(pool.deref().deref().clone(), bind)
+                .unwrap_or_else(|err| panic!("Failed to bind to {}: {}", bind, err));
+            let peers = config
+                .spectators
+                .iter()
+                .map(|addr| socket.connect(UdpConnectionConfig::unbounded(*addr)))
+                .collect();
+            commands.insert_resource(spectator::SpectatorHost::new(socket, peers));
+        }
+    }
 }
@@ -1,8 +1,16 @@
+pub mod join;
+pub mod menu;
+pub mod profile;
+
+use crate::{
+    r#match::{input::InputSource, MatchConfig},
+    AppState,
+};
 use bevy::prelude::*;
-use std::collections::HashSet;
 
+/// The connected gamepads, in the order they were connected.
 #[derive(Default)]
-pub struct GamepadLobby(HashSet<Gamepad>);
+pub struct GamepadLobby(Vec<Gamepad>);
 
 fn gamepad_connection_system(
     mut lobby: ResMut<GamepadLobby>,
@@ -11,11 +19,13 @@ fn gamepad_connection_system(
     for event in gamepad_event.iter() {
         match &event {
             GamepadEvent(gamepad, GamepadEventType::Connected) => {
-                lobby.0.insert(*gamepad);
+                if !lobby.0.contains(gamepad) {
+                    lobby.0.push(*gamepad);
+                }
                 info!("{:?} Connected", gamepad);
             }
             GamepadEvent(gamepad, GamepadEventType::Disconnected) => {
-                lobby.0.remove(gamepad);
+                lobby.0.retain(|connected| connected != gamepad);
                 info!("{:?} Disconnected", gamepad);
             }
             _ => (),
@@ -23,12 +33,94 @@ fn gamepad_connection_system(
     }
 }
 
+/// Hands each player on a gamepad that is not connected one of the connected gamepads no
+/// other player is using, in the order they were connected. Returns the number of players
+/// left without a connected gamepad.
+fn assign_gamepads(connected: &[Gamepad], config: &mut MatchConfig) -> usize {
+    let gamepads = config
+        .players
+        .iter_mut()
+        .flatten()
+        .filter_map(|player| match player.input {
+            InputSource::Gamepad {
+                ref mut gamepad, ..
+            } => Some(gamepad),
+            _ => None,
+        });
+    let (assigned, unassigned): (Vec<&mut Gamepad>, Vec<&mut Gamepad>) =
+        gamepads.partition(|gamepad| connected.contains(gamepad));
+    let mut available = connected
+        .iter()
+        .filter(|gamepad| !assigned.iter().any(|assigned| **assigned == **gamepad));
+    let mut missing = 0;
+    for gamepad in unassigned {
+        match available.next() {
+            Some(available) => *gamepad = *available,
+            None => missing += 1,
+        }
+    }
+    missing
+}
+
+fn assign_connected_gamepads(lobby: Res<GamepadLobby>, mut config: ResMut<MatchConfig>) {
+    let missing = assign_gamepads(&lobby.0, &mut *config);
+    if missing > 0 {
+        warn!("{} player(s) do not have a connected gamepad", missing);
+    }
+}
+
 pub struct FcInputPlugin;
 
 impl Plugin for FcInputPlugin {
     fn build(&self, builder: &mut bevy::prelude::AppBuilder) {
         builder
             .init_resource::<GamepadLobby>()
-            .add_system(gamepad_connection_system.system());
+            .add_system(gamepad_connection_system.system())
+            .add_system_set(
+                SystemSet::on_enter(AppState::MATCH)
+                    .with_system(assign_connected_gamepads.system().before("INIT_MATCH")),
+            );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::r#match::player::PlayerConfig;
+    use bevy_backroll::backroll;
+
+    fn gamepad_player(gamepad: Gamepad) -> Option<PlayerConfig> {
+        Some(PlayerConfig {
+            player: backroll::Player::Local,
+            character_id: 0,
+            pallete: 0,
+            default_damage: 0.0,
+            input: InputSource::default_gamepad(gamepad),
+        })
+    }
+
+    fn gamepad_of(config: &MatchConfig, slot: usize) -> Gamepad {
+        match config.players[slot].as_ref().unwrap().input {
+            InputSource::Gamepad { gamepad, .. } => gamepad,
+            _ => panic!("Expected a gamepad player"),
+        }
+    }
+
+    #[test]
+    pub fn test_assign_gamepads_uses_connected_gamepads() {
+        let mut config = MatchConfig::default();
+        config.players[0] = gamepad_player(Gamepad(0));
+        config.players[1] = gamepad_player(Gamepad(1));
+        config.players[3] = gamepad_player(Gamepad(2));
+
+        // Players keep connected gamepads, the rest are handed out in connection order.
+        let connected = [Gamepad(5), Gamepad(1), Gamepad(3)];
+        assert_eq!(assign_gamepads(&connected, &mut config), 0);
+        assert_eq!(gamepad_of(&config, 0), Gamepad(5));
+        assert_eq!(gamepad_of(&config, 1), Gamepad(1));
+        assert_eq!(gamepad_of(&config, 3), Gamepad(3));
+
+        config.players[2] = gamepad_player(Gamepad(7));
+        assert_eq!(assign_gamepads(&connected, &mut config), 1);
     }
 }
@@ -0,0 +1,293 @@
+use super::GamepadLobby;
+use crate::input::profile::{ControlProfiles, GAMEPAD_PROFILE};
+use crate::r#match::{
+    input::InputSource, player::PlayerConfig, MatchConfig, MAX_PLAYERS_PER_MATCH,
+};
+use crate::AppState;
+use bevy::prelude::*;
+use bevy_backroll::backroll;
+
+/// The device a local player joined with.
+#[derive(Clone, Debug, PartialEq)]
+pub enum JoinDevice {
+    /// The name of a keyboard control profile.
+    Keyboard(String),
+    Gamepad(Gamepad),
+}
+
+/// The local players that have claimed a slot in the match, in slot order.
+pub struct JoinLobby {
+    profiles: ControlProfiles,
+    /// The keyboard profiles players can join with.
+    keyboards: Vec<String>,
+    slots: [Option<JoinDevice>; MAX_PLAYERS_PER_MATCH],
+    characters: Vec<u32>,
+    palletes: Vec<u8>,
+    started: bool,
+}
+
+impl JoinLobby {
+    pub fn new(profiles: ControlProfiles, characters: Vec<u32>, palletes: Vec<u8>) -> Self {
+        Self {
+            keyboards: (0..2)
+                .map(|player| ControlProfiles::default_name(player).to_string())
+                .collect(),
+            profiles,
+            slots: Default::default(),
+            characters,
+            palletes,
+            started: false,
+        }
+    }
+
+    /// Claims the next free slot for a device. Returns None if the device has already
+    /// joined or every slot is taken.
+    pub fn join(&mut self, device: JoinDevice) -> Option<usize> {
+        if self.slots.iter().flatten().any(|joined| *joined == device) {
+            return None;
+        }
+        let slot = self.slots.iter().position(Option::is_none)?;
+        self.slots[slot] = Some(device);
+        Some(slot)
+    }
+
+    /// Frees the slot claimed by a device, if it has one.
+    pub fn leave(&mut self, device: &JoinDevice) -> Option<usize> {
+        let slot = self
+            .slots
+            .iter()
+            .position(|joined| joined.as_ref() == Some(device))?;
+        self.slots[slot] = None;
+        Some(slot)
+    }
+
+    pub fn player_count(&self) -> usize {
+        self.slots.iter().flatten().count()
+    }
+
+    fn input_source(&self, device: &JoinDevice) -> InputSource {
+        match device {
+            JoinDevice::Keyboard(name) => self.profiles.get(name).cloned().unwrap_or_default(),
+            JoinDevice::Gamepad(joined) => {
+                let mut input = self
+                    .profiles
+                    .get(GAMEPAD_PROFILE)
+                    .cloned()
+                    .unwrap_or_else(|| InputSource::default_gamepad(*joined));
+                if let InputSource::Gamepad {
+                    ref mut gamepad, ..
+                } = input
+                {
+                    *gamepad = *joined;
+                }
+                input
+            }
+        }
+    }
+
+    /// Fills in the players of the match from the joined slots.
+    pub fn apply(&self, config: &mut MatchConfig) {
+        for (slot, device) in self.slots.iter().enumerate() {
+            config.players[slot] = device.as_ref().map(|device| PlayerConfig {
+                player: backroll::Player::Local,
+                character_id: self.characters.get(slot).cloned().unwrap_or(0),
+                pallete: self.palletes.get(slot).cloned().unwrap_or(0),
+                default_damage: 0.0,
+                input: self.input_source(device),
+            });
+        }
+    }
+}
+
+struct JoinText;
+
+fn spawn_join_text(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Percent(30.0),
+                    left: Val::Percent(35.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 30.0,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(JoinText);
+}
+
+fn despawn_join_text(texts: Query<Entity, With<JoinText>>, mut commands: Commands) {
+    for entity in texts.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn join_players(
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    gamepads: Res<GamepadLobby>,
+    mut lobby: ResMut<JoinLobby>,
+    mut config: ResMut<MatchConfig>,
+) {
+    if lobby.started {
+        return;
+    }
+
+    // Free up the slots of gamepads that were disconnected.
+    let disconnected: Vec<JoinDevice> = lobby
+        .slots
+        .iter()
+        .flatten()
+        .filter(|device| match device {
+            JoinDevice::Gamepad(gamepad) => !gamepads.0.contains(gamepad),
+            JoinDevice::Keyboard(_) => false,
+        })
+        .cloned()
+        .collect();
+    for device in disconnected {
+        if let Some(slot) = lobby.leave(&device) {
+            info!("Player {} left: {:?} disconnected", slot + 1, device);
+        }
+    }
+
+    let mut joined = Vec::new();
+    for name in lobby.keyboards.iter() {
+        if let Some(InputSource::Keyboard { buttons, .. }) = lobby.profiles.get(name) {
+            if !buttons
+                .evaluate_all_with(|key| keys.just_pressed(key))
+                .is_empty()
+            {
+                joined.push(JoinDevice::Keyboard(name.clone()));
+            }
+        }
+    }
+    for button in buttons.get_just_pressed() {
+        // The start button is reserved for starting the match.
+        if gamepads.0.contains(&button.0) && button.1 != GamepadButtonType::Start {
+            joined.push(JoinDevice::Gamepad(button.0));
+        }
+    }
+    for device in joined {
+        if let Some(slot) = lobby.join(device.clone()) {
+            info!("Player {} joined with {:?}", slot + 1, device);
+        }
+    }
+
+    let start = keys.just_pressed(KeyCode::Return)
+        || buttons
+            .get_just_pressed()
+            .any(|button| button.1 == GamepadButtonType::Start);
+    if start && lobby.player_count() >= 2 {
+        // Assets finish loading and the match starts once the config is valid.
+        lobby.apply(&mut config);
+        lobby.started = true;
+    }
+}
+
+fn update_join_text(lobby: Res<JoinLobby>, mut texts: Query<&mut Text, With<JoinText>>) {
+    let mut value = String::from("Press a button to join\n\n");
+    for (slot, device) in lobby.slots.iter().enumerate() {
+        let device = match device {
+            Some(JoinDevice::Keyboard(name)) => name.clone(),
+            Some(JoinDevice::Gamepad(gamepad)) => format!("gamepad {}", gamepad.0 + 1),
+            None => "-".to_string(),
+        };
+        value.push_str(&format!("Player {}: {}\n", slot + 1, device));
+    }
+    if lobby.started {
+        value.push_str("\nLoading...");
+    } else if lobby.player_count() >= 2 {
+        value.push_str("\nPress enter or start to begin");
+    }
+    texts.for_each_mut(|mut text| {
+        text.sections[0].value = value.clone();
+    });
+}
+
+/// Lets local players claim a slot in the match by pressing a button on their keyboard
+/// or gamepad, starting the match once at least two players have joined.
+pub struct FcJoinPlugin {
+    pub profiles: ControlProfiles,
+    /// The characters for each slot, in order.
+    pub characters: Vec<u32>,
+    /// The palletes for each slot, in order.
+    pub palletes: Vec<u8>,
+}
+
+impl Plugin for FcJoinPlugin {
+    fn build(&self, builder: &mut AppBuilder) {
+        builder
+            .insert_resource(JoinLobby::new(
+                self.profiles.clone(),
+                self.characters.clone(),
+                self.palletes.clone(),
+            ))
+            .add_system_set(
+                SystemSet::on_enter(AppState::STARTUP).with_system(spawn_join_text.system()),
+            )
+            .add_system_set(
+                SystemSet::on_update(AppState::STARTUP)
+                    .with_system(join_players.system().label("JOIN_PLAYERS"))
+                    .with_system(update_join_text.system().after("JOIN_PLAYERS")),
+            )
+            .add_system_set(
+                SystemSet::on_exit(AppState::STARTUP).with_system(despawn_join_text.system()),
+            );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lobby() -> JoinLobby {
+        JoinLobby::new(ControlProfiles::default(), vec![3], vec![])
+    }
+
+    #[test]
+    pub fn test_join_claims_next_free_slot() {
+        let mut lobby = lobby();
+        let keyboard = JoinDevice::Keyboard("keyboard-left".to_string());
+        assert_eq!(lobby.join(keyboard.clone()), Some(0));
+        assert_eq!(lobby.join(keyboard.clone()), None);
+        assert_eq!(lobby.join(JoinDevice::Gamepad(Gamepad(1))), Some(1));
+        assert_eq!(lobby.leave(&keyboard), Some(0));
+        assert_eq!(lobby.join(JoinDevice::Gamepad(Gamepad(0))), Some(0));
+        assert_eq!(lobby.player_count(), 2);
+    }
+
+    #[test]
+    pub fn test_join_builds_match_players() {
+        let mut lobby = lobby();
+        lobby.join(JoinDevice::Gamepad(Gamepad(2)));
+        lobby.join(JoinDevice::Keyboard("keyboard-right".to_string()));
+        let mut config = MatchConfig::default();
+        lobby.apply(&mut config);
+        assert!(config.validate().is_ok());
+        let first = config.players[0].as_ref().unwrap();
+        assert_eq!(first.character_id, 3);
+        assert!(matches!(
+            first.input,
+            InputSource::Gamepad {
+                gamepad: Gamepad(2),
+                ..
+            }
+        ));
+        assert!(matches!(
+            config.players[1].as_ref().unwrap().input,
+            InputSource::Keyboard { .. }
+        ));
+        assert!(config.players[2].is_none());
+    }
+}
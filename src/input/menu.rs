@@ -0,0 +1,596 @@
+use crate::input::profile::ControlProfiles;
+use crate::r#match::input::{ButtonAxis2D, Buttons, InputSource, Stick, StickDirection};
+use bevy::{app::AppExit, prelude::*};
+use std::fmt::Debug;
+
+/// How far a gamepad stick must be pushed for its axis to be captured.
+const AXIS_CAPTURE_THRESHOLD: f32 = 0.5;
+
+/// A single control of a profile that can be rebound.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Control {
+    Button(Buttons),
+    /// Gamepad sticks bind an axis rather than each direction, so only `Right` (the
+    /// horizontal axis) and `Up` (the vertical axis) are listed for gamepads.
+    Direction(Stick, StickDirection),
+    /// A keyboard modifier key, by its index in the profile's modifiers.
+    Modifier(usize),
+}
+
+/// An input captured while rebinding a control.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Capture {
+    Key(KeyCode),
+    GamepadButton(GamepadButtonType),
+    GamepadAxis(GamepadAxisType),
+}
+
+impl Control {
+    /// Lists the controls of an input source that can be rebound.
+    fn all(source: &InputSource) -> Vec<Self> {
+        let directions: &[StickDirection] = match source {
+            InputSource::Keyboard { .. } => &[
+                StickDirection::Up,
+                StickDirection::Down,
+                StickDirection::Left,
+                StickDirection::Right,
+            ],
+            InputSource::Gamepad { .. } => &[StickDirection::Right, StickDirection::Up],
+            _ => return vec![],
+        };
+        let modifiers = match source {
+            InputSource::Keyboard { analog, .. } => analog.modifiers.len(),
+            _ => 0,
+        };
+        let sticks = [Stick::Movement, Stick::Smash];
+        let buttons = Buttons::ALL.iter().map(|button| Self::Button(*button));
+        let sticks = sticks.iter().flat_map(|stick| {
+            directions
+                .iter()
+                .map(move |direction| Self::Direction(*stick, *direction))
+        });
+        let modifiers = (0..modifiers).map(Self::Modifier);
+        buttons.chain(sticks).chain(modifiers).collect()
+    }
+
+    fn name(self, source: &InputSource) -> String {
+        match self {
+            Self::Button(button) => button.name().to_string(),
+            Self::Direction(stick, direction) => {
+                let stick = match stick {
+                    Stick::Movement => "movement",
+                    Stick::Smash => "smash",
+                };
+                let direction = match (source, direction) {
+                    (InputSource::Gamepad { .. }, StickDirection::Right) => {
+                        "horizontal".to_string()
+                    }
+                    (InputSource::Gamepad { .. }, _) => "vertical".to_string(),
+                    (_, direction) => format!("{:?}", direction).to_lowercase(),
+                };
+                format!("{} {}", stick, direction)
+            }
+            Self::Modifier(idx) => match source {
+                InputSource::Keyboard { analog, .. } => match analog.modifiers.get(idx) {
+                    Some(modifier) => format!("{}% modifier", (modifier.scale * 100.0).round()),
+                    None => "modifier".to_string(),
+                },
+                _ => "modifier".to_string(),
+            },
+        }
+    }
+
+    /// Describes the inputs currently bound to the control.
+    fn binding(self, source: &InputSource) -> String {
+        match (source, self) {
+            (InputSource::Keyboard { buttons, .. }, Self::Button(button)) => {
+                describe(buttons.0.get(&button))
+            }
+            (InputSource::Gamepad { buttons, .. }, Self::Button(button)) => {
+                describe(buttons.0.get(&button))
+            }
+            (InputSource::Keyboard { movement, .. }, Self::Direction(Stick::Movement, dir)) => {
+                format!("{:?}", key(movement, dir))
+            }
+            (InputSource::Keyboard { smash, .. }, Self::Direction(Stick::Smash, dir)) => {
+                match smash {
+                    Some(smash) => format!("{:?}", key(smash, dir)),
+                    None => "flick movement".to_string(),
+                }
+            }
+            (
+                InputSource::Gamepad {
+                    movement, smash, ..
+                },
+                Self::Direction(stick, dir),
+            ) => {
+                let stick = match stick {
+                    Stick::Movement => movement,
+                    Stick::Smash => smash,
+                };
+                match dir {
+                    StickDirection::Right => format!("{:?}", stick.horizontal),
+                    _ => format!("{:?}", stick.vertical),
+                }
+            }
+            (InputSource::Keyboard { analog, .. }, Self::Modifier(idx)) => analog
+                .modifiers
+                .get(idx)
+                .map(|modifier| format!("{:?}", modifier.key))
+                .unwrap_or_default(),
+            _ => String::new(),
+        }
+    }
+
+    /// Lists the controls of a keyboard source that must stay bound, other than this one,
+    /// that use a key. Unlike buttons, they cannot be unbound from the key to resolve the
+    /// conflict, so they are only reported.
+    fn shared_with(self, source: &InputSource, input: KeyCode) -> Vec<String> {
+        let (movement, smash, analog) = match source {
+            InputSource::Keyboard {
+                movement,
+                smash,
+                analog,
+                ..
+            } => (movement, smash, analog),
+            _ => return vec![],
+        };
+        Self::all(source)
+            .into_iter()
+            .filter(|control| *control != self)
+            .filter(|control| match *control {
+                Self::Button(_) => false,
+                Self::Direction(Stick::Movement, dir) => key(movement, dir) == input,
+                Self::Direction(Stick::Smash, dir) => smash
+                    .as_ref()
+                    .map_or(false, |smash| key(smash, dir) == input),
+                Self::Modifier(idx) => analog.modifiers[idx].key == input,
+            })
+            .map(|control| control.name(source))
+            .collect()
+    }
+
+    /// Rebinds the control to a captured input. Returns a message describing the change,
+    /// or None if the input cannot be bound to the control.
+    fn rebind(self, source: &mut InputSource, capture: Capture) -> Option<String> {
+        let name = self.name(source);
+        let shared = match capture {
+            Capture::Key(input) => self.shared_with(source, input),
+            _ => vec![],
+        };
+        match (source, self, capture) {
+            (InputSource::Keyboard { buttons, .. }, Self::Button(button), Capture::Key(key)) => {
+                Some(bound(&name, key, buttons.bind(button, key), shared))
+            }
+            (
+                InputSource::Gamepad { buttons, .. },
+                Self::Button(button),
+                Capture::GamepadButton(input),
+            ) => Some(bound(&name, input, buttons.bind(button, input), shared)),
+            (
+                InputSource::Keyboard {
+                    movement,
+                    smash,
+                    buttons,
+                    ..
+                },
+                Self::Direction(stick, dir),
+                Capture::Key(input),
+            ) => {
+                let axes = match stick {
+                    Stick::Movement => movement,
+                    // Start from the movement keys when smash inputs were inferred.
+                    Stick::Smash => smash.get_or_insert_with(|| movement.clone()),
+                };
+                *key_mut(axes, dir) = input;
+                Some(bound(&name, input, buttons.unbind(input), shared))
+            }
+            (
+                InputSource::Keyboard {
+                    buttons, analog, ..
+                },
+                Self::Modifier(idx),
+                Capture::Key(input),
+            ) => {
+                analog.modifiers.get_mut(idx)?.key = input;
+                Some(bound(&name, input, buttons.unbind(input), shared))
+            }
+            (
+                InputSource::Gamepad {
+                    movement, smash, ..
+                },
+                Self::Direction(stick, dir),
+                Capture::GamepadAxis(axis),
+            ) => {
+                let stick = match stick {
+                    Stick::Movement => movement,
+                    Stick::Smash => smash,
+                };
+                match dir {
+                    StickDirection::Right => stick.horizontal = axis,
+                    _ => stick.vertical = axis,
+                }
+                Some(bound(&name, axis, vec![], shared))
+            }
+            _ => None,
+        }
+    }
+
+    /// Removes the control's binding, if it can be left unbound. Returns a message
+    /// describing the change.
+    fn clear(self, source: &mut InputSource) -> Option<String> {
+        match (source, self) {
+            (InputSource::Keyboard { smash, .. }, Self::Direction(Stick::Smash, _)) => {
+                *smash = None;
+                Some("Smash inputs are now inferred from movement flicks".to_string())
+            }
+            _ => None,
+        }
+    }
+}
+
+fn describe<T: Debug>(bindings: Option<&Vec<T>>) -> String {
+    match bindings {
+        Some(bindings) if !bindings.is_empty() => bindings
+            .iter()
+            .map(|binding| format!("{:?}", binding))
+            .collect::<Vec<_>>()
+            .join(", "),
+        _ => "unbound".to_string(),
+    }
+}
+
+fn bound(name: &str, input: impl Debug, conflicts: Vec<Buttons>, shared: Vec<String>) -> String {
+    let mut message = format!("Bound {} to {:?}", name, input);
+    if !conflicts.is_empty() {
+        let names: Vec<&str> = conflicts.iter().map(|button| button.name()).collect();
+        message.push_str(&format!(", removing it from {}", names.join(", ")));
+    }
+    if !shared.is_empty() {
+        message.push_str(&format!(", which {} also uses", shared.join(", ")));
+    }
+    message
+}
+
+fn key(axes: &ButtonAxis2D<KeyCode>, direction: StickDirection) -> KeyCode {
+    match direction {
+        StickDirection::Up => axes.vertical.pos,
+        StickDirection::Down => axes.vertical.neg,
+        StickDirection::Left => axes.horizontal.neg,
+        StickDirection::Right | StickDirection::Any => axes.horizontal.pos,
+    }
+}
+
+fn key_mut(axes: &mut ButtonAxis2D<KeyCode>, direction: StickDirection) -> &mut KeyCode {
+    match direction {
+        StickDirection::Up => &mut axes.vertical.pos,
+        StickDirection::Down => &mut axes.vertical.neg,
+        StickDirection::Left => &mut axes.horizontal.neg,
+        StickDirection::Right | StickDirection::Any => &mut axes.horizontal.pos,
+    }
+}
+
+/// A menu action, from either the keyboard or a gamepad.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MenuAction {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Clear,
+    Back,
+}
+
+struct ControlsMenu {
+    profiles: ControlProfiles,
+    /// The index of the profile being edited.
+    profile: usize,
+    /// The selected row: the profile picker, then each control, then the save button.
+    selected: usize,
+    capturing: bool,
+    message: String,
+}
+
+impl ControlsMenu {
+    fn source(&self) -> &InputSource {
+        &self.profiles.iter().nth(self.profile).unwrap().controls
+    }
+
+    fn source_mut(&mut self) -> &mut InputSource {
+        let name = self.profiles.iter().nth(self.profile).unwrap().name.clone();
+        self.profiles.get_mut(&name).unwrap()
+    }
+
+    fn controls(&self) -> Vec<Control> {
+        Control::all(self.source())
+    }
+
+    /// The selected control, if a control row is selected.
+    fn selected_control(&self) -> Option<Control> {
+        self.selected
+            .checked_sub(1)
+            .and_then(|idx| self.controls().get(idx).cloned())
+    }
+
+    fn is_save_selected(&self) -> bool {
+        self.selected == self.controls().len() + 1
+    }
+
+    fn navigate(&mut self, action: MenuAction, exit: &mut EventWriter<AppExit>) {
+        let rows = self.controls().len() + 2;
+        let profiles = self.profiles.iter().count();
+        match action {
+            MenuAction::Up => self.selected = (self.selected + rows - 1) % rows,
+            MenuAction::Down => self.selected = (self.selected + 1) % rows,
+            MenuAction::Left if self.selected == 0 => {
+                self.profile = (self.profile + profiles - 1) % profiles;
+            }
+            MenuAction::Right if self.selected == 0 => {
+                self.profile = (self.profile + 1) % profiles;
+            }
+            MenuAction::Select if self.is_save_selected() => {
+                self.message = match self.profiles.save() {
+                    Ok(()) => "Saved controls".to_string(),
+                    Err(err) => format!("Failed to save controls: {:#}", err),
+                };
+            }
+            MenuAction::Select if self.selected_control().is_some() => {
+                self.capturing = true;
+                self.message = String::new();
+            }
+            MenuAction::Clear => {
+                if let Some(control) = self.selected_control() {
+                    if let Some(message) = control.clear(self.source_mut()) {
+                        self.message = message;
+                    }
+                }
+            }
+            MenuAction::Back => exit.send(AppExit),
+            _ => {}
+        }
+    }
+
+    fn capture(&mut self, capture: Capture) {
+        let control = match self.selected_control() {
+            Some(control) => control,
+            None => return,
+        };
+        if let Some(message) = control.rebind(self.source_mut(), capture) {
+            self.message = message;
+            self.capturing = false;
+        }
+    }
+}
+
+struct ControlsMenuText;
+
+fn spawn_menu(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn_bundle(UiCameraBundle::default());
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Px(20.0),
+                    left: Val::Px(20.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(ControlsMenuText);
+}
+
+fn update_menu(
+    keys: Res<Input<KeyCode>>,
+    buttons: Res<Input<GamepadButton>>,
+    mut gamepad_events: EventReader<GamepadEvent>,
+    mut menu: ResMut<ControlsMenu>,
+    mut exit: EventWriter<AppExit>,
+) {
+    // Always read the axis events so stale ones are not captured later.
+    let axes: Vec<Capture> = gamepad_events
+        .iter()
+        .filter_map(|event| match event {
+            GamepadEvent(_, GamepadEventType::AxisChanged(axis, value))
+                if value.abs() >= AXIS_CAPTURE_THRESHOLD =>
+            {
+                Some(Capture::GamepadAxis(*axis))
+            }
+            _ => None,
+        })
+        .collect();
+    if menu.capturing {
+        if keys.just_pressed(KeyCode::Escape) {
+            menu.capturing = false;
+            return;
+        }
+        let captures: Vec<Capture> = keys
+            .get_just_pressed()
+            .map(|key| Capture::Key(*key))
+            .chain(
+                buttons
+                    .get_just_pressed()
+                    .map(|button| Capture::GamepadButton(button.1)),
+            )
+            .chain(axes)
+            .collect();
+        for capture in captures {
+            if menu.capturing {
+                menu.capture(capture);
+            }
+        }
+        return;
+    }
+
+    let key_actions = [
+        (KeyCode::Up, MenuAction::Up),
+        (KeyCode::Down, MenuAction::Down),
+        (KeyCode::Left, MenuAction::Left),
+        (KeyCode::Right, MenuAction::Right),
+        (KeyCode::Return, MenuAction::Select),
+        (KeyCode::Back, MenuAction::Clear),
+        (KeyCode::Escape, MenuAction::Back),
+    ];
+    let button_actions = [
+        (GamepadButtonType::DPadUp, MenuAction::Up),
+        (GamepadButtonType::DPadDown, MenuAction::Down),
+        (GamepadButtonType::DPadLeft, MenuAction::Left),
+        (GamepadButtonType::DPadRight, MenuAction::Right),
+        (GamepadButtonType::South, MenuAction::Select),
+        (GamepadButtonType::West, MenuAction::Clear),
+        (GamepadButtonType::East, MenuAction::Back),
+    ];
+    let mut actions: Vec<MenuAction> = key_actions
+        .iter()
+        .filter(|(key, _)| keys.just_pressed(*key))
+        .map(|(_, action)| *action)
+        .collect();
+    for button in buttons.get_just_pressed() {
+        if let Some((_, action)) = button_actions.iter().find(|(b, _)| *b == button.1) {
+            actions.push(*action);
+        }
+    }
+    for action in actions {
+        menu.navigate(action, &mut exit);
+        // Stop handling inputs once a control starts being rebound.
+        if menu.capturing {
+            break;
+        }
+    }
+}
+
+fn render_menu(menu: Res<ControlsMenu>, mut texts: Query<&mut Text, With<ControlsMenuText>>) {
+    let cursor = |row: usize| if menu.selected == row { "> " } else { "  " };
+    let profile = menu.profiles.iter().nth(menu.profile).unwrap();
+    let mut value = String::from(
+        "Controls\n\
+         Arrow keys or d-pad to move, enter or south to rebind, backspace or west to\n\
+         clear, escape or east to exit\n\n",
+    );
+    value.push_str(&format!("{}Profile: < {} >\n", cursor(0), profile.name));
+    for (idx, control) in menu.controls().into_iter().enumerate() {
+        let row = idx + 1;
+        let binding = if menu.capturing && menu.selected == row {
+            "press an input... (escape to cancel)".to_string()
+        } else {
+            control.binding(&profile.controls)
+        };
+        value.push_str(&format!(
+            "{}{}: {}\n",
+            cursor(row),
+            control.name(&profile.controls),
+            binding
+        ));
+    }
+    value.push_str(&format!("{}Save\n\n", cursor(menu.controls().len() + 1)));
+    value.push_str(&menu.message);
+    texts.for_each_mut(|mut text| {
+        text.sections[0].value = value.clone();
+    });
+}
+
+/// Shows a menu for rebinding the controls of each control profile.
+pub struct FcControlsMenuPlugin {
+    pub profiles: ControlProfiles,
+}
+
+impl Plugin for FcControlsMenuPlugin {
+    fn build(&self, builder: &mut AppBuilder) {
+        builder
+            .insert_resource(ControlsMenu {
+                profiles: self.profiles.clone(),
+                profile: 0,
+                selected: 0,
+                capturing: false,
+                message: String::new(),
+            })
+            .add_startup_system(spawn_menu.system())
+            .add_system(update_menu.system().label("UPDATE_CONTROLS_MENU"))
+            .add_system(render_menu.system().after("UPDATE_CONTROLS_MENU"));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bevy::input::gamepad::Gamepad;
+
+    #[test]
+    pub fn test_rebind_keyboard_smash_starts_from_movement() {
+        let mut source = ControlProfiles::default()
+            .get(crate::input::profile::KEYBOARD_LEFT_PROFILE)
+            .unwrap()
+            .clone();
+        let control = Control::Direction(Stick::Smash, StickDirection::Up);
+        assert_eq!(control.binding(&source), "flick movement");
+        assert!(control
+            .rebind(&mut source, Capture::Key(KeyCode::T))
+            .is_some());
+        assert_eq!(control.binding(&source), "T");
+        let down = Control::Direction(Stick::Smash, StickDirection::Down);
+        assert_eq!(down.binding(&source), "S");
+        assert!(control.clear(&mut source).is_some());
+        assert_eq!(control.binding(&source), "flick movement");
+    }
+
+    #[test]
+    pub fn test_rebind_checks_directions_and_modifiers_against_buttons() {
+        let mut source = ControlProfiles::default()
+            .get(crate::input::profile::KEYBOARD_LEFT_PROFILE)
+            .unwrap()
+            .clone();
+        let walk = Control::Modifier(0);
+        assert!(Control::all(&source).contains(&walk));
+        assert_eq!(walk.name(&source), "50% modifier");
+        assert_eq!(walk.binding(&source), "LShift");
+
+        // Buttons give up keys taken by directions and modifiers.
+        let down = Control::Direction(Stick::Movement, StickDirection::Down);
+        let message = down.rebind(&mut source, Capture::Key(KeyCode::F)).unwrap();
+        assert_eq!(message, "Bound movement down to F, removing it from attack");
+        let message = walk.rebind(&mut source, Capture::Key(KeyCode::G)).unwrap();
+        assert_eq!(message, "Bound 50% modifier to G, removing it from special");
+        assert_eq!(walk.binding(&source), "G");
+
+        // Directions and modifiers must stay bound, so sharing their keys is only reported.
+        let shield = Control::Button(Buttons::SHIELD);
+        let message = shield
+            .rebind(&mut source, Capture::Key(KeyCode::G))
+            .unwrap();
+        assert_eq!(message, "Bound shield to G, which 50% modifier also uses");
+        let message = shield
+            .rebind(&mut source, Capture::Key(KeyCode::F))
+            .unwrap();
+        assert_eq!(message, "Bound shield to F, which movement down also uses");
+    }
+
+    #[test]
+    pub fn test_rebind_ignores_inputs_from_other_devices() {
+        let mut source = InputSource::default_gamepad(Gamepad(0));
+        let jump = Control::Button(Buttons::JUMP);
+        assert!(jump
+            .rebind(&mut source, Capture::Key(KeyCode::Space))
+            .is_none());
+        let message = jump
+            .rebind(&mut source, Capture::GamepadButton(GamepadButtonType::West))
+            .unwrap();
+        assert_eq!(message, "Bound jump to West, removing it from attack");
+        let horizontal = Control::Direction(Stick::Movement, StickDirection::Right);
+        assert!(horizontal
+            .rebind(&mut source, Capture::GamepadAxis(GamepadAxisType::DPadX),)
+            .is_some());
+        assert_eq!(horizontal.binding(&source), "DPadX");
+    }
+}
@@ -0,0 +1,333 @@
+use crate::r#match::input::*;
+use anyhow::{bail, Context, Result};
+use bevy::input::{gamepad::Gamepad, keyboard::KeyCode};
+use bevy::log::warn;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// The current version of the control profile file format.
+///
+/// When the format changes, bump this and add a step to `migrate` that upgrades files
+/// saved with the previous version, so that existing profiles keep loading.
+pub const CONTROLS_VERSION: u32 = 2;
+
+const CONTROLS_FILE: &str = "controls.json";
+
+pub const KEYBOARD_LEFT_PROFILE: &str = "keyboard-left";
+pub const KEYBOARD_RIGHT_PROFILE: &str = "keyboard-right";
+pub const GAMEPAD_PROFILE: &str = "gamepad";
+
+/// A named set of controls a player can pick for their slot.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ControlProfile {
+    pub name: String,
+    pub controls: InputSource,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ControlsFile {
+    version: u32,
+    profiles: Vec<serde_json::Value>,
+}
+
+/// The control profiles available to local players.
+#[derive(Clone, Debug)]
+pub struct ControlProfiles {
+    profiles: Vec<ControlProfile>,
+}
+
+impl Default for ControlProfiles {
+    /// Creates the built-in profiles.
+    fn default() -> Self {
+        Self {
+            profiles: vec![
+                ControlProfile {
+                    name: KEYBOARD_LEFT_PROFILE.to_string(),
+                    controls: keyboard_controls(
+                        [KeyCode::W, KeyCode::A, KeyCode::S, KeyCode::D],
+                        KeyCode::W,
+                        KeyCode::F,
+                        KeyCode::G,
+                        KeyCode::Q,
+                        KeyCode::LShift,
+                    ),
+                },
+                ControlProfile {
+                    name: KEYBOARD_RIGHT_PROFILE.to_string(),
+                    controls: keyboard_controls(
+                        [KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L],
+                        KeyCode::I,
+                        KeyCode::Semicolon,
+                        KeyCode::Apostrophe,
+                        KeyCode::U,
+                        KeyCode::RShift,
+                    ),
+                },
+                ControlProfile {
+                    name: GAMEPAD_PROFILE.to_string(),
+                    controls: InputSource::default_gamepad(Gamepad(0)),
+                },
+            ],
+        }
+    }
+}
+
+impl ControlProfiles {
+    /// Gets the path of the file profiles are saved to, if the config directory is known.
+    pub fn path() -> Option<PathBuf> {
+        config_dir().map(|dir| dir.join(CONTROLS_FILE))
+    }
+
+    /// Loads the player's saved profiles. Falls back to the built-in profiles if none have
+    /// been saved.
+    pub fn load() -> Result<Self> {
+        match Self::path() {
+            Some(path) if path.exists() => Self::load_from(path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn load_from(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to open control profiles {}", path.display()))?;
+        Self::from_json(&contents)
+            .with_context(|| format!("failed to read control profiles {}", path.display()))
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().context("could not find the config directory")?;
+        self.save_to(path)
+    }
+
+    pub fn save_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_json()?)
+            .with_context(|| format!("failed to save control profiles {}", path.display()))
+    }
+
+    /// Parses a profile file. Built-in profiles that are missing from the file are added
+    /// back, and profiles that fail to parse are skipped.
+    pub fn from_json(json: &str) -> Result<Self> {
+        let mut file: ControlsFile = serde_json::from_str(json)?;
+        if file.version > CONTROLS_VERSION {
+            bail!(
+                "profiles were saved by a newer version of the game (version {})",
+                file.version
+            );
+        }
+        migrate(&mut file)?;
+
+        let mut profiles = Self { profiles: vec![] };
+        for value in file.profiles {
+            match serde_json::from_value::<ControlProfile>(value) {
+                Ok(profile) => profiles.insert(profile),
+                Err(err) => warn!("Skipping invalid control profile: {}", err),
+            }
+        }
+        for profile in Self::default().profiles {
+            if profiles.get(&profile.name).is_none() {
+                profiles.insert(profile);
+            }
+        }
+        Ok(profiles)
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        let file = ControlsFile {
+            version: CONTROLS_VERSION,
+            profiles: self
+                .profiles
+                .iter()
+                .map(serde_json::to_value)
+                .collect::<serde_json::Result<_>>()?,
+        };
+        Ok(serde_json::to_string_pretty(&file)?)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&InputSource> {
+        self.profiles
+            .iter()
+            .find(|profile| profile.name == name)
+            .map(|profile| &profile.controls)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut InputSource> {
+        self.profiles
+            .iter_mut()
+            .find(|profile| profile.name == name)
+            .map(|profile| &mut profile.controls)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ControlProfile> {
+        self.profiles.iter()
+    }
+
+    /// Adds a profile, replacing any existing profile with the same name.
+    pub fn insert(&mut self, profile: ControlProfile) {
+        match self.profiles.iter_mut().find(|p| p.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    /// Gets the profile used by the nth local player when they have not picked one.
+    pub fn default_name(local_player: usize) -> &'static str {
+        match local_player {
+            0 => KEYBOARD_LEFT_PROFILE,
+            1 => KEYBOARD_RIGHT_PROFILE,
+            _ => GAMEPAD_PROFILE,
+        }
+    }
+}
+
+/// Upgrades a profile file saved by an older version of the game to the current format.
+///
+/// Each step upgrades a file by a single version, i.e.
+/// `if file.version == 1 { ...; file.version = 2; }`.
+fn migrate(file: &mut ControlsFile) -> Result<()> {
+    // Version 0 was never released.
+    if file.version == 0 {
+        bail!("unsupported control profile version: {}", file.version);
+    }
+    // Version 2 made the keyboard smash binding optional. Keyboard profiles that bound it
+    // to the movement keys now infer smash inputs from flicks instead.
+    if file.version == 1 {
+        for profile in file.profiles.iter_mut() {
+            let keyboard = profile
+                .pointer_mut("/controls/Keyboard")
+                .and_then(|keyboard| keyboard.as_object_mut());
+            if let Some(keyboard) = keyboard {
+                if keyboard.get("smash") == keyboard.get("movement") {
+                    keyboard.remove("smash");
+                }
+            }
+        }
+        file.version = 2;
+    }
+    Ok(())
+}
+
+fn keyboard_controls(
+    [up, left, down, right]: [KeyCode; 4],
+    jump: KeyCode,
+    attack: KeyCode,
+    special: KeyCode,
+    shield: KeyCode,
+    walk: KeyCode,
+) -> InputSource {
+    let movement = ButtonAxis2D {
+        horizontal: ButtonAxis1D {
+            pos: right,
+            neg: left,
+        },
+        vertical: ButtonAxis1D { pos: up, neg: down },
+    };
+    let mut buttons: HashMap<Buttons, Vec<KeyCode>> = HashMap::new();
+    buttons.insert(Buttons::ATTACK, vec![attack]);
+    buttons.insert(Buttons::SPECIAL, vec![special]);
+    buttons.insert(Buttons::JUMP, vec![jump]);
+    buttons.insert(Buttons::SHIELD, vec![shield]);
+    InputSource::Keyboard {
+        movement,
+        smash: None,
+        buttons: ButtonMapping(buttons),
+        analog: KeyboardAnalog {
+            modifiers: vec![KeyboardModifier {
+                key: walk,
+                scale: 0.5,
+            }],
+            diagonals: DiagonalMode::Square,
+        },
+    }
+}
+
+/// Gets the directory the game's settings are saved to.
+pub fn config_dir() -> Option<PathBuf> {
+    let env = |name| std::env::var_os(name).map(PathBuf::from);
+    let base = if cfg!(windows) {
+        env("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        env("HOME").map(|home| home.join("Library").join("Application Support"))
+    } else {
+        env("XDG_CONFIG_HOME").or_else(|| env("HOME").map(|home| home.join(".config")))
+    };
+    base.map(|base| base.join("fantasy-crescendo"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_profiles_round_trip() {
+        let mut profiles = ControlProfiles::default();
+        profiles.insert(ControlProfile {
+            name: "pad-2".to_string(),
+            controls: InputSource::default_gamepad(Gamepad(1)),
+        });
+        let loaded = ControlProfiles::from_json(&profiles.to_json().unwrap()).unwrap();
+        let names: Vec<&str> = loaded.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                KEYBOARD_LEFT_PROFILE,
+                KEYBOARD_RIGHT_PROFILE,
+                GAMEPAD_PROFILE,
+                "pad-2"
+            ]
+        );
+        assert!(matches!(
+            loaded.get("pad-2"),
+            Some(InputSource::Gamepad {
+                gamepad: Gamepad(1),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    pub fn test_profiles_restore_builtins_and_skip_invalid() {
+        let profiles = ControlProfiles::from_json(
+            r#"{ "version": 1, "profiles": [{ "name": "broken", "controls": 5 }] }"#,
+        )
+        .unwrap();
+        assert!(profiles.get("broken").is_none());
+        assert!(profiles.get(KEYBOARD_LEFT_PROFILE).is_some());
+    }
+
+    #[test]
+    pub fn test_profiles_migrate_keyboard_smash_bindings() {
+        let json = r#"{ "version": 1, "profiles": [{ "name": "arrows", "controls": { "Keyboard": {
+            "movement": {
+                "horizontal": { "pos": "Right", "neg": "Left" },
+                "vertical": { "pos": "Up", "neg": "Down" }
+            },
+            "smash": {
+                "horizontal": { "pos": "Right", "neg": "Left" },
+                "vertical": { "pos": "Up", "neg": "Down" }
+            },
+            "buttons": { "attack": ["Z"] }
+        } } }] }"#;
+        let profiles = ControlProfiles::from_json(json).unwrap();
+        assert!(matches!(
+            profiles.get("arrows"),
+            Some(InputSource::Keyboard { smash: None, .. })
+        ));
+    }
+
+    #[test]
+    pub fn test_profiles_reject_newer_versions() {
+        let json = format!(
+            r#"{{ "version": {}, "profiles": [] }}"#,
+            CONTROLS_VERSION + 1
+        );
+        assert!(ControlProfiles::from_json(&json).is_err());
+    }
+}
@@ -0,0 +1,23 @@
+#![allow(clippy::float_cmp)]
+
+#[macro_use]
+extern crate bitflags;
+
+pub mod assets;
+pub mod character;
+pub mod cli;
+#[cfg(debug_assertions)]
+pub mod debug;
+pub mod geo;
+pub mod input;
+pub mod lobby;
+pub mod r#match;
+pub mod time;
+
+use r#match::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AppState {
+    STARTUP,
+    MATCH,
+}
@@ -0,0 +1,132 @@
+pub mod protocol;
+
+use self::protocol::*;
+use anyhow::{bail, Context, Result};
+use std::{
+    io::BufRead,
+    net::{SocketAddr, UdpSocket},
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant},
+};
+
+/// How often the client resends its state to the lobby server.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The selections a client makes for itself in a lobby room.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LobbyRequest {
+    pub server: SocketAddr,
+    /// The code of the room to join. A new room is created if not set.
+    pub room: Option<String>,
+    pub slot: Option<usize>,
+    pub character_id: u32,
+    pub pallete: u8,
+    /// The number of players that must be in the room before readying up.
+    pub min_players: usize,
+}
+
+/// Toggles whether the player is ready every time Enter is pressed.
+fn read_ready_toggles() -> Receiver<()> {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        for _ in std::io::stdin().lock().lines() {
+            if sender.send(()).is_err() {
+                return;
+            }
+        }
+    });
+    receiver
+}
+
+fn send(socket: &UdpSocket, server: SocketAddr, message: &ClientMessage) -> Result<()> {
+    socket.send_to(&serde_json::to_vec(message)?, server)?;
+    Ok(())
+}
+
+/// Joins a room on a lobby server and blocks until every player in the room is ready.
+/// Players ready up by pressing Enter once the room has enough players.
+///
+/// The socket used to talk to the lobby server is bound to the same address as the
+/// session, so the addresses the server hands out can be used to reach each peer. It is
+/// closed before returning so the session can be bound to it.
+pub fn join_match(bind: SocketAddr, request: &LobbyRequest) -> Result<MatchStart> {
+    let socket = UdpSocket::bind(bind).with_context(|| format!("failed to bind to {}", bind))?;
+    socket.set_read_timeout(Some(UPDATE_INTERVAL))?;
+    println!("Connecting to lobby server at {}", request.server);
+
+    let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+    let mut room: Option<RoomState> = None;
+    let mut last_sent: Option<Instant> = None;
+    let toggles = read_ready_toggles();
+    let mut ready = false;
+    loop {
+        if toggles.try_iter().count() % 2 == 1 {
+            ready = !ready;
+            println!("{}", if ready { "Ready!" } else { "No longer ready." });
+            last_sent = None;
+        }
+        if last_sent.map_or(true, |sent| sent.elapsed() >= UPDATE_INTERVAL) {
+            let message = match room {
+                None => match request.room {
+                    Some(ref room) => ClientMessage::Join { room: room.clone() },
+                    None => ClientMessage::Create,
+                },
+                Some(ref room) => ClientMessage::Update {
+                    slot: request.slot,
+                    character_id: request.character_id,
+                    pallete: request.pallete,
+                    ready: ready && room.slots.iter().flatten().count() >= request.min_players,
+                },
+            };
+            send(&socket, request.server, &message)?;
+            last_sent = Some(Instant::now());
+        }
+
+        let len = match socket.recv_from(&mut buffer) {
+            Ok((len, addr)) if addr == request.server => len,
+            Ok(_) => continue,
+            Err(err)
+                if err.kind() == std::io::ErrorKind::WouldBlock
+                    || err.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                continue
+            }
+            Err(err) => return Err(err.into()),
+        };
+        match serde_json::from_slice(&buffer[..len])? {
+            ServerMessage::Room(state) => {
+                if room.as_ref() != Some(&state) {
+                    print_room(&state);
+                    // Respond to changes in the room immediately.
+                    last_sent = None;
+                }
+                room = Some(state);
+            }
+            ServerMessage::Start(start) => {
+                // Let the server know we're done, in case the start message is resent.
+                let _ = send(&socket, request.server, &ClientMessage::Leave);
+                return Ok(start);
+            }
+            ServerMessage::Error(err) => bail!("lobby server error: {}", err),
+        }
+    }
+}
+
+fn print_room(room: &RoomState) {
+    println!("Room {}:", room.code);
+    for (idx, slot) in room.slots.iter().enumerate() {
+        let local = if idx == room.local_slot { " (you)" } else { "" };
+        match slot {
+            Some(slot) => println!(
+                "  Player {}{}: character {}, palette {}{}",
+                idx + 1,
+                local,
+                slot.character_id,
+                slot.pallete,
+                if slot.ready { ", ready" } else { "" }
+            ),
+            None => println!("  Player {}: open", idx + 1),
+        }
+    }
+    println!("Press Enter to toggle ready.");
+}
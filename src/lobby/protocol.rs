@@ -0,0 +1,76 @@
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+
+/// The default port the lobby server is bound to.
+pub const DEFAULT_LOBBY_PORT: u16 = 4000;
+
+/// The number of player slots in a room. Mirrors `MAX_PLAYERS_PER_MATCH`.
+pub const ROOM_SLOTS: usize = 4;
+
+/// The largest datagram either side will send.
+pub const MAX_MESSAGE_SIZE: usize = 2048;
+
+/// A message sent from a game client to the lobby server as a single JSON datagram.
+///
+/// Clients repeatedly send their desired state instead of individual commands, so dropped
+/// messages are recovered from by the next update.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ClientMessage {
+    /// Creates a new room. Clients that are already in a room are sent its state instead.
+    Create,
+    /// Joins an existing room by its code.
+    Join { room: String },
+    /// Updates the client's selections in its current room.
+    Update {
+        /// The slot the client wants to play in. Ignored if the slot is already claimed.
+        slot: Option<usize>,
+        character_id: u32,
+        pallete: u8,
+        ready: bool,
+    },
+    /// Leaves the current room.
+    Leave,
+}
+
+/// A message sent from the lobby server to a game client as a single JSON datagram.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ServerMessage {
+    /// The current state of the client's room.
+    Room(RoomState),
+    /// Every player in the room is ready and the match should start.
+    Start(MatchStart),
+    /// The last message could not be handled.
+    Error(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RoomState {
+    pub code: String,
+    pub slots: Vec<Option<SlotState>>,
+    /// The slot claimed by the client receiving the message.
+    pub local_slot: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SlotState {
+    pub character_id: u32,
+    pub pallete: u8,
+    pub ready: bool,
+}
+
+/// Everything a client needs to start a session with the other players in its room.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatchStart {
+    /// The index of the receiving client in `players`.
+    pub local_player: usize,
+    /// The players in the match, ordered by player slot.
+    pub players: Vec<MatchPlayer>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MatchPlayer {
+    /// The address the player's session is bound to, as seen by the lobby server.
+    pub addr: SocketAddr,
+    pub character_id: u32,
+    pub pallete: u8,
+}
@@ -0,0 +1,498 @@
+use anyhow::{bail, Context, Result};
+use fc::lobby::protocol::*;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant, SystemTime},
+};
+
+const USAGE: &str = "\
+USAGE:
+    fc-lobby [OPTIONS]
+
+OPTIONS:
+    --port <PORT>    Port to bind the lobby server to (default: 4000)
+    --help           Print this message
+";
+
+/// How long a client can go without sending a message before it is removed from its room.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a started room is kept around to resend the start message to its players.
+const STARTED_ROOM_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Member {
+    addr: SocketAddr,
+    character_id: u32,
+    pallete: u8,
+    ready: bool,
+    last_seen: Instant,
+}
+
+#[derive(Default)]
+struct Room {
+    slots: [Option<Member>; ROOM_SLOTS],
+    /// The players of the match and when it was started, if it has been.
+    started: Option<(Instant, Vec<MatchPlayer>)>,
+}
+
+impl Room {
+    fn slot_of(&self, addr: SocketAddr) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|member| member.as_ref().map(|m| m.addr) == Some(addr))
+    }
+
+    fn members(&self) -> impl Iterator<Item = &Member> {
+        self.slots.iter().flatten()
+    }
+
+    fn state(&self, code: &str, local_slot: usize) -> RoomState {
+        RoomState {
+            code: code.to_string(),
+            slots: self
+                .slots
+                .iter()
+                .map(|member| {
+                    member.as_ref().map(|member| SlotState {
+                        character_id: member.character_id,
+                        pallete: member.pallete,
+                        ready: member.ready,
+                    })
+                })
+                .collect(),
+            local_slot,
+        }
+    }
+
+    /// Starts the match if there are enough players and all of them are ready. Players
+    /// are assigned to consecutive slots in the order of the slots they claimed.
+    fn try_start(&mut self, now: Instant) -> bool {
+        let count = self.members().count();
+        if count < 2 || !self.members().all(|member| member.ready) {
+            return false;
+        }
+        let players = self
+            .members()
+            .map(|member| MatchPlayer {
+                addr: member.addr,
+                character_id: member.character_id,
+                pallete: member.pallete,
+            })
+            .collect();
+        self.started = Some((now, players));
+        true
+    }
+
+    fn start_message(&self, addr: SocketAddr) -> Option<ServerMessage> {
+        let (_, players) = self.started.as_ref()?;
+        let local_player = players.iter().position(|player| player.addr == addr)?;
+        Some(ServerMessage::Start(MatchStart {
+            local_player,
+            players: players.clone(),
+        }))
+    }
+}
+
+/// The rooms of the lobby server and the clients in them.
+///
+/// Kept separate from the socket so that it can be driven directly. Messages for clients
+/// are queued up and sent by the server after each update.
+#[derive(Default)]
+struct Lobby {
+    rooms: HashMap<String, Room>,
+    clients: HashMap<SocketAddr, String>,
+    rooms_created: u64,
+    outbox: Vec<(SocketAddr, ServerMessage)>,
+}
+
+impl Lobby {
+    /// Queues the room's current state to be sent to each of its members.
+    fn broadcast(&mut self, code: &str) {
+        let room = match self.rooms.get(code) {
+            Some(room) => room,
+            None => return,
+        };
+        for (slot, member) in room.slots.iter().enumerate() {
+            if let Some(member) = member {
+                let message = room
+                    .start_message(member.addr)
+                    .unwrap_or_else(|| ServerMessage::Room(room.state(code, slot)));
+                self.outbox.push((member.addr, message));
+            }
+        }
+    }
+
+    fn generate_code(&mut self) -> String {
+        loop {
+            let mut hasher = DefaultHasher::new();
+            self.rooms_created.hash(&mut hasher);
+            SystemTime::now().hash(&mut hasher);
+            self.rooms_created += 1;
+            let code = format!("{:06X}", hasher.finish() & 0xFF_FFFF);
+            if !self.rooms.contains_key(&code) {
+                return code;
+            }
+        }
+    }
+
+    fn join(&mut self, addr: SocketAddr, code: String, now: Instant) -> Result<()> {
+        if let Some(current) = self.clients.get(&addr) {
+            if *current == code {
+                return Ok(());
+            }
+            self.leave(addr);
+        }
+        let room = self
+            .rooms
+            .get_mut(&code)
+            .with_context(|| format!("room {} does not exist", code))?;
+        if room.started.is_some() {
+            bail!("room {} has already started", code);
+        }
+        let slot = room
+            .slots
+            .iter()
+            .position(|slot| slot.is_none())
+            .with_context(|| format!("room {} is full", code))?;
+        room.slots[slot] = Some(Member {
+            addr,
+            character_id: 0,
+            pallete: 0,
+            ready: false,
+            last_seen: now,
+        });
+        println!("{} joined room {} in slot {}", addr, code, slot);
+        self.clients.insert(addr, code);
+        Ok(())
+    }
+
+    fn leave(&mut self, addr: SocketAddr) {
+        let code = match self.clients.remove(&addr) {
+            Some(code) => code,
+            None => return,
+        };
+        if let Some(room) = self.rooms.get_mut(&code) {
+            if let Some(slot) = room.slot_of(addr) {
+                room.slots[slot] = None;
+            }
+            println!("{} left room {}", addr, code);
+            if room.members().next().is_none() {
+                println!("Closing empty room {}", code);
+                self.rooms.remove(&code);
+                return;
+            }
+        }
+        self.broadcast(&code);
+    }
+
+    fn handle(&mut self, addr: SocketAddr, message: ClientMessage, now: Instant) -> Result<()> {
+        if let Some(code) = self.clients.get(&addr) {
+            if let Some(room) = self.rooms.get_mut(code) {
+                if let Some(slot) = room.slot_of(addr) {
+                    room.slots[slot].as_mut().unwrap().last_seen = now;
+                }
+            }
+        }
+
+        match message {
+            ClientMessage::Create => {
+                if !self.clients.contains_key(&addr) {
+                    let code = self.generate_code();
+                    println!("{} created room {}", addr, code);
+                    self.rooms.insert(code.clone(), Room::default());
+                    self.join(addr, code, now)?;
+                }
+            }
+            ClientMessage::Join { room } => self.join(addr, room, now)?,
+            ClientMessage::Update {
+                slot,
+                character_id,
+                pallete,
+                ready,
+            } => {
+                let code = self.clients.get(&addr).context("not in a room")?.clone();
+                let room = self.rooms.get_mut(&code).context("not in a room")?;
+                if room.started.is_none() {
+                    let mut current = room.slot_of(addr).context("not in a room")?;
+                    if let Some(slot) = slot {
+                        if slot < ROOM_SLOTS && room.slots[slot].is_none() {
+                            room.slots[slot] = room.slots[current].take();
+                            current = slot;
+                        }
+                    }
+                    let member = room.slots[current].as_mut().unwrap();
+                    member.character_id = character_id;
+                    member.pallete = pallete;
+                    member.ready = ready;
+                    if room.try_start(now) {
+                        println!("Starting match in room {}", code);
+                    }
+                }
+                self.broadcast(&code);
+                return Ok(());
+            }
+            ClientMessage::Leave => {
+                self.leave(addr);
+                return Ok(());
+            }
+        }
+
+        if let Some(code) = self.clients.get(&addr) {
+            self.broadcast(&code.clone());
+        }
+        Ok(())
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let expired: Vec<SocketAddr> = self
+            .rooms
+            .values()
+            .filter(|room| room.started.is_none())
+            .flat_map(|room| room.members())
+            .filter(|member| now.duration_since(member.last_seen) > CLIENT_TIMEOUT)
+            .map(|member| member.addr)
+            .collect();
+        for addr in expired {
+            println!("{} timed out", addr);
+            self.leave(addr);
+        }
+
+        let started: Vec<String> = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| {
+                room.started
+                    .as_ref()
+                    .map(|(start, _)| now.duration_since(*start) > STARTED_ROOM_TIMEOUT)
+                    .unwrap_or(false)
+            })
+            .map(|(code, _)| code.clone())
+            .collect();
+        for code in started {
+            self.rooms.remove(&code);
+            self.clients.retain(|_, room| *room != code);
+        }
+    }
+}
+
+struct LobbyServer {
+    socket: UdpSocket,
+    lobby: Lobby,
+}
+
+impl LobbyServer {
+    fn send(&self, addr: SocketAddr, message: &ServerMessage) {
+        let result = serde_json::to_vec(message)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(self.socket.send_to(&bytes, addr)?));
+        if let Err(err) = result {
+            eprintln!("Failed to send message to {}: {:#}", addr, err);
+        }
+    }
+
+    fn run(&mut self) -> Result<()> {
+        self.socket.set_read_timeout(Some(Duration::from_secs(1)))?;
+        let mut buffer = [0u8; MAX_MESSAGE_SIZE];
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((len, addr)) => match serde_json::from_slice(&buffer[..len]) {
+                    Ok(message) => {
+                        if let Err(err) = self.lobby.handle(addr, message, Instant::now()) {
+                            self.send(addr, &ServerMessage::Error(format!("{:#}", err)));
+                        }
+                    }
+                    Err(err) => eprintln!("Received malformed message from {}: {}", addr, err),
+                },
+                Err(err)
+                    if err.kind() == std::io::ErrorKind::WouldBlock
+                        || err.kind() == std::io::ErrorKind::TimedOut => {}
+                // Windows reports ICMP port unreachable errors from disconnected clients.
+                Err(err) if err.kind() == std::io::ErrorKind::ConnectionReset => {}
+                Err(err) => return Err(err.into()),
+            }
+            self.lobby.expire(Instant::now());
+            for (addr, message) in std::mem::take(&mut self.lobby.outbox) {
+                self.send(addr, &message);
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let mut port = DEFAULT_LOBBY_PORT;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--help" | "-h" => {
+                print!("{}", USAGE);
+                return;
+            }
+            "--port" => match args.next().map(|port| port.parse()) {
+                Some(Ok(value)) => port = value,
+                _ => {
+                    eprintln!("error: --port requires a valid port\n\n{}", USAGE);
+                    std::process::exit(2);
+                }
+            },
+            other => {
+                eprintln!("error: unrecognized argument: '{}'\n\n{}", other, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let result = UdpSocket::bind(("0.0.0.0", port))
+        .with_context(|| format!("failed to bind to port {}", port))
+        .and_then(|socket| {
+            println!("Lobby server listening on port {}", port);
+            LobbyServer {
+                socket,
+                lobby: Lobby::default(),
+            }
+            .run()
+        });
+    if let Err(err) = result {
+        eprintln!("error: {:#}", err);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn update(ready: bool) -> ClientMessage {
+        ClientMessage::Update {
+            slot: None,
+            character_id: 1,
+            pallete: 2,
+            ready,
+        }
+    }
+
+    /// Gets the last message queued for a client.
+    fn last_message(lobby: &Lobby, client: SocketAddr) -> Option<&ServerMessage> {
+        lobby
+            .outbox
+            .iter()
+            .rev()
+            .find(|(addr, _)| *addr == client)
+            .map(|(_, message)| message)
+    }
+
+    /// Creates a room with a client in it, returning the room's code.
+    fn create_room(lobby: &mut Lobby, client: SocketAddr, now: Instant) -> String {
+        lobby.handle(client, ClientMessage::Create, now).unwrap();
+        match last_message(lobby, client) {
+            Some(ServerMessage::Room(state)) => state.code.clone(),
+            message => panic!("Expected room state, got {:?}", message),
+        }
+    }
+
+    #[test]
+    pub fn test_join_and_leave_rooms() {
+        let mut lobby = Lobby::default();
+        let now = Instant::now();
+        let code = create_room(&mut lobby, addr(1), now);
+
+        let join = |room: &str| ClientMessage::Join {
+            room: room.to_string(),
+        };
+        assert!(lobby.handle(addr(2), join("NOROOM"), now).is_err());
+        for port in 2..=ROOM_SLOTS as u16 {
+            lobby.handle(addr(port), join(&code), now).unwrap();
+        }
+        assert!(lobby.handle(addr(100), join(&code), now).is_err());
+        match last_message(&lobby, addr(2)) {
+            Some(ServerMessage::Room(state)) => {
+                assert_eq!(state.local_slot, 1);
+                assert_eq!(state.slots.iter().flatten().count(), ROOM_SLOTS);
+            }
+            message => panic!("Expected room state, got {:?}", message),
+        }
+
+        // Leaving frees up the slot for the next client.
+        lobby.handle(addr(2), ClientMessage::Leave, now).unwrap();
+        lobby.handle(addr(100), join(&code), now).unwrap();
+        assert_eq!(lobby.rooms[&code].slot_of(addr(100)), Some(1));
+
+        // Rooms are closed once everyone has left.
+        for port in [1, 3, 4, 100].iter() {
+            lobby
+                .handle(addr(*port), ClientMessage::Leave, now)
+                .unwrap();
+        }
+        assert!(lobby.rooms.is_empty());
+        assert!(lobby.clients.is_empty());
+    }
+
+    #[test]
+    pub fn test_start_requires_every_player_ready() {
+        let mut lobby = Lobby::default();
+        let now = Instant::now();
+        let code = create_room(&mut lobby, addr(1), now);
+
+        // A single ready player cannot start a match.
+        lobby.handle(addr(1), update(true), now).unwrap();
+        assert!(lobby.rooms[&code].started.is_none());
+
+        let join = ClientMessage::Join { room: code.clone() };
+        lobby.handle(addr(2), join, now).unwrap();
+        lobby.handle(addr(2), update(false), now).unwrap();
+        assert!(lobby.rooms[&code].started.is_none());
+
+        lobby.handle(addr(2), update(true), now).unwrap();
+        assert!(lobby.rooms[&code].started.is_some());
+        match last_message(&lobby, addr(2)) {
+            Some(ServerMessage::Start(start)) => {
+                assert_eq!(start.local_player, 1);
+                assert_eq!(start.players.len(), 2);
+                assert_eq!(start.players[0].addr, addr(1));
+                assert_eq!(start.players[1].character_id, 1);
+                assert_eq!(start.players[1].pallete, 2);
+            }
+            message => panic!("Expected match start, got {:?}", message),
+        }
+
+        // Nobody else can join once the match has started.
+        let join = ClientMessage::Join { room: code.clone() };
+        assert!(lobby.handle(addr(3), join, now).is_err());
+    }
+
+    #[test]
+    pub fn test_expire_idle_clients_and_started_rooms() {
+        let mut lobby = Lobby::default();
+        let now = Instant::now();
+        let idle = create_room(&mut lobby, addr(1), now);
+        let code = create_room(&mut lobby, addr(2), now);
+        let join = ClientMessage::Join { room: code.clone() };
+        lobby.handle(addr(3), join, now).unwrap();
+
+        // Only clients that stop sending messages time out.
+        let later = now + CLIENT_TIMEOUT;
+        lobby.handle(addr(2), update(false), later).unwrap();
+        lobby.expire(later + Duration::from_secs(1));
+        assert!(!lobby.rooms.contains_key(&idle));
+        assert!(!lobby.clients.contains_key(&addr(3)));
+        assert_eq!(lobby.rooms[&code].members().count(), 1);
+
+        let join = ClientMessage::Join { room: code.clone() };
+        lobby.handle(addr(3), join, later).unwrap();
+        lobby.handle(addr(2), update(true), later).unwrap();
+        lobby.handle(addr(3), update(true), later).unwrap();
+        assert!(lobby.rooms[&code].started.is_some());
+
+        // Started rooms are kept around to resend the start message.
+        lobby.expire(later + CLIENT_TIMEOUT * 2);
+        assert!(lobby.rooms.contains_key(&code));
+        lobby.expire(later + STARTED_ROOM_TIMEOUT * 2);
+        assert!(lobby.rooms.is_empty());
+        assert!(lobby.clients.is_empty());
+    }
+}
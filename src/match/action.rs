@@ -0,0 +1,152 @@
+use super::{
+    events::{self, FrameEvent, RollbackEvent},
+    physics::Body,
+    player::{PlayerId, PlayerMovement},
+};
+use crate::character::{frame_data::action::FrameAction, state::PlayerState};
+use bevy::{math::*, prelude::*};
+
+/// An effect outside of the simulation requested by a player's frame actions.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FrameCue {
+    Sound {
+        player: PlayerId,
+        sound: String,
+    },
+    Vfx {
+        player: PlayerId,
+        effect: String,
+        position: Vec2,
+    },
+}
+
+/// Runs a frame action for a player at the given position. Returns the cue the action
+/// sends to systems outside of the simulation, if any.
+pub(super) fn run_action(
+    action: &FrameAction,
+    player: PlayerId,
+    position: Vec2,
+    body: &mut Body,
+    movement: &mut PlayerMovement,
+    state: &mut PlayerState,
+) -> Option<FrameCue> {
+    let facing_left = body.facing.is_left();
+    let facing = |value: Vec2| {
+        if facing_left {
+            Vec2::new(-value.x, value.y)
+        } else {
+            value
+        }
+    };
+    match action {
+        FrameAction::SetVelocity(velocity) => body.velocity = facing(*velocity),
+        FrameAction::AddVelocity(velocity) => body.velocity += facing(*velocity),
+        FrameAction::ResetJumps => movement.reset_jumps(),
+        FrameAction::SetGravityMultiplier(multiplier) => {
+            body.gravity = movement.gravity * multiplier
+        }
+        FrameAction::FastFall => {
+            if !body.location.is_grounded() {
+                movement.fast_falling = true;
+            }
+        }
+        FrameAction::SetVariable { variable, value } => {
+            if let Some(slot) = state.variables.get_mut(*variable) {
+                *slot = *value;
+            }
+        }
+        FrameAction::PlaySound(sound) => {
+            return Some(FrameCue::Sound {
+                player,
+                sound: sound.clone(),
+            })
+        }
+        FrameAction::PlayVfx { effect, offset } => {
+            return Some(FrameCue::Vfx {
+                player,
+                effect: effect.clone(),
+                position: position + facing(*offset),
+            })
+        }
+    }
+    None
+}
+
+fn log_frame_cues(mut events: EventReader<RollbackEvent<FrameCue>>) {
+    for event in events.iter() {
+        if let RollbackEvent::Confirmed(FrameEvent { frame, event }) = event {
+            info!("Frame cue on frame {}: {:?}", frame, event);
+        }
+    }
+}
+
+pub(super) fn build(builder: &mut AppBuilder) {
+    events::add_rollback_event::<FrameCue>(builder);
+    builder.add_system_to_stage(CoreStage::Last, log_frame_cues.system());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::r#match::physics::Facing;
+
+    #[test]
+    pub fn test_actions_face_the_player() {
+        let mut body = Body {
+            facing: Facing::Left,
+            velocity: Vec2::new(1.0, 0.0),
+            gravity: 1.0,
+            ..Default::default()
+        };
+        let mut movement = PlayerMovement {
+            gravity: 1.0,
+            jump_count: 2,
+            ..Default::default()
+        };
+        let mut state = PlayerState::default();
+        let actions = [
+            FrameAction::AddVelocity(Vec2::new(2.0, 1.0)),
+            FrameAction::ResetJumps,
+            FrameAction::SetGravityMultiplier(0.5),
+            FrameAction::FastFall,
+            FrameAction::SetVariable {
+                variable: 1,
+                value: 7,
+            },
+            FrameAction::SetVariable {
+                variable: 100,
+                value: 7,
+            },
+        ];
+        for action in actions.iter() {
+            let cue = run_action(action, 0, Vec2::ZERO, &mut body, &mut movement, &mut state);
+            assert_eq!(cue, None);
+        }
+        assert_eq!(body.velocity, Vec2::new(-1.0, 1.0));
+        assert_eq!(body.gravity, 0.5);
+        assert_eq!(movement.jump_count, 0);
+        assert!(movement.fast_falling);
+        assert_eq!(state.variables[1], 7);
+
+        let vfx = FrameAction::PlayVfx {
+            effect: "spark".to_string(),
+            offset: Vec2::new(1.0, 2.0),
+        };
+        let cue = run_action(
+            &vfx,
+            3,
+            Vec2::new(5.0, 0.0),
+            &mut body,
+            &mut movement,
+            &mut state,
+        );
+        assert_eq!(
+            cue,
+            Some(FrameCue::Vfx {
+                player: 3,
+                effect: "spark".to_string(),
+                position: Vec2::new(4.0, 2.0),
+            })
+        );
+    }
+}
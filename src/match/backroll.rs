@@ -1,24 +1,331 @@
 use super::{
-    input::{InputSource, PlayerInputFrame},
-    MatchConfig,
+    desync::{StateChecksum, StateHistory},
+    disconnect::DisconnectPolicy,
+    hitbox::HitboxState,
+    input::{FrameInputs, InputDevices, InputSource, PlayerInput, PlayerInputFrame},
+    physics::{Body, Location},
+    player::{Player, PlayerDamage, PlayerMovement},
+    stage::{RespawnPoint, StageIndex},
+    sync_test::{self, SyncTestConfig},
+    MatchConfig, MatchResult, MatchState, MAX_PLAYERS_PER_MATCH,
+};
+use crate::{
+    character::{
+        frame_data::{action::FrameAction, CharacterFrame},
+        state::PlayerState,
+    },
+    time::DELTA_TIME,
 };
-use crate::time::DELTA_TIME;
 use bevy::{core::FixedTimestep, prelude::*};
 use bevy_backroll::backroll::PlayerHandle;
 use bevy_backroll::*;
-use bytemuck::Zeroable;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
 
 pub type P2PSession = bevy_backroll::backroll::P2PSession<BackrollConfig>;
+pub type SyncTestSession = bevy_backroll::backroll::SyncTestSession<BackrollConfig>;
+
+/// Gets the most recent frame the session has received every player's input for. Frames
+/// up to and including it will never be rolled back to.
+pub fn confirmed_frame(session: &P2PSession) -> Option<u32> {
+    // The session reports a negative frame until the first frame is confirmed.
+    u32::try_from(session.confirmed_frame()).ok()
+}
 
 pub struct BackrollConfig;
 
+/// Configuration for the backroll session started at the beginning of a match.
+#[derive(Clone, Debug, Default)]
+pub struct SessionConfig {
+    /// The number of frames local inputs are delayed by before being applied.
+    pub input_delay: usize,
+    /// What to do when a remote peer disconnects during the match.
+    pub disconnect_policy: DisconnectPolicy,
+}
+
+/// Running totals of the rollbacks performed over the lifetime of the game.
+#[derive(Clone, Debug, Default)]
+pub struct RollbackStats {
+    /// The number of times the simulation has been rolled back.
+    pub rollbacks: u64,
+    /// The number of frames that have been re-simulated after rolling back.
+    pub resimulated_frames: u64,
+}
+
 impl backroll::Config for BackrollConfig {
     type Input = PlayerInputFrame;
     type State = GameState;
 }
 
-#[derive(Clone, PartialEq, Hash)]
-pub struct GameState {}
+/// A full snapshot of the simulation state of a match.
+///
+/// Everything that is read or written by the rollback system set must be captured here,
+/// otherwise re-simulating a frame will start from already advanced state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameState {
+    match_state: MatchState,
+    result: MatchResult,
+    players: [Option<PlayerSnapshot>; MAX_PLAYERS_PER_MATCH],
+    /// Hitbox states sorted by player and hitbox ID.
+    hitboxes: Vec<HitboxSnapshot>,
+    /// Respawn point occupancy sorted by stage index.
+    respawn_points: Vec<(StageIndex, Option<Player>)>,
+}
+
+// Entity IDs differ between peers, so only hash what the checksum covers.
+impl Hash for GameState {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.checksum().hash(state);
+    }
+}
+
+impl GameState {
+    /// Gets the frame the state was saved on.
+    pub fn frame(&self) -> u32 {
+        self.match_state.frame
+    }
+
+    /// Gets the inputs that were applied to each player on the frame before the state
+    /// was saved, and which players' peers had disconnected by then.
+    pub fn inputs(&self) -> FrameInputs {
+        let mut inputs = FrameInputs::default();
+        for (input, player) in inputs.inputs.iter_mut().zip(self.players.iter()) {
+            if let Some(player) = player {
+                *input = player.input.current;
+            }
+        }
+        for (disconnected, player) in inputs
+            .disconnected
+            .iter_mut()
+            .zip(self.result.players.iter())
+        {
+            *disconnected = player
+                .as_ref()
+                .map_or(false, |player| player.disconnect.is_some());
+        }
+        inputs
+    }
+
+    /// Computes a checksum of each of the components of the state.
+    ///
+    /// The default hasher uses fixed keys, so the result is deterministic between
+    /// processes running the same build of the game.
+    pub fn checksum(&self) -> StateChecksum {
+        fn checksum(hash: impl FnOnce(&mut DefaultHasher)) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            hash(&mut hasher);
+            hasher.finish()
+        }
+
+        let players = |hash: fn(&PlayerSnapshot, &mut DefaultHasher)| {
+            checksum(|hasher| {
+                for player in self.players.iter() {
+                    player.is_some().hash(hasher);
+                    if let Some(player) = player {
+                        hash(player, hasher);
+                    }
+                }
+            })
+        };
+
+        StateChecksum {
+            frame: self.frame(),
+            components: [
+                checksum(|hasher| hash_match_state(&self.match_state, hasher)),
+                checksum(|hasher| self.result.hash(hasher)),
+                players(|player, hasher| hash_vec3(player.translation, hasher)),
+                players(|player, hasher| hash_body(&player.body, player.location, hasher)),
+                players(|player, hasher| player.input.hash(hasher)),
+                players(|player, hasher| hash_damage(&player.damage, hasher)),
+                players(|player, hasher| hash_movement(&player.movement, hasher)),
+                players(|player, hasher| player.state.hash(hasher)),
+                players(|player, hasher| hash_frame(&player.frame, hasher)),
+                checksum(|hasher| self.hitboxes.hash(hasher)),
+                checksum(|hasher| self.respawn_points.hash(hasher)),
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct PlayerSnapshot {
+    translation: Vec3,
+    body: Body,
+    /// The stage index of the surface or respawn point the body's location refers to.
+    location: Option<StageIndex>,
+    input: PlayerInput,
+    damage: PlayerDamage,
+    movement: PlayerMovement,
+    state: PlayerState,
+    frame: CharacterFrame,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct HitboxSnapshot(HitboxState);
+
+impl HitboxSnapshot {
+    fn key(&self) -> (u8, u8) {
+        (self.0.player, self.0.id)
+    }
+}
+
+// Floating point values do not implement Hash, but the simulation needs to be bit-for-bit
+// deterministic anyway, so hashing the raw bits of each value is exactly what is needed.
+fn hash_f32<H: Hasher>(value: f32, state: &mut H) {
+    value.to_bits().hash(state);
+}
+
+fn hash_vec2<H: Hasher>(value: Vec2, state: &mut H) {
+    hash_f32(value.x, state);
+    hash_f32(value.y, state);
+}
+
+fn hash_vec3<H: Hasher>(value: Vec3, state: &mut H) {
+    hash_f32(value.x, state);
+    hash_f32(value.y, state);
+    hash_f32(value.z, state);
+}
+
+fn hash_match_state<H: Hasher>(match_state: &MatchState, state: &mut H) {
+    match_state.frame.hash(state);
+    match_state.time_remaining.hash(state);
+    for player in match_state.players.iter() {
+        player.is_some().hash(state);
+    }
+}
+
+/// Hashes a body, using the stage index of the surface or respawn point its location
+/// refers to in place of the entity.
+fn hash_body<H: Hasher>(body: &Body, location: Option<StageIndex>, state: &mut H) {
+    hash_f32(body.weight, state);
+    body.facing.hash(state);
+    match &body.location {
+        Location::Airborne(position) => {
+            0_u8.hash(state);
+            hash_vec2(*position, state);
+        }
+        Location::Respawning { remaining_time, .. } => {
+            1_u8.hash(state);
+            location.hash(state);
+            remaining_time.hash(state);
+        }
+        Location::Surface { position, .. } => {
+            2_u8.hash(state);
+            location.hash(state);
+            hash_f32(*position, state);
+        }
+    }
+    hash_vec2(body.velocity, state);
+    hash_f32(body.drag, state);
+    hash_f32(body.gravity, state);
+    hash_vec2(body.ecb.0.center, state);
+    hash_vec2(body.ecb.0.extents, state);
+}
+
+fn hash_damage<H: Hasher>(damage: &PlayerDamage, state: &mut H) {
+    match damage {
+        PlayerDamage::Score {
+            score,
+            damage,
+            default_damage,
+        } => {
+            0_u8.hash(state);
+            score.hash(state);
+            hash_f32(*damage, state);
+            hash_f32(*default_damage, state);
+        }
+        PlayerDamage::Stock {
+            stocks,
+            damage,
+            default_damage,
+        } => {
+            1_u8.hash(state);
+            stocks.hash(state);
+            hash_f32(*damage, state);
+            hash_f32(*default_damage, state);
+        }
+        PlayerDamage::Stamina {
+            health,
+            full_health,
+        } => {
+            2_u8.hash(state);
+            hash_f32(*health, state);
+            hash_f32(*full_health, state);
+        }
+    }
+}
+
+fn hash_movement<H: Hasher>(movement: &PlayerMovement, state: &mut H) {
+    hash_f32(movement.gravity, state);
+    movement.jump_count.hash(state);
+    movement.jump_power.len().hash(state);
+    for power in movement.jump_power.iter() {
+        hash_f32(*power, state);
+    }
+    hash_f32(movement.short_jump_power, state);
+    movement.fast_falling.hash(state);
+    hash_f32(movement.fast_fall_speed, state);
+    hash_f32(movement.max_fall_speed, state);
+}
+
+fn hash_frame<H: Hasher>(frame: &CharacterFrame, state: &mut H) {
+    frame.flags.hash(state);
+    frame.active_hitboxes.hash(state);
+    hash_vec2(frame.movement, state);
+    hash_f32(frame.damage_resistance, state);
+    hash_f32(frame.knockback_resistance, state);
+    frame.actions.len().hash(state);
+    for action in frame.actions.iter() {
+        hash_action(action, state);
+    }
+}
+
+fn hash_action<H: Hasher>(action: &FrameAction, state: &mut H) {
+    match action {
+        FrameAction::SetVelocity(velocity) => {
+            0_u8.hash(state);
+            hash_vec2(*velocity, state);
+        }
+        FrameAction::AddVelocity(velocity) => {
+            1_u8.hash(state);
+            hash_vec2(*velocity, state);
+        }
+        FrameAction::ResetJumps => 2_u8.hash(state),
+        FrameAction::SetGravityMultiplier(multiplier) => {
+            3_u8.hash(state);
+            hash_f32(*multiplier, state);
+        }
+        FrameAction::FastFall => 4_u8.hash(state),
+        FrameAction::SetVariable { variable, value } => {
+            5_u8.hash(state);
+            variable.hash(state);
+            value.hash(state);
+        }
+        FrameAction::PlaySound(sound) => {
+            6_u8.hash(state);
+            sound.hash(state);
+        }
+        FrameAction::PlayVfx { effect, offset } => {
+            7_u8.hash(state);
+            effect.hash(state);
+            hash_vec2(*offset, state);
+        }
+    }
+}
+
+impl Hash for HitboxSnapshot {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.id.hash(state);
+        self.0.player.hash(state);
+        self.0.enabled.hash(state);
+        self.0.previous_position.is_some().hash(state);
+        if let Some(position) = self.0.previous_position {
+            hash_vec3(position, state);
+        }
+    }
+}
 
 const MATCH_UPDATE_LABEL: &str = "MATCH_UPDATE";
 
@@ -27,6 +334,8 @@ pub struct FcBackrollPlugin;
 impl Plugin for FcBackrollPlugin {
     fn build(&self, builder: &mut AppBuilder) {
         builder
+            .init_resource::<SessionConfig>()
+            .init_resource::<RollbackStats>()
             .add_plugin(BackrollPlugin::<BackrollConfig>::default())
             .with_rollback_run_criteria::<BackrollConfig, _>(
                 FixedTimestep::step(DELTA_TIME.into()).with_label(MATCH_UPDATE_LABEL),
@@ -40,28 +349,377 @@ impl Plugin for FcBackrollPlugin {
 fn sample_input(
     handle: In<PlayerHandle>,
     keyboard: Res<Input<KeyCode>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    gamepad_button_axes: Res<Axis<GamepadButton>>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
     config: Res<MatchConfig>,
+    players: Query<(&Player, &PlayerHandle)>,
 ) -> PlayerInputFrame {
-    let player = config.players.get(handle.0 .0).unwrap().as_ref().unwrap();
-    match &player.input {
-        InputSource::None | InputSource::CPU => {
-            panic!("Cannot get local input for player {:?}", handle.0 .0);
+    let devices = InputDevices {
+        keyboard: &keyboard,
+        gamepad_buttons: &gamepad_buttons,
+        gamepad_button_axes: &gamepad_button_axes,
+        gamepad_axes: &gamepad_axes,
+    };
+    // Handles are given out in order to the players that are present, so slots left
+    // empty (i.e. by a player leaving before the match) do not have a handle.
+    let (player, _) = players
+        .iter()
+        .find(|(_, player_handle)| **player_handle == handle.0)
+        .unwrap_or_else(|| panic!("No player was spawned for {:?}", handle.0));
+    let player = config.players[player.id as usize].as_ref().unwrap();
+    match player.input {
+        // CPU inputs are decided as part of the simulation instead.
+        InputSource::CPU(_) => PlayerInputFrame::default(),
+        ref input => input
+            .sample(&devices)
+            .unwrap_or_else(|| panic!("Cannot get local input for player {:?}", handle.0)),
+    }
+}
+
+type PlayerStateQuery = (
+    &'static Transform,
+    &'static Body,
+    &'static PlayerInput,
+    &'static PlayerDamage,
+    &'static PlayerMovement,
+    &'static PlayerState,
+    &'static CharacterFrame,
+);
+
+type PlayerStateQueryMut = (
+    &'static mut Transform,
+    &'static mut Body,
+    &'static mut PlayerInput,
+    &'static mut PlayerDamage,
+    &'static mut PlayerMovement,
+    &'static mut PlayerState,
+    &'static mut CharacterFrame,
+);
+
+pub(super) fn save_world(
+    match_state: Res<MatchState>,
+    result: Res<MatchResult>,
+    players: Query<PlayerStateQuery, With<Player>>,
+    hitboxes: Query<&HitboxState>,
+    respawn_points: Query<(&StageIndex, &RespawnPoint)>,
+    stage: Query<&StageIndex>,
+    sync_test: Option<Res<SyncTestConfig>>,
+    mut history: ResMut<StateHistory>,
+) -> GameState {
+    let mut snapshots: [Option<PlayerSnapshot>; MAX_PLAYERS_PER_MATCH] = Default::default();
+    for (id, entity) in match_state.players.iter().enumerate() {
+        snapshots[id] = entity.and_then(|entity| players.get(entity).ok()).map(
+            |(transform, body, input, damage, movement, state, frame)| PlayerSnapshot {
+                translation: transform.translation,
+                body: body.clone(),
+                location: match body.location {
+                    Location::Airborne(_) => None,
+                    Location::Respawning { point: entity, .. }
+                    | Location::Surface {
+                        surface: entity, ..
+                    } => stage.get(entity).ok().copied(),
+                },
+                input: *input,
+                damage: damage.clone(),
+                movement: movement.clone(),
+                state: state.clone(),
+                frame: frame.clone(),
+            },
+        );
+    }
+
+    // Query iteration order is not guaranteed to be stable, sort everything to keep the
+    // snapshot comparable between frames and peers.
+    let mut hitboxes: Vec<HitboxSnapshot> = hitboxes
+        .iter()
+        .map(|state| HitboxSnapshot(state.clone()))
+        .collect();
+    hitboxes.sort_by_key(HitboxSnapshot::key);
+
+    let mut respawn_points: Vec<(StageIndex, Option<Player>)> = respawn_points
+        .iter()
+        .map(|(index, point)| (*index, point.occupied_by.clone()))
+        .collect();
+    respawn_points.sort_by_key(|(index, _)| *index);
+
+    let state = GameState {
+        match_state: match_state.clone(),
+        result: result.clone(),
+        players: snapshots,
+        hitboxes,
+        respawn_points,
+    };
+    // Sync tests re-simulate frames with the same inputs, so every state saved again
+    // must match the one originally saved.
+    if sync_test.is_some() {
+        if let Some(expected) = history.get(state.frame()) {
+            sync_test::check(expected, &state);
         }
-        InputSource::Keyboard {
-            movement,
-            smash,
-            buttons,
-        } => PlayerInputFrame {
-            movement: movement.sample(&keyboard),
-            smash: smash.sample(&keyboard),
-            buttons: buttons.evaluate_all(&keyboard),
-        },
-        InputSource::Gamepad { .. } => PlayerInputFrame::zeroed(),
     }
+    history.record(state.clone());
+    state
 }
 
-fn save_world() -> GameState {
-    GameState {}
+pub(super) fn load_world(
+    state: In<GameState>,
+    mut match_state: ResMut<MatchState>,
+    mut result: ResMut<MatchResult>,
+    mut players: Query<PlayerStateQueryMut, With<Player>>,
+    mut hitboxes: Query<&mut HitboxState>,
+    mut respawn_points: Query<(&StageIndex, &mut RespawnPoint)>,
+    mut stats: ResMut<RollbackStats>,
+) {
+    stats.rollbacks += 1;
+    stats.resimulated_frames += match_state.frame.saturating_sub(state.0.frame()) as u64;
+
+    let GameState {
+        match_state: saved_match_state,
+        result: saved_result,
+        players: saved_players,
+        hitboxes: saved_hitboxes,
+        respawn_points: saved_respawn_points,
+    } = state.0;
+
+    for (id, snapshot) in saved_players.iter().enumerate() {
+        let player = match_state.players[id].and_then(|entity| players.get_mut(entity).ok());
+        match (player, snapshot) {
+            (Some(player), Some(snapshot)) => {
+                let (
+                    mut transform,
+                    mut body,
+                    mut input,
+                    mut damage,
+                    mut movement,
+                    mut state,
+                    mut frame,
+                ) = player;
+                transform.translation = snapshot.translation;
+                *body = snapshot.body.clone();
+                *input = snapshot.input;
+                *damage = snapshot.damage.clone();
+                *movement = snapshot.movement.clone();
+                *state = snapshot.state.clone();
+                *frame = snapshot.frame.clone();
+            }
+            (None, None) => {}
+            _ => warn!("Loaded game state does not match spawned player {}", id),
+        }
+    }
+
+    hitboxes.for_each_mut(|mut hitbox| {
+        let key = (hitbox.player, hitbox.id);
+        match saved_hitboxes.binary_search_by_key(&key, HitboxSnapshot::key) {
+            Ok(idx) => *hitbox = saved_hitboxes[idx].0.clone(),
+            Err(_) => warn!("Loaded game state is missing hitbox {:?}", key),
+        }
+    });
+
+    respawn_points.for_each_mut(|(index, mut point)| {
+        match saved_respawn_points.binary_search_by_key(index, |(index, _)| *index) {
+            Ok(idx) => point.occupied_by = saved_respawn_points[idx].1.clone(),
+            Err(_) => warn!("Loaded game state is missing respawn point {:?}", index),
+        }
+    });
+
+    *match_state = saved_match_state;
+    *result = saved_result;
 }
 
-fn load_world(state: In<GameState>) {}
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::input::{
+        join::{JoinDevice, JoinLobby},
+        profile::ControlProfiles,
+    };
+    use crate::r#match::{input::Buttons, physics::Facing, rule::MatchWinner};
+    use bevy::ecs::system::System;
+
+    fn stock_damage() -> PlayerDamage {
+        PlayerDamage::Stock {
+            stocks: 3,
+            damage: 0.0,
+            default_damage: 0.0,
+        }
+    }
+
+    #[test]
+    pub fn test_sample_input_after_a_player_leaves() {
+        let mut lobby = JoinLobby::new(ControlProfiles::default(), Vec::new(), Vec::new());
+        let keyboard = JoinDevice::Keyboard("keyboard-left".to_string());
+        lobby.join(keyboard.clone());
+        lobby.join(JoinDevice::Gamepad(Gamepad(1)));
+        lobby.join(JoinDevice::Gamepad(Gamepad(2)));
+        lobby.leave(&keyboard);
+        let mut config = MatchConfig::default();
+        lobby.apply(&mut config);
+        assert!(config.validate().is_ok());
+
+        let mut world = World::default();
+        world.insert_resource(Input::<KeyCode>::default());
+        let mut buttons = Input::<GamepadButton>::default();
+        buttons.press(GamepadButton(Gamepad(2), GamepadButtonType::South));
+        world.insert_resource(buttons);
+        world.insert_resource(Axis::<GamepadButton>::default());
+        world.insert_resource(Axis::<GamepadAxis>::default());
+        // Spawn the players the way the match does, handing out handles in order.
+        let present = config
+            .players
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.is_some());
+        for (handle, (id, _)) in present.enumerate() {
+            world
+                .spawn()
+                .insert(Player { id: id as u8 })
+                .insert(PlayerHandle(handle));
+        }
+        world.insert_resource(config);
+
+        let mut sample = sample_input.system();
+        sample.initialize(&mut world);
+        assert_eq!(
+            sample.run(PlayerHandle(0), &mut world).buttons,
+            Buttons::empty()
+        );
+        assert_ne!(
+            sample.run(PlayerHandle(1), &mut world).buttons,
+            Buttons::empty()
+        );
+    }
+
+    #[test]
+    pub fn test_load_world_restores_saved_state() {
+        let mut world = World::default();
+        world.insert_resource(StateHistory::default());
+        world.insert_resource(RollbackStats::default());
+        world.insert_resource(MatchResult::default());
+        let player = world
+            .spawn()
+            .insert(Player { id: 0 })
+            .insert(Transform::default())
+            .insert(Body::default())
+            .insert(PlayerInput::default())
+            .insert(stock_damage())
+            .insert(PlayerMovement::default())
+            .insert(PlayerState::default())
+            .insert(CharacterFrame::default())
+            .id();
+        let hitbox = world.spawn().insert(HitboxState::default()).id();
+        let respawn_point = world
+            .spawn()
+            .insert(RespawnPoint {
+                position: Vec2::ZERO,
+                facing: Facing::Right,
+                occupied_by: None,
+            })
+            .insert(StageIndex(0))
+            .id();
+        let mut players: [Option<Entity>; MAX_PLAYERS_PER_MATCH] = Default::default();
+        players[0] = Some(player);
+        world.insert_resource(MatchState {
+            players,
+            ..Default::default()
+        });
+
+        let mut save = save_world.system();
+        save.initialize(&mut world);
+        let saved = save.run((), &mut world);
+
+        world.get_mut::<Transform>(player).unwrap().translation = Vec3::ONE;
+        world.get_mut::<Body>(player).unwrap().velocity = Vec2::new(1.0, 2.0);
+        world.get_mut::<PlayerInput>(player).unwrap().push(
+            PlayerInputFrame {
+                buttons: Buttons::JUMP,
+                ..Default::default()
+            },
+            &Default::default(),
+        );
+        world.get_mut::<PlayerDamage>(player).unwrap().kill();
+        world.get_mut::<PlayerMovement>(player).unwrap().jump_count = 1;
+        world.get_mut::<PlayerState>(player).unwrap().frame = 4;
+        {
+            let mut frame = world.get_mut::<CharacterFrame>(player).unwrap();
+            frame.damage_resistance = 2.0;
+            frame.actions.push(FrameAction::ResetJumps);
+        }
+        world
+            .get_mut::<HitboxState>(hitbox)
+            .unwrap()
+            .previous_position = Some(Vec3::ONE);
+        world
+            .get_mut::<RespawnPoint>(respawn_point)
+            .unwrap()
+            .occupied_by = Some(Player { id: 0 });
+        {
+            let mut match_state = world.get_resource_mut::<MatchState>().unwrap();
+            match_state.frame = 10;
+            match_state.time_remaining = Some(5);
+        }
+        world.get_resource_mut::<MatchResult>().unwrap().winner = MatchWinner::NoContest;
+        assert_ne!(save.run((), &mut world), saved);
+
+        let mut load = load_world.system();
+        load.initialize(&mut world);
+        load.run(saved.clone(), &mut world);
+
+        assert_eq!(save.run((), &mut world), saved);
+        assert_eq!(world.get_resource::<RollbackStats>().unwrap().rollbacks, 1);
+    }
+
+    fn player_state(entity: Entity, location: Location) -> GameState {
+        let mut state = GameState {
+            match_state: MatchState::default(),
+            result: MatchResult::default(),
+            players: Default::default(),
+            hitboxes: Vec::new(),
+            respawn_points: Vec::new(),
+        };
+        state.match_state.players[0] = Some(entity);
+        state.players[0] = Some(PlayerSnapshot {
+            translation: Vec3::ZERO,
+            body: Body {
+                location,
+                ..Default::default()
+            },
+            location: Some(StageIndex(1)),
+            input: PlayerInput::default(),
+            damage: stock_damage(),
+            movement: PlayerMovement::default(),
+            state: PlayerState::default(),
+            frame: CharacterFrame::default(),
+        });
+        state
+    }
+
+    fn on_surface(surface: Entity) -> Location {
+        Location::Surface {
+            surface,
+            position: 0.5,
+        }
+    }
+
+    #[test]
+    pub fn test_checksum_ignores_entity_ids() {
+        let local = player_state(Entity::new(3), on_surface(Entity::new(8)));
+        let remote = player_state(Entity::new(5), on_surface(Entity::new(2)));
+        assert_eq!(local.checksum(), remote.checksum());
+
+        let mut moved = remote.clone();
+        if let Some(player) = moved.players[0].as_mut() {
+            player.location = Some(StageIndex(2));
+        }
+        assert_eq!(local.checksum().diff(&moved.checksum()), vec!["Body"]);
+    }
+
+    #[test]
+    pub fn test_checksum_covers_frame_actions() {
+        let mut state = player_state(Entity::new(0), Location::default());
+        let before = state.checksum();
+        if let Some(player) = state.players[0].as_mut() {
+            player.frame.actions.push(FrameAction::FastFall);
+        }
+        assert_eq!(before.diff(&state.checksum()), vec!["CharacterFrame"]);
+    }
+}
@@ -0,0 +1,378 @@
+use super::{
+    disconnect,
+    input::{Axis2D, Buttons, InputSource, PlayerInput, PlayerInputFrame},
+    physics::{Body, Location},
+    player::{Player, PlayerDamage},
+    stage::{BlastZone, Surface},
+    MatchConfig, MatchResult, MatchState,
+};
+use crate::geo::Bounds2D;
+use anyhow::anyhow;
+use bevy::{math::*, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// How close a target must be for a CPU to attack or shield.
+const ATTACK_RANGE: f32 = 1.0;
+
+/// How far above a CPU a target must be before it jumps to reach them.
+const JUMP_HEIGHT: f32 = 1.5;
+
+/// How close to the bottom blast zone a CPU will let itself fall before jumping back.
+const DANGER_HEIGHT: f32 = 3.0;
+
+/// The difficulty of a computer-controlled player.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CpuLevel {
+    Easy,
+    Normal,
+    Hard,
+}
+
+impl Default for CpuLevel {
+    fn default() -> Self {
+        Self::Normal
+    }
+}
+
+impl FromStr for CpuLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "easy" => Ok(Self::Easy),
+            "normal" => Ok(Self::Normal),
+            "hard" => Ok(Self::Hard),
+            _ => Err(anyhow!("expected one of easy, normal or hard")),
+        }
+    }
+}
+
+impl CpuLevel {
+    /// Parses a control name of the form `cpu` or `cpu:<LEVEL>`. Returns None if the
+    /// name is not a CPU.
+    pub fn from_control_name(name: &str) -> Option<anyhow::Result<Self>> {
+        if name == "cpu" {
+            return Some(Ok(Self::default()));
+        }
+        name.strip_prefix("cpu:").map(str::parse)
+    }
+
+    /// The number of frames between each of the CPU's decisions. Inputs are held in
+    /// between decisions.
+    fn reaction_frames(self) -> u32 {
+        match self {
+            Self::Easy => 20,
+            Self::Normal => 10,
+            Self::Hard => 4,
+        }
+    }
+
+    /// The chance out of 256 that the CPU attacks a target in range.
+    fn attack_chance(self) -> u32 {
+        match self {
+            Self::Easy => 64,
+            Self::Normal => 128,
+            Self::Hard => 224,
+        }
+    }
+
+    /// The chance out of 256 that the CPU shields when a target is in range.
+    fn shield_chance(self) -> u32 {
+        match self {
+            Self::Easy => 16,
+            Self::Normal => 48,
+            Self::Hard => 96,
+        }
+    }
+}
+
+/// What a CPU knows about a player in the match.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CpuView {
+    pub position: Vec2,
+    pub velocity: Vec2,
+    pub grounded: bool,
+    /// Set if the player is still in the match and not waiting to respawn.
+    pub active: bool,
+}
+
+/// The parts of the stage a CPU navigates by.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CpuStage {
+    /// The leftmost and rightmost points of the stage's floors.
+    pub left: f32,
+    pub right: f32,
+    /// The height of the highest floor.
+    pub top: f32,
+    /// The lowest point a player can fall to before being killed.
+    pub bottom: f32,
+}
+
+impl CpuStage {
+    fn new<'a>(surfaces: impl Iterator<Item = &'a Surface>, blast_zone: Option<Bounds2D>) -> Self {
+        let mut stage = Self {
+            left: f32::MAX,
+            right: f32::MIN,
+            top: f32::MIN,
+            bottom: blast_zone.map(|zone| zone.min().y).unwrap_or(f32::MIN),
+        };
+        for surface in surfaces.filter(|surface| surface.is_floor()) {
+            stage.left = stage.left.min(surface.left().point.x);
+            stage.right = stage.right.max(surface.right().point.x);
+            stage.top = stage
+                .top
+                .max(surface.left().point.y.max(surface.right().point.y));
+        }
+        // Without any floors, there is no stage to recover to.
+        if stage.left > stage.right {
+            stage.left = f32::MIN;
+            stage.right = f32::MAX;
+        }
+        stage
+    }
+
+    fn center(&self) -> f32 {
+        (self.left + self.right) * 0.5
+    }
+
+    fn is_offstage(&self, position: Vec2) -> bool {
+        position.x < self.left || position.x > self.right || position.y < self.top
+    }
+}
+
+/// Picks a number from 0 to 255 for a CPU's decision. Only depends on the simulation's
+/// state so that every peer, rollback and replay makes the same decisions.
+fn roll(frame: u32, player: u8, salt: u32) -> u32 {
+    let mut value = frame
+        .wrapping_mul(0x9E37_79B9)
+        .wrapping_add(u32::from(player).wrapping_mul(0x85EB_CA6B))
+        .wrapping_add(salt.wrapping_mul(0xC2B2_AE35));
+    value ^= value >> 16;
+    value = value.wrapping_mul(0x7FEB_352D);
+    value ^= value >> 15;
+    value & 0xFF
+}
+
+/// Presses a button if it was not held on the previous frame, otherwise releases it so
+/// that it can be pressed again.
+fn press(previous: &PlayerInputFrame, button: Buttons) -> Buttons {
+    if previous.buttons.contains(button) {
+        Buttons::empty()
+    } else {
+        button
+    }
+}
+
+fn frame_input(movement: Vec2, buttons: Buttons) -> PlayerInputFrame {
+    PlayerInputFrame {
+        movement: Axis2D::from(movement),
+        buttons,
+        ..Default::default()
+    }
+}
+
+/// Decides a CPU player's inputs for the next frame.
+pub fn decide(
+    level: CpuLevel,
+    frame: u32,
+    player: u8,
+    me: &CpuView,
+    others: &[CpuView],
+    stage: &CpuStage,
+    previous: &PlayerInputFrame,
+) -> PlayerInputFrame {
+    // Stagger each CPU's decisions so they do not all act on the same frame.
+    if (frame + u32::from(player)) % level.reaction_frames() != 0 {
+        return *previous;
+    }
+    if !me.active {
+        return PlayerInputFrame::default();
+    }
+
+    // Head back to the stage, jumping once falling or in danger of being killed.
+    if !me.grounded && stage.is_offstage(me.position) {
+        let direction = (stage.center() - me.position.x).signum();
+        let in_danger = me.position.y - stage.bottom < DANGER_HEIGHT;
+        let buttons = if me.velocity.y <= 0.0 || in_danger {
+            press(previous, Buttons::JUMP)
+        } else {
+            Buttons::empty()
+        };
+        return frame_input(Vec2::new(direction, 1.0), buttons);
+    }
+
+    let target = others.iter().filter(|other| other.active).min_by(|a, b| {
+        let a = a.position.distance_squared(me.position);
+        let b = b.position.distance_squared(me.position);
+        a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let target = match target {
+        Some(target) => target,
+        None => return PlayerInputFrame::default(),
+    };
+
+    let delta = target.position - me.position;
+    if delta.x.abs() <= ATTACK_RANGE && delta.y.abs() <= ATTACK_RANGE {
+        if roll(frame, player, 0) < level.shield_chance() {
+            return frame_input(Vec2::ZERO, Buttons::SHIELD);
+        }
+        if roll(frame, player, 1) < level.attack_chance() {
+            let facing = Vec2::new(delta.x.signum(), 0.0);
+            return frame_input(facing, press(previous, Buttons::ATTACK));
+        }
+        return PlayerInputFrame::default();
+    }
+
+    // Approach the target without walking off the edge of the stage.
+    let direction = delta.x.signum();
+    let at_edge = (direction < 0.0 && me.position.x <= stage.left + ATTACK_RANGE)
+        || (direction > 0.0 && me.position.x >= stage.right - ATTACK_RANGE);
+    let movement = if me.grounded && at_edge {
+        Vec2::ZERO
+    } else {
+        Vec2::new(direction, 0.0)
+    };
+    let buttons = if me.grounded && delta.y > JUMP_HEIGHT {
+        press(previous, Buttons::JUMP)
+    } else {
+        Buttons::empty()
+    };
+    frame_input(movement, buttons)
+}
+
+fn view(transform: &Transform, body: &Body, damage: &PlayerDamage) -> CpuView {
+    CpuView {
+        position: transform.translation.xy(),
+        velocity: body.velocity,
+        grounded: body.location.is_grounded(),
+        active: damage.is_alive() && !matches!(body.location, Location::Respawning { .. }),
+    }
+}
+
+/// Advances the inputs of every CPU player, including players a CPU took over from after
+/// they disconnected. Runs as part of the simulation, after the inputs of other players
+/// have been applied.
+pub(super) fn sample_cpu_input(
+    state: Res<MatchState>,
+    config: Res<MatchConfig>,
+    result: Res<MatchResult>,
+    surfaces: Query<&Surface>,
+    blast_zones: Query<&BlastZone>,
+    views: Query<(&Player, &Transform, &Body, &PlayerDamage)>,
+    mut cpus: Query<(
+        &Player,
+        &InputSource,
+        &Transform,
+        &Body,
+        &PlayerDamage,
+        &mut PlayerInput,
+    )>,
+) {
+    let stage = CpuStage::new(
+        surfaces.iter(),
+        blast_zones.iter().next().map(|zone| zone.0),
+    );
+    cpus.for_each_mut(|(player, source, transform, body, damage, mut input)| {
+        let level = match source {
+            InputSource::CPU(level) => *level,
+            _ if disconnect::replaced_by_cpu(&result, player.id as usize) => CpuLevel::default(),
+            _ => return,
+        };
+        let me = view(transform, body, damage);
+        let mut others: Vec<(u8, CpuView)> = views
+            .iter()
+            .filter(|(other, ..)| other.id != player.id)
+            .map(|(other, transform, body, damage)| (other.id, view(transform, body, damage)))
+            .collect();
+        // Query order is not guaranteed to match between peers.
+        others.sort_by_key(|(id, _)| *id);
+        let others: Vec<CpuView> = others.into_iter().map(|(_, view)| view).collect();
+        let frame = decide(
+            level,
+            state.frame,
+            player.id,
+            &me,
+            &others,
+            &stage,
+            &input.current,
+        );
+        input.push(frame, &config.smash_detection);
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stage() -> CpuStage {
+        CpuStage {
+            left: -5.0,
+            right: 5.0,
+            top: 0.0,
+            bottom: -10.0,
+        }
+    }
+
+    fn grounded(x: f32) -> CpuView {
+        CpuView {
+            position: Vec2::new(x, 0.0),
+            grounded: true,
+            active: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_cpu_approaches_nearest_target() {
+        let me = grounded(0.0);
+        let others = [grounded(-4.0), grounded(3.0)];
+        let input = decide(
+            CpuLevel::Hard,
+            0,
+            0,
+            &me,
+            &others,
+            &stage(),
+            &Default::default(),
+        );
+        assert!(input.movement.x() > 0.0);
+        assert!(input.buttons.is_empty());
+    }
+
+    #[test]
+    pub fn test_cpu_recovers_to_stage() {
+        let me = CpuView {
+            position: Vec2::new(7.0, -2.0),
+            velocity: Vec2::new(0.0, -1.0),
+            active: true,
+            ..Default::default()
+        };
+        let input = decide(
+            CpuLevel::Easy,
+            0,
+            0,
+            &me,
+            &[grounded(0.0)],
+            &stage(),
+            &Default::default(),
+        );
+        assert!(input.movement.x() < 0.0);
+        assert!(input.buttons.jump());
+        // The jump is released on the next decision so that it can be pressed again.
+        let next = decide(CpuLevel::Easy, 20, 0, &me, &[], &stage(), &input);
+        assert!(!next.buttons.jump());
+    }
+
+    #[test]
+    pub fn test_cpu_holds_inputs_between_decisions() {
+        let me = grounded(0.0);
+        let others = [grounded(0.5)];
+        let previous = frame_input(Vec2::new(-1.0, 0.0), Buttons::empty());
+        let held = decide(CpuLevel::Normal, 3, 0, &me, &others, &stage(), &previous);
+        assert_eq!(held, previous);
+        let first = decide(CpuLevel::Normal, 30, 0, &me, &others, &stage(), &previous);
+        let second = decide(CpuLevel::Normal, 30, 0, &me, &others, &stage(), &previous);
+        assert_eq!(first, second);
+    }
+}
@@ -0,0 +1,360 @@
+use super::backroll::{self, GameState, P2PSession};
+use crate::AppState;
+use backroll_transport_udp::UdpManager;
+use bevy::prelude::*;
+use bevy_backroll::backroll::transport::Peer;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+/// The number of confirmed frames kept around to compare against late checksum reports.
+const CHECKSUM_HISTORY_FRAMES: u32 = 600;
+
+/// The directory desynced game states are written to.
+const DESYNC_DUMP_DIR: &str = "desyncs";
+
+/// A per-component checksum of the simulation state at the start of a given frame.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StateChecksum {
+    pub frame: u32,
+    pub components: [u64; StateChecksum::COMPONENT_COUNT],
+}
+
+impl StateChecksum {
+    pub const COMPONENT_COUNT: usize = 11;
+
+    /// The names of the components checksummed, in the same order as `components`.
+    pub const COMPONENTS: [&'static str; Self::COMPONENT_COUNT] = [
+        "MatchState",
+        "MatchResult",
+        "Transform",
+        "Body",
+        "PlayerInput",
+        "PlayerDamage",
+        "PlayerMovement",
+        "PlayerState",
+        "CharacterFrame",
+        "HitboxState",
+        "RespawnPoint",
+    ];
+
+    /// Lists the names of all of the components that do not match between two checksums.
+    pub fn diff(&self, other: &Self) -> Vec<&'static str> {
+        Self::COMPONENTS
+            .iter()
+            .zip(self.components.iter().zip(other.components.iter()))
+            .filter(|(_, (a, b))| a != b)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+/// Fired when a remote peer reports a different checksum for a confirmed frame.
+#[derive(Clone, Debug)]
+pub struct DesyncDetected {
+    /// The first frame the simulations were found to diverge on.
+    pub frame: u32,
+    /// The components that differ between the local and remote simulation.
+    pub components: Vec<&'static str>,
+    /// Where the local game state was dumped to, if it was successfully written. The
+    /// remote peers' states are written next to it once they arrive.
+    pub dump: Option<PathBuf>,
+}
+
+/// Messages exchanged between the desync detectors of each peer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum DesyncMessage {
+    /// The checksum of a confirmed frame.
+    Checksum(StateChecksum),
+    /// A peer's full state on the first frame it found to be desynced, sent so that every
+    /// peer can dump both sides of the desync.
+    State {
+        name: String,
+        frame: u32,
+        state: String,
+    },
+}
+
+/// A history of the most recently saved game states, keyed by frame.
+///
+/// Saved states for frames that are re-simulated during a rollback are overwritten, so
+/// any state older than the rollback window reflects the confirmed simulation.
+#[derive(Default)]
+pub struct StateHistory(BTreeMap<u32, GameState>);
+
+impl StateHistory {
+    pub fn record(&mut self, state: GameState) {
+        let frame = state.frame();
+        self.0.insert(frame, state);
+        if let Some(min) = frame.checked_sub(CHECKSUM_HISTORY_FRAMES) {
+            self.0 = self.0.split_off(&min);
+        }
+    }
+
+    pub fn get(&self, frame: u32) -> Option<&GameState> {
+        self.0.get(&frame)
+    }
+
+    /// Gets the most recent recorded frame that can no longer be rolled back. Matches that
+    /// are not run through a backroll session are never rolled back.
+    pub fn confirmed_frame(&self, session: Option<&P2PSession>) -> Option<u32> {
+        let latest = *self.0.keys().next_back()?;
+        match session {
+            Some(session) => backroll::confirmed_frame(session).map(|frame| frame.min(latest)),
+            None => Some(latest),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.0.clear();
+    }
+}
+
+/// Exchanges checksums of confirmed frames with remote peers.
+///
+/// Checksums are sent over a separate socket from the one used by the backroll session,
+/// as the session consumes every message received from its peers.
+pub struct DesyncDetector {
+    name: String,
+    // Kept alive for as long as the peers are in use.
+    _socket: UdpManager,
+    peers: Vec<Peer>,
+    last_sent: Option<u32>,
+    local: BTreeMap<u32, StateChecksum>,
+    /// The checksums reported by each peer, in the same order as `peers`.
+    remote: Vec<BTreeMap<u32, StateChecksum>>,
+    first_desync: Option<u32>,
+}
+
+impl DesyncDetector {
+    /// Creates a new detector. The name is used to disambiguate dumps from multiple
+    /// clients on the same machine.
+    pub fn new(name: impl Into<String>, socket: UdpManager, peers: Vec<Peer>) -> Self {
+        let remote = peers.iter().map(|_| BTreeMap::new()).collect();
+        Self {
+            name: name.into(),
+            _socket: socket,
+            peers,
+            last_sent: None,
+            local: BTreeMap::new(),
+            remote,
+            first_desync: None,
+        }
+    }
+
+    fn send(&self, message: &DesyncMessage) {
+        let bytes = match serde_json::to_vec(message) {
+            Ok(bytes) => bytes.into_boxed_slice(),
+            Err(err) => {
+                error!("Failed to serialize desync message: {}", err);
+                return;
+            }
+        };
+        for peer in self.peers.iter() {
+            if peer.send(bytes.clone()).is_err() {
+                warn!("Failed to send desync message to a peer");
+            }
+        }
+    }
+
+    /// Stores the checksums received from each peer and dumps any desynced states they
+    /// sent.
+    fn receive(&mut self) {
+        for (peer, remote) in self.peers.iter().zip(self.remote.iter_mut()) {
+            while let Ok(bytes) = peer.try_recv() {
+                match serde_json::from_slice::<DesyncMessage>(&bytes) {
+                    Ok(DesyncMessage::Checksum(checksum)) => {
+                        remote.insert(checksum.frame, checksum);
+                    }
+                    Ok(DesyncMessage::State { name, frame, state }) => {
+                        match write_dump(frame, &name, &state) {
+                            Ok(path) => info!("Dumped remote desynced state to {}", path.display()),
+                            Err(err) => error!("Failed to dump remote desynced state: {}", err),
+                        }
+                    }
+                    Err(err) => warn!("Received malformed desync message: {}", err),
+                }
+            }
+        }
+    }
+
+    fn trim(&mut self, confirmed: u32) {
+        if let Some(min) = confirmed.checked_sub(CHECKSUM_HISTORY_FRAMES) {
+            self.local = self.local.split_off(&min);
+            for remote in self.remote.iter_mut() {
+                *remote = remote.split_off(&min);
+            }
+        }
+    }
+
+    /// Forgets every checksum and desync from previous matches.
+    fn reset(&mut self) {
+        self.last_sent = None;
+        self.local.clear();
+        for remote in self.remote.iter_mut() {
+            remote.clear();
+        }
+        self.first_desync = None;
+    }
+
+    /// Dumps the local state and sends it to every peer so they can dump it too.
+    fn dump(&self, state: &GameState) -> std::io::Result<PathBuf> {
+        let contents = format!("{:#?}", state);
+        self.send(&DesyncMessage::State {
+            name: self.name.clone(),
+            frame: state.frame(),
+            state: contents.clone(),
+        });
+        write_dump(state.frame(), &self.name, &contents)
+    }
+}
+
+fn write_dump(frame: u32, name: &str, contents: &str) -> std::io::Result<PathBuf> {
+    let dir = Path::new(DESYNC_DUMP_DIR);
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("frame-{}-{}.txt", frame, name));
+    std::fs::write(&path, contents)?;
+    Ok(path)
+}
+
+/// Finds the earliest frame any peer reported a different checksum for than the local
+/// one, along with the components that differ.
+fn first_mismatch(
+    local: &BTreeMap<u32, StateChecksum>,
+    remote: &[BTreeMap<u32, StateChecksum>],
+) -> Option<(u32, Vec<&'static str>)> {
+    remote
+        .iter()
+        .filter_map(|remote| {
+            remote
+                .iter()
+                .filter_map(|(frame, remote)| Some((*frame, local.get(frame)?, remote)))
+                .find(|(_, local, remote)| local != remote)
+        })
+        .min_by_key(|(frame, ..)| *frame)
+        .map(|(frame, local, remote)| (frame, local.diff(remote)))
+}
+
+fn reset_history(mut history: ResMut<StateHistory>, detector: Option<ResMut<DesyncDetector>>) {
+    history.clear();
+    if let Some(mut detector) = detector {
+        detector.reset();
+    }
+}
+
+fn check_desyncs(
+    history: Res<StateHistory>,
+    session: Option<Res<P2PSession>>,
+    detector: Option<ResMut<DesyncDetector>>,
+    mut desyncs: EventWriter<DesyncDetected>,
+) {
+    let mut detector = match detector {
+        Some(detector) => detector,
+        None => return,
+    };
+    let confirmed = match history.confirmed_frame(session.as_deref()) {
+        Some(confirmed) => confirmed,
+        None => return,
+    };
+
+    // Checksum and broadcast every newly confirmed frame.
+    let start = detector.last_sent.map(|frame| frame + 1).unwrap_or(0);
+    for frame in start..=confirmed {
+        if let Some(state) = history.get(frame) {
+            let checksum = state.checksum();
+            detector.send(&DesyncMessage::Checksum(checksum.clone()));
+            detector.local.insert(frame, checksum);
+        }
+    }
+    detector.last_sent = Some(confirmed);
+    detector.receive();
+
+    if detector.first_desync.is_none() {
+        if let Some((frame, components)) = first_mismatch(&detector.local, &detector.remote) {
+            detector.first_desync = Some(frame);
+            let dump = history
+                .get(frame)
+                .and_then(|state| match detector.dump(state) {
+                    Ok(path) => Some(path),
+                    Err(err) => {
+                        error!("Failed to dump desynced state: {}", err);
+                        None
+                    }
+                });
+            error!(
+                "Desync detected on frame {}. Mismatched components: {:?}",
+                frame, components
+            );
+            desyncs.send(DesyncDetected {
+                frame,
+                components,
+                dump,
+            });
+        }
+    }
+
+    detector.trim(confirmed);
+}
+
+pub(super) fn build(builder: &mut AppBuilder) {
+    builder
+        .init_resource::<StateHistory>()
+        .add_event::<DesyncDetected>()
+        .add_system_set(SystemSet::on_enter(AppState::MATCH).with_system(reset_history.system()))
+        .add_system_set(SystemSet::on_update(AppState::MATCH).with_system(check_desyncs.system()));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn checksum(frame: u32, components: &[(usize, u64)]) -> StateChecksum {
+        let mut checksum = StateChecksum {
+            frame,
+            components: [0; StateChecksum::COMPONENT_COUNT],
+        };
+        for (idx, value) in components.iter() {
+            checksum.components[*idx] = *value;
+        }
+        checksum
+    }
+
+    fn history(checksums: Vec<StateChecksum>) -> BTreeMap<u32, StateChecksum> {
+        checksums
+            .into_iter()
+            .map(|checksum| (checksum.frame, checksum))
+            .collect()
+    }
+
+    #[test]
+    pub fn test_diff_names_mismatched_components() {
+        let local = checksum(3, &[(0, 1), (3, 2), (10, 3)]);
+        assert!(local.diff(&local.clone()).is_empty());
+        let remote = checksum(3, &[(0, 1), (3, 5), (10, 4)]);
+        assert_eq!(local.diff(&remote), vec!["Body", "RespawnPoint"]);
+    }
+
+    #[test]
+    pub fn test_first_mismatch_checks_every_peer() {
+        let local = history(vec![checksum(1, &[]), checksum(2, &[]), checksum(3, &[])]);
+        let remote = vec![
+            history(vec![checksum(1, &[]), checksum(3, &[(1, 1)])]),
+            history(vec![checksum(2, &[(4, 1)]), checksum(4, &[(5, 1)])]),
+        ];
+        assert_eq!(
+            first_mismatch(&local, &remote),
+            Some((2, vec!["PlayerInput"]))
+        );
+        assert_eq!(
+            first_mismatch(&local, &remote[..1]),
+            Some((3, vec!["MatchResult"]))
+        );
+        // Frames the local peer has not confirmed yet are not compared.
+        assert_eq!(
+            first_mismatch(&local, &[history(vec![checksum(4, &[(0, 1)])])]),
+            None
+        );
+    }
+}
@@ -0,0 +1,418 @@
+use super::{backroll::SessionConfig, rule::MatchWinner, MatchResult, MAX_PLAYERS_PER_MATCH};
+use crate::{player::Player, AppState};
+use anyhow::{anyhow, Context};
+use bevy::prelude::*;
+use bevy_backroll::backroll::{self, PlayerHandle};
+use serde::{Deserialize, Serialize};
+use std::{str::FromStr, time::Duration};
+
+/// What happens to a match when a remote peer disconnects from the session.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DisconnectPolicy {
+    /// End the match immediately without a winner.
+    NoContest,
+    /// Hand the disconnected player's slot to a CPU.
+    Cpu,
+    /// Wait for the peer to reconnect, ending the match without a winner if they do not
+    /// reconnect before the grace period runs out.
+    ReconnectGrace(Duration),
+}
+
+impl Default for DisconnectPolicy {
+    fn default() -> Self {
+        Self::NoContest
+    }
+}
+
+impl FromStr for DisconnectPolicy {
+    type Err = anyhow::Error;
+
+    /// Parses one of `no-contest`, `cpu` or `wait:<SECONDS>`.
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        match value {
+            "no-contest" => Ok(Self::NoContest),
+            "cpu" => Ok(Self::Cpu),
+            _ => {
+                let seconds = value
+                    .strip_prefix("wait:")
+                    .ok_or_else(|| anyhow!("expected one of no-contest, cpu or wait:<SECONDS>"))?;
+                let seconds: u64 = seconds
+                    .parse()
+                    .with_context(|| format!("invalid grace period: '{}'", seconds))?;
+                Ok(Self::ReconnectGrace(Duration::from_secs(seconds)))
+            }
+        }
+    }
+}
+
+/// How a player's disconnect was resolved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DisconnectOutcome {
+    /// The match was ended without a winner.
+    NoContest,
+    /// A CPU took over the player's slot for the rest of the match.
+    ReplacedByCpu,
+}
+
+/// A disconnect recorded in the match result.
+///
+/// Peers that reconnect within the grace period never miss an input, so only disconnects
+/// that outlast it are recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PlayerDisconnect {
+    /// The first frame the player's inputs were missing on.
+    pub frame: u32,
+    pub outcome: DisconnectOutcome,
+}
+
+impl DisconnectPolicy {
+    fn outcome(&self) -> DisconnectOutcome {
+        match self {
+            Self::Cpu => DisconnectOutcome::ReplacedByCpu,
+            // The grace period has run out once the player's inputs are missing.
+            Self::NoContest | Self::ReconnectGrace(_) => DisconnectOutcome::NoContest,
+        }
+    }
+}
+
+/// Records that a player's inputs are missing on a frame because their peer disconnected.
+///
+/// Runs as part of the simulation on the frames inputs are missing on, so every peer
+/// records the same frame, including when frames are re-simulated. Only the first frame
+/// is recorded.
+pub(super) fn record_disconnect(
+    result: &mut MatchResult,
+    player: usize,
+    frame: u32,
+    policy: &DisconnectPolicy,
+) {
+    let player = match result.players.get_mut(player).and_then(Option::as_mut) {
+        Some(player) => player,
+        None => return,
+    };
+    if player.disconnect.is_none() {
+        player.disconnect = Some(PlayerDisconnect {
+            frame,
+            outcome: policy.outcome(),
+        });
+    }
+}
+
+/// Checks if a player's slot was handed to a CPU after their peer disconnected.
+pub(super) fn replaced_by_cpu(result: &MatchResult, player: usize) -> bool {
+    result
+        .players
+        .get(player)
+        .and_then(Option::as_ref)
+        .and_then(|player| player.disconnect)
+        .map_or(false, |disconnect| {
+            disconnect.outcome == DisconnectOutcome::ReplacedByCpu
+        })
+}
+
+/// The connection state of remote peers, shown to the local players.
+///
+/// Peers report connection changes at different times, so this is not part of the
+/// simulation. Disconnects are recorded from the inputs instead, see `record_disconnect`.
+#[derive(Default)]
+pub struct PeerDisconnects {
+    /// Remaining time before interrupted peers are considered disconnected.
+    countdowns: [Option<Timer>; MAX_PLAYERS_PER_MATCH],
+}
+
+struct ReconnectCountdown;
+
+fn reset_disconnects(mut disconnects: ResMut<PeerDisconnects>) {
+    *disconnects = Default::default();
+}
+
+fn handle_peer_events(
+    mut events: EventReader<backroll::Event>,
+    session: Res<SessionConfig>,
+    mut disconnects: ResMut<PeerDisconnects>,
+    players: Query<(&Player, &PlayerHandle)>,
+) {
+    for event in events.iter() {
+        let handle = match event {
+            backroll::Event::ConnectionInterrupted { player, .. } => player,
+            backroll::Event::ConnectionResumed(player) => player,
+            backroll::Event::Disconnected(player) => player,
+            _ => continue,
+        };
+        let (player, _) = match players.iter().find(|(_, h)| *h == handle) {
+            Some(player) => player,
+            None => continue,
+        };
+        let id = player.id as usize;
+
+        match (event, &session.disconnect_policy) {
+            (
+                backroll::Event::ConnectionInterrupted {
+                    disconnect_timeout, ..
+                },
+                DisconnectPolicy::ReconnectGrace(_),
+            ) => {
+                warn!("Player {} connection interrupted", id);
+                disconnects.countdowns[id] =
+                    Some(Timer::new(*disconnect_timeout, /*repeating=*/ false));
+            }
+            (backroll::Event::ConnectionResumed(_), DisconnectPolicy::ReconnectGrace(_)) => {
+                info!("Player {} reconnected", id);
+                disconnects.countdowns[id] = None;
+            }
+            (backroll::Event::Disconnected(_), DisconnectPolicy::Cpu) => {
+                warn!("Player {} disconnected, replacing them with a CPU", id);
+            }
+            (backroll::Event::Disconnected(_), _) => {
+                warn!("Player {} disconnected, ending the match", id);
+                disconnects.countdowns[id] = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Ends the match without a winner once a disconnect that ends the match has been
+/// recorded. Runs after every other system that decides the winner of the frame.
+pub(super) fn apply_disconnects(mut result: ResMut<MatchResult>) {
+    let no_contest = result
+        .players
+        .iter()
+        .flatten()
+        .filter_map(|player| player.disconnect)
+        .any(|disconnect| disconnect.outcome == DisconnectOutcome::NoContest);
+    if no_contest {
+        result.winner = MatchWinner::NoContest;
+    }
+}
+
+fn spawn_countdown(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands
+        .spawn_bundle(TextBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                position: Rect {
+                    top: Val::Percent(45.0),
+                    left: Val::Percent(35.0),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: asset_server.load("fonts/FiraSans-Bold.ttf"),
+                    font_size: 30.0,
+                    color: Color::WHITE,
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(ReconnectCountdown);
+}
+
+fn despawn_countdown(countdowns: Query<Entity, With<ReconnectCountdown>>, mut commands: Commands) {
+    for entity in countdowns.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+fn update_countdown(
+    time: Res<Time>,
+    mut disconnects: ResMut<PeerDisconnects>,
+    mut texts: Query<&mut Text, With<ReconnectCountdown>>,
+) {
+    let mut value = String::new();
+    for (id, countdown) in disconnects.countdowns.iter_mut().enumerate() {
+        if let Some(timer) = countdown {
+            timer.tick(time.delta());
+            let remaining = timer.duration().saturating_sub(timer.elapsed());
+            value.push_str(&format!(
+                "Player {} disconnected. Waiting to reconnect: {}s\n",
+                id + 1,
+                remaining.as_secs_f32().ceil()
+            ));
+        }
+    }
+    texts.for_each_mut(|mut text| {
+        text.sections[0].value = value.clone();
+    });
+}
+
+pub(super) fn build(builder: &mut AppBuilder) {
+    builder
+        .init_resource::<PeerDisconnects>()
+        .add_system_set(
+            SystemSet::on_enter(AppState::MATCH).with_system(reset_disconnects.system()),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::MATCH).with_system(handle_peer_events.system()),
+        );
+}
+
+pub(super) fn build_view(builder: &mut AppBuilder) {
+    builder
+        .add_system_set(SystemSet::on_enter(AppState::MATCH).with_system(spawn_countdown.system()))
+        .add_system_set(
+            SystemSet::on_update(AppState::MATCH).with_system(update_countdown.system()),
+        )
+        .add_system_set(
+            SystemSet::on_exit(AppState::MATCH).with_system(despawn_countdown.system()),
+        );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::r#match::{
+        input::{
+            inject_frame_inputs, Buttons, FrameInputs, InputSource, PlayerInput, PlayerInputFrame,
+        },
+        player::PlayerConfig,
+        rule::update_match_state,
+        MatchConfig, MatchState,
+    };
+    use bevy::input::gamepad::Gamepad;
+    use std::ops::Range;
+
+    const DISCONNECT_FRAME: u32 = 4;
+
+    struct Match {
+        world: World,
+        stage: SystemStage,
+        players: Vec<Entity>,
+    }
+
+    type Snapshot = (MatchState, MatchResult, Vec<PlayerInput>);
+
+    impl Match {
+        fn new(policy: DisconnectPolicy) -> Self {
+            let mut config = MatchConfig::default();
+            for id in 0..2 {
+                config.players[id] = Some(PlayerConfig {
+                    player: backroll::Player::Local,
+                    character_id: 0,
+                    pallete: 0,
+                    default_damage: 0.0,
+                    input: InputSource::default_gamepad(Gamepad(id)),
+                });
+            }
+            let mut world = World::default();
+            world.insert_resource(MatchResult::from_config(&config));
+            world.insert_resource(config);
+            world.insert_resource(MatchState::default());
+            world.insert_resource(SessionConfig {
+                disconnect_policy: policy,
+                ..Default::default()
+            });
+            let players = (0..2)
+                .map(|id| {
+                    world
+                        .spawn()
+                        .insert(Player { id })
+                        .insert(InputSource::default_gamepad(Gamepad(id as usize)))
+                        .insert(PlayerInput::default())
+                        .id()
+                })
+                .collect();
+            let stage = SystemStage::single_threaded()
+                .with_system(inject_frame_inputs.system().label("SAMPLE_INPUT"))
+                .with_system(
+                    update_match_state
+                        .system()
+                        .label("UPDATE_MATCH_STATE")
+                        .after("SAMPLE_INPUT"),
+                )
+                .with_system(apply_disconnects.system().after("UPDATE_MATCH_STATE"));
+            Self {
+                world,
+                stage,
+                players,
+            }
+        }
+
+        /// Simulates frames on which the second player's peer disconnects on
+        /// `DISCONNECT_FRAME`.
+        fn simulate(&mut self, frames: Range<u32>) {
+            for frame in frames {
+                let mut inputs = FrameInputs::new(
+                    [PlayerInputFrame {
+                        buttons: Buttons::ATTACK,
+                        ..Default::default()
+                    }; MAX_PLAYERS_PER_MATCH],
+                );
+                inputs.disconnected[1] = frame >= DISCONNECT_FRAME;
+                self.world.insert_resource(inputs);
+                self.stage.run(&mut self.world);
+            }
+        }
+
+        fn save(&self) -> Snapshot {
+            (
+                self.world.get_resource::<MatchState>().unwrap().clone(),
+                self.world.get_resource::<MatchResult>().unwrap().clone(),
+                self.players
+                    .iter()
+                    .map(|player| *self.world.get::<PlayerInput>(*player).unwrap())
+                    .collect(),
+            )
+        }
+
+        fn load(&mut self, (state, result, inputs): Snapshot) {
+            self.world.insert_resource(state);
+            self.world.insert_resource(result);
+            for (player, input) in self.players.iter().zip(inputs) {
+                *self.world.get_mut::<PlayerInput>(*player).unwrap() = input;
+            }
+        }
+
+        fn result(&self) -> &MatchResult {
+            self.world.get_resource::<MatchResult>().unwrap()
+        }
+    }
+
+    #[test]
+    pub fn test_disconnects_survive_rollbacks() {
+        let mut game = Match::new(DisconnectPolicy::Cpu);
+        game.simulate(0..2);
+        let before = game.save();
+        game.simulate(2..5);
+        let after = game.save();
+        game.simulate(5..8);
+        let expected = game.save();
+
+        let disconnect = game.result().players[1].as_ref().unwrap().disconnect;
+        assert_eq!(
+            disconnect,
+            Some(PlayerDisconnect {
+                frame: DISCONNECT_FRAME,
+                outcome: DisconnectOutcome::ReplacedByCpu,
+            })
+        );
+        assert_eq!(game.result().players[0].as_ref().unwrap().disconnect, None);
+        assert_eq!(game.result().winner, MatchWinner::Undecided);
+        assert!(replaced_by_cpu(game.result(), 1));
+        // The CPU provides the inputs once it takes over.
+        assert_eq!(expected.2[1], after.2[1]);
+
+        // Re-simulating frames from before and after the disconnect records it on the
+        // same frame.
+        game.load(before);
+        game.simulate(2..8);
+        assert_eq!(game.save(), expected);
+        game.load(after);
+        game.simulate(5..8);
+        assert_eq!(game.save(), expected);
+    }
+
+    #[test]
+    pub fn test_disconnects_end_the_match() {
+        let mut game = Match::new(DisconnectPolicy::NoContest);
+        game.simulate(0..DISCONNECT_FRAME);
+        assert_eq!(game.result().winner, MatchWinner::Undecided);
+        game.simulate(DISCONNECT_FRAME..DISCONNECT_FRAME + 1);
+        assert_eq!(game.result().winner, MatchWinner::NoContest);
+        assert!(!replaced_by_cpu(game.result(), 1));
+    }
+}
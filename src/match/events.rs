@@ -1,12 +1,305 @@
-use super::player::{Player, PlayerDamage};
-use bevy::prelude::*;
+use super::{
+    backroll::{self, P2PSession},
+    player::{Player, PlayerDamage},
+    MatchState,
+};
+use crate::AppState;
+use bevy::{ecs::component::Component, prelude::*};
+use std::collections::BTreeMap;
 
+#[derive(Clone, Debug, PartialEq)]
 pub(super) struct PlayerDied {
     pub revive: bool,
     pub player: Player,
     pub damage: PlayerDamage,
 }
 
+/// An event produced by the match simulation, along with the frame that produced it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameEvent<T> {
+    pub frame: u32,
+    pub event: T,
+}
+
+/// Delivers events produced by the match simulation to systems outside of it.
+///
+/// Events sent inside the simulation are resent every time their frame is re-simulated,
+/// so systems outside of it should read these instead.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RollbackEvent<T> {
+    /// Produced on a frame that may still be rolled back. Cosmetic effects can react to
+    /// these early, but must be undone if the event is later cancelled.
+    Predicted(FrameEvent<T>),
+    /// A predicted event that did not occur after its frame was re-simulated.
+    Cancelled(FrameEvent<T>),
+    /// Produced on a frame that can no longer be rolled back. Each event is confirmed
+    /// exactly once.
+    Confirmed(FrameEvent<T>),
+}
+
+/// The events produced on each frame that has yet to be confirmed.
+pub(super) struct EventHistory<T> {
+    frames: BTreeMap<u32, Vec<T>>,
+    /// Events from frames before this one have already been confirmed.
+    next_unconfirmed: u32,
+}
+
+impl<T> Default for EventHistory<T> {
+    fn default() -> Self {
+        Self {
+            frames: BTreeMap::new(),
+            next_unconfirmed: 0,
+        }
+    }
+}
+
+/// Records the events produced on the current frame, sending out the events that were
+/// newly predicted or cancelled by a re-simulation of the frame.
+///
+/// Runs as part of the simulation, before the frame is advanced.
+pub(super) fn record_events<T: Component + Clone + PartialEq>(
+    mut events: EventReader<T>,
+    state: Res<MatchState>,
+    mut history: ResMut<EventHistory<T>>,
+    mut output: EventWriter<RollbackEvent<T>>,
+) {
+    let frame = state.frame;
+    let events: Vec<T> = events.iter().cloned().collect();
+    // Confirmed frames may still be re-simulated by sync tests.
+    if frame < history.next_unconfirmed {
+        return;
+    }
+    let previous = history.frames.insert(frame, events.clone());
+    let previous = previous.unwrap_or_default();
+    for event in previous.iter().filter(|event| !events.contains(event)) {
+        output.send(RollbackEvent::Cancelled(FrameEvent {
+            frame,
+            event: event.clone(),
+        }));
+    }
+    for event in events.into_iter().filter(|event| !previous.contains(event)) {
+        output.send(RollbackEvent::Predicted(FrameEvent { frame, event }));
+    }
+}
+
+/// Confirms the events of every frame that can no longer be rolled back. Matches that are
+/// not run through a backroll session are never rolled back, so every frame is confirmed
+/// as soon as it is simulated.
+fn confirm_events<T: Component + Clone>(
+    state: Option<Res<MatchState>>,
+    session: Option<Res<P2PSession>>,
+    mut history: ResMut<EventHistory<T>>,
+    mut output: EventWriter<RollbackEvent<T>>,
+) {
+    // Only run while there is a match in progress.
+    let state = match state {
+        Some(state) => state,
+        None => return,
+    };
+    // Events from frames before this one can no longer be rolled back.
+    let confirmed = match session {
+        Some(session) => match backroll::confirmed_frame(&session) {
+            Some(frame) => (frame + 1).min(state.frame),
+            None => return,
+        },
+        None => state.frame,
+    };
+    let pending = history.frames.split_off(&confirmed);
+    let frames = std::mem::replace(&mut history.frames, pending);
+    for (frame, events) in frames {
+        for event in events {
+            output.send(RollbackEvent::Confirmed(FrameEvent { frame, event }));
+        }
+    }
+    history.next_unconfirmed = history.next_unconfirmed.max(confirmed);
+}
+
+/// Confirms the events of any remaining frames at the end of a match.
+fn flush_events<T: Component + Clone>(
+    mut history: ResMut<EventHistory<T>>,
+    mut output: EventWriter<RollbackEvent<T>>,
+) {
+    for (frame, events) in std::mem::take(&mut history.frames) {
+        for event in events {
+            output.send(RollbackEvent::Confirmed(FrameEvent { frame, event }));
+        }
+    }
+}
+
+fn reset_history<T: Component>(mut history: ResMut<EventHistory<T>>) {
+    *history = Default::default();
+}
+
+fn log_player_deaths(mut events: EventReader<RollbackEvent<PlayerDied>>) {
+    for event in events.iter() {
+        if let RollbackEvent::Confirmed(FrameEvent { frame, event }) = event {
+            info!(
+                "Player {} died on frame {}: {:?}",
+                event.player.id, frame, event.damage
+            );
+        }
+    }
+}
+
+/// Registers an event that is sent inside the match simulation, along with the
+/// `RollbackEvent` channel used to deliver it to systems outside of the simulation.
+///
+/// `record_events::<T>` must be added to the simulation after the systems that send it.
+pub(super) fn add_rollback_event<T: Component + Clone>(builder: &mut AppBuilder) {
+    builder
+        .add_event::<T>()
+        .add_event::<RollbackEvent<T>>()
+        .init_resource::<EventHistory<T>>()
+        .add_system_set(
+            SystemSet::on_enter(AppState::MATCH).with_system(reset_history::<T>.system()),
+        )
+        // Runs after every stage that may advance the simulation.
+        .add_system_to_stage(CoreStage::PostUpdate, confirm_events::<T>.system())
+        .add_system_set(
+            SystemSet::on_exit(AppState::MATCH).with_system(flush_events::<T>.system()),
+        );
+}
+
 pub fn build(builder: &mut AppBuilder) {
-    builder.add_event::<PlayerDied>();
+    add_rollback_event::<PlayerDied>(builder);
+    builder.add_system_to_stage(CoreStage::Last, log_player_deaths.system());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bevy::{app::ManualEventReader, ecs::system::System};
+
+    struct Simulation {
+        world: World,
+        output: ManualEventReader<RollbackEvent<u32>>,
+    }
+
+    impl Simulation {
+        fn new() -> Self {
+            let mut world = World::default();
+            world.insert_resource(MatchState::default());
+            world.insert_resource(EventHistory::<u32>::default());
+            world.insert_resource(Events::<u32>::default());
+            let output = Events::<RollbackEvent<u32>>::default();
+            let reader = output.get_reader();
+            world.insert_resource(output);
+            Self {
+                world,
+                output: reader,
+            }
+        }
+
+        fn set_frame(&mut self, frame: u32) {
+            self.world.get_resource_mut::<MatchState>().unwrap().frame = frame;
+        }
+
+        /// Simulates a frame that sends the given events.
+        fn simulate(
+            &mut self,
+            record: &mut impl System<In = (), Out = ()>,
+            frame: u32,
+            events: &[u32],
+        ) {
+            self.set_frame(frame);
+            {
+                let mut input = self.world.get_resource_mut::<Events<u32>>().unwrap();
+                for event in events {
+                    input.send(*event);
+                }
+            }
+            record.run((), &mut self.world);
+        }
+
+        fn delivered(&mut self) -> Vec<RollbackEvent<u32>> {
+            let output = self.world.get_resource::<Events<RollbackEvent<u32>>>();
+            self.output.iter(output.unwrap()).cloned().collect()
+        }
+    }
+
+    fn event(frame: u32, event: u32) -> FrameEvent<u32> {
+        FrameEvent { frame, event }
+    }
+
+    #[test]
+    pub fn test_rollback_cancels_mispredicted_events() {
+        let mut sim = Simulation::new();
+        let mut record = record_events::<u32>.system();
+        let mut confirm = confirm_events::<u32>.system();
+        record.initialize(&mut sim.world);
+        confirm.initialize(&mut sim.world);
+
+        sim.simulate(&mut record, 0, &[1]);
+        sim.simulate(&mut record, 1, &[2]);
+        assert_eq!(
+            sim.delivered(),
+            vec![
+                RollbackEvent::Predicted(event(0, 1)),
+                RollbackEvent::Predicted(event(1, 2)),
+            ]
+        );
+
+        // Roll back to frame 1, which now produces a different event.
+        sim.simulate(&mut record, 1, &[3]);
+        assert_eq!(
+            sim.delivered(),
+            vec![
+                RollbackEvent::Cancelled(event(1, 2)),
+                RollbackEvent::Predicted(event(1, 3)),
+            ]
+        );
+        // Events that are predicted again are not resent.
+        sim.simulate(&mut record, 1, &[3]);
+        assert_eq!(sim.delivered(), vec![]);
+
+        sim.set_frame(2);
+        confirm.run((), &mut sim.world);
+        assert_eq!(
+            sim.delivered(),
+            vec![
+                RollbackEvent::Confirmed(event(0, 1)),
+                RollbackEvent::Confirmed(event(1, 3)),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_confirmed_events_are_delivered_once() {
+        let mut sim = Simulation::new();
+        let mut record = record_events::<u32>.system();
+        let mut confirm = confirm_events::<u32>.system();
+        record.initialize(&mut sim.world);
+        confirm.initialize(&mut sim.world);
+
+        sim.simulate(&mut record, 0, &[1]);
+        sim.set_frame(1);
+        confirm.run((), &mut sim.world);
+        assert_eq!(
+            sim.delivered(),
+            vec![
+                RollbackEvent::Predicted(event(0, 1)),
+                RollbackEvent::Confirmed(event(0, 1)),
+            ]
+        );
+        confirm.run((), &mut sim.world);
+        assert_eq!(sim.delivered(), vec![]);
+
+        // Re-simulating a confirmed frame neither cancels nor confirms its events again.
+        sim.simulate(&mut record, 0, &[4]);
+        sim.set_frame(1);
+        confirm.run((), &mut sim.world);
+        assert_eq!(sim.delivered(), vec![]);
+
+        // Frames past the confirmed boundary are still confirmed as they are simulated.
+        sim.simulate(&mut record, 1, &[2]);
+        sim.set_frame(2);
+        confirm.run((), &mut sim.world);
+        assert_eq!(
+            sim.delivered(),
+            vec![
+                RollbackEvent::Predicted(event(1, 2)),
+                RollbackEvent::Confirmed(event(1, 2)),
+            ]
+        );
+    }
 }
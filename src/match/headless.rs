@@ -0,0 +1,231 @@
+use super::{
+    frame_input_stage,
+    input::{Axis1D, Axis2D, Buttons, FrameInputs, PlayerInputFrame},
+    MatchResult, MatchState, MAX_PLAYERS_PER_MATCH,
+};
+use anyhow::{anyhow, bail, Context, Result};
+use bevy::{app::AppExit, prelude::*};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// The inputs of a single player for one step of an input script.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+struct ScriptInput {
+    movement: [i8; 2],
+    smash: [i8; 2],
+    /// The names of the held buttons, i.e. "attack" or "jump".
+    buttons: Vec<String>,
+}
+
+impl ScriptInput {
+    fn to_frame(&self) -> Result<PlayerInputFrame> {
+        let mut buttons = Buttons::empty();
+        for button in self.buttons.iter() {
+            buttons |= Buttons::from_name(button)
+                .ok_or_else(|| anyhow!("unknown button: '{}'", button))?;
+        }
+        let axis = |[x, y]: [i8; 2]| Axis2D {
+            x: Axis1D(x),
+            y: Axis1D(y),
+        };
+        Ok(PlayerInputFrame {
+            movement: axis(self.movement),
+            smash: axis(self.smash),
+            buttons,
+        })
+    }
+}
+
+/// Holds the given inputs for a number of frames.
+#[derive(Clone, Debug, Deserialize)]
+struct ScriptStep {
+    frames: u32,
+    /// The inputs of each player slot, in order. Missing players are left idle.
+    #[serde(default)]
+    inputs: Vec<ScriptInput>,
+}
+
+/// A scripted sequence of inputs to drive a match with.
+///
+/// Scripts are JSON files of the form:
+/// `{ "steps": [{ "frames": 30, "inputs": [{ "movement": [127, 0], "buttons": ["jump"] }] }] }`
+#[derive(Clone, Debug, Deserialize)]
+pub struct InputScript {
+    steps: Vec<ScriptStep>,
+}
+
+impl InputScript {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to open input script {}", path.display()))?;
+        serde_json::from_str(&contents)
+            .with_context(|| format!("failed to read input script {}", path.display()))
+    }
+
+    /// Expands the script into the inputs for every frame of the match.
+    pub fn frames(&self) -> Result<Vec<[PlayerInputFrame; MAX_PLAYERS_PER_MATCH]>> {
+        let mut frames = Vec::new();
+        for (idx, step) in self.steps.iter().enumerate() {
+            if step.inputs.len() > MAX_PLAYERS_PER_MATCH {
+                bail!(
+                    "step {} has inputs for more than {} players",
+                    idx,
+                    MAX_PLAYERS_PER_MATCH
+                );
+            }
+            let mut inputs: [PlayerInputFrame; MAX_PLAYERS_PER_MATCH] = Default::default();
+            for (slot, input) in step.inputs.iter().enumerate() {
+                inputs[slot] = input
+                    .to_frame()
+                    .with_context(|| format!("invalid input in step {}", idx))?;
+            }
+            frames.extend(std::iter::repeat(inputs).take(step.frames as usize));
+        }
+        Ok(frames)
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+struct HeadlessStageLabel;
+
+/// Advances the simulation by a frame every update, as fast as the machine allows.
+struct HeadlessStage {
+    inputs: Vec<[PlayerInputFrame; MAX_PLAYERS_PER_MATCH]>,
+    max_frames: u32,
+    simulation: SystemStage,
+    finished: bool,
+}
+
+impl Stage for HeadlessStage {
+    fn run(&mut self, world: &mut World) {
+        // Only run while there is a match in progress.
+        let frame = match world.get_resource::<MatchState>() {
+            Some(state) => state.frame,
+            None => return,
+        };
+        if self.finished {
+            return;
+        }
+
+        let inputs = match self.inputs.get(frame as usize) {
+            Some(inputs) if frame < self.max_frames => *inputs,
+            Some(_) => {
+                info!("Stopping match after reaching the frame limit: {}", frame);
+                self.finish(world);
+                return;
+            }
+            None => {
+                info!("Stopping match after running out of inputs: {}", frame);
+                self.finish(world);
+                return;
+            }
+        };
+        world.insert_resource(FrameInputs::new(inputs));
+        self.simulation.run(world);
+    }
+}
+
+impl HeadlessStage {
+    fn finish(&mut self, world: &mut World) {
+        self.finished = true;
+        world
+            .get_resource_mut::<Events<AppExit>>()
+            .unwrap()
+            .send(AppExit);
+    }
+}
+
+/// Where to write the match result to. Written to stdout if not set.
+struct HeadlessOutput(Option<PathBuf>);
+
+fn write_result(
+    mut exits: EventReader<AppExit>,
+    output: Res<HeadlessOutput>,
+    result: Res<MatchResult>,
+) {
+    if exits.iter().count() == 0 {
+        return;
+    }
+    let json = match serde_json::to_string(&*result) {
+        Ok(json) => json,
+        Err(err) => {
+            error!("Failed to serialize match result: {}", err);
+            return;
+        }
+    };
+    match output.0 {
+        Some(ref path) => {
+            if let Err(err) = std::fs::write(path, json) {
+                error!(
+                    "Failed to write match result to {}: {}",
+                    path.display(),
+                    err
+                );
+            }
+        }
+        None => println!("{}", json),
+    }
+}
+
+/// Runs a match without a window using a fixed list of inputs, writing the `MatchResult`
+/// as JSON when the match ends.
+pub struct FcHeadlessPlugin {
+    pub inputs: Vec<[PlayerInputFrame; MAX_PLAYERS_PER_MATCH]>,
+    /// The number of frames to simulate before giving up on the match.
+    pub max_frames: u32,
+    pub output: Option<PathBuf>,
+}
+
+impl Plugin for FcHeadlessPlugin {
+    fn build(&self, builder: &mut AppBuilder) {
+        builder
+            .insert_resource(FrameInputs::default())
+            .insert_resource(HeadlessOutput(self.output.clone()))
+            .add_stage_after(
+                CoreStage::Update,
+                HeadlessStageLabel,
+                HeadlessStage {
+                    inputs: self.inputs.clone(),
+                    max_frames: self.max_frames,
+                    simulation: frame_input_stage(),
+                    finished: false,
+                },
+            )
+            .add_system_to_stage(CoreStage::Last, write_result.system());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_script_expands_steps() {
+        let script: InputScript = serde_json::from_str(
+            r#"{
+                "steps": [
+                    { "frames": 2 },
+                    { "frames": 3, "inputs": [{}, { "movement": [127, 0], "buttons": ["jump"] }] }
+                ]
+            }"#,
+        )
+        .unwrap();
+        let frames = script.frames().unwrap();
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames[0], Default::default());
+        assert_eq!(frames[4][0], PlayerInputFrame::default());
+        assert_eq!(frames[4][1].movement.x, Axis1D(127));
+        assert!(frames[4][1].buttons.jump());
+    }
+
+    #[test]
+    pub fn test_script_rejects_unknown_buttons() {
+        let script: InputScript = serde_json::from_str(
+            r#"{ "steps": [{ "frames": 1, "inputs": [{ "buttons": ["dance"] }] }] }"#,
+        )
+        .unwrap();
+        assert!(script.frames().is_err());
+    }
+}
@@ -1,17 +1,21 @@
 use super::{
+    events,
     physics::Body,
     player::{Player, PlayerDamage, PlayerId},
     stage::StageContext,
     MatchState,
 };
 use crate::{
-    character::frame_data::{hitbox::Hitbox, hurtbox::Hurtbox, *},
+    character::{
+        frame_data::{hitbox::Hitbox, hurtbox::Hurtbox, *},
+        state::PlayerState,
+    },
     geo::Capsule3D,
 };
 use bevy::prelude::*;
 use std::collections::HashMap;
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct HitboxState {
     pub id: u8,
     pub player: PlayerId,
@@ -36,7 +40,7 @@ pub(super) struct HitboxBundle {
     pub state: HitboxState,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct HitCollision {
     pub hitbox: Hitbox,
     pub hitbox_state: HitboxState,
@@ -109,10 +113,9 @@ const BASE_KNOCKBACK_SCALING: f32 = 0.1;
 const IMPACT_KNOCKBACK_SCALING: f32 = 0.05;
 
 pub(super) fn hit_players(
-    // TODO(james7132): Replace this with EventConsumer
     mut hits: EventReader<HitCollision>,
     match_state: Res<MatchState>,
-    mut players: Query<(&mut PlayerDamage, &mut Body), With<Player>>,
+    mut players: Query<(&mut PlayerDamage, &mut Body, &mut PlayerState), With<Player>>,
     mut stage: StageContext,
 ) {
     let mut player_hits: HashMap<PlayerId, HitCollision> = HashMap::new();
@@ -128,7 +131,7 @@ pub(super) fn hit_players(
     for (player_id, hit) in player_hits.iter() {
         let player = match_state.players[*player_id as usize]
             .and_then(|entity| players.get_mut(entity).ok());
-        if let Some((mut damage, mut body)) = player {
+        if let Some((mut damage, mut body, mut state)) = player {
             let hitbox = &hit.hitbox;
             let hurtbox = &hit.hurtbox;
 
@@ -141,6 +144,7 @@ pub(super) fn hit_players(
             let knockback = scaler.evaluate(damage_launch)
                 * Vec2::new(libm::cosf(knockback_angle), libm::sinf(knockback_angle));
             body.launch(knockback, &mut stage);
+            state.hit = true;
         } else {
             warn!("Registered hit for unknown player ID: {}", player_id)
         }
@@ -148,5 +152,5 @@ pub(super) fn hit_players(
 }
 
 pub(super) fn build(builder: &mut AppBuilder) {
-    builder.add_event::<HitCollision>();
+    events::add_rollback_event::<HitCollision>(builder);
 }
@@ -1,15 +1,25 @@
-use super::player::Player;
+use super::{
+    backroll::SessionConfig,
+    cpu::CpuLevel,
+    disconnect::{self, DisconnectPolicy},
+    player::Player,
+    MatchConfig, MatchResult, MatchState, MAX_PLAYERS_PER_MATCH,
+};
 use bevy::{
-    input::{gamepad::GamepadButton, keyboard::KeyCode, Input},
+    input::{
+        gamepad::{Gamepad, GamepadAxis, GamepadAxisType, GamepadButton, GamepadButtonType},
+        keyboard::KeyCode,
+        Axis, Input,
+    },
     math::{Vec2, Vec3},
     prelude::*,
 };
 use bevy_backroll::backroll::{GameInput, PlayerHandle};
 use bytemuck::{Pod, Zeroable};
-use serde::{Deserialize, Serialize};
+use serde::{de::Error as _, ser::SerializeMap, Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt;
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     hash::Hash,
     ops::{Add, Sub},
 };
@@ -35,6 +45,25 @@ impl Buttons {
         Self::GRAB,
     ];
 
+    /// Gets the name of a single button, as used in config files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ATTACK => "attack",
+            Self::SPECIAL => "special",
+            Self::JUMP => "jump",
+            Self::SHIELD => "shield",
+            Self::GRAB => "grab",
+            _ => "unknown",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .cloned()
+            .find(|button| button.name() == name)
+    }
+
     #[inline]
     pub fn attack(&self) -> bool {
         self.contains(Self::ATTACK)
@@ -96,7 +125,7 @@ impl Buttons {
 }
 
 #[repr(C)]
-#[derive(Pod, Zeroable, Clone, Copy, Default, Eq, PartialEq)]
+#[derive(Pod, Zeroable, Clone, Copy, Default, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub struct Axis1D(pub i8);
 
 impl Add<Axis1D> for Axis1D {
@@ -136,7 +165,9 @@ impl fmt::Debug for Axis1D {
 }
 
 #[repr(C)]
-#[derive(Pod, Zeroable, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[derive(
+    Pod, Zeroable, Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize,
+)]
 pub struct Axis2D {
     pub x: Axis1D,
     pub y: Axis1D,
@@ -196,22 +227,191 @@ impl From<Vec2> for Axis2D {
 }
 
 #[repr(C)]
-#[derive(Pod, Zeroable, Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[derive(
+    Pod, Zeroable, Clone, Copy, Debug, Default, Eq, PartialEq, Hash, Serialize, Deserialize,
+)]
 pub struct PlayerInputFrame {
     pub movement: Axis2D,
     pub smash: Axis2D,
     pub buttons: Buttons,
 }
 
-#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+/// The number of frames of inputs kept in each player's input history.
+pub const INPUT_HISTORY_FRAMES: usize = 32;
+
+/// One of the analog sticks on a player's input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Stick {
+    Movement,
+    Smash,
+}
+
+impl Stick {
+    fn get(self, frame: &PlayerInputFrame) -> Axis2D {
+        match self {
+            Self::Movement => frame.movement,
+            Self::Smash => frame.smash,
+        }
+    }
+}
+
+/// A direction the stick can be pushed in. Directions are absolute, not relative to the
+/// direction the player is facing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StickDirection {
+    Any,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl StickDirection {
+    /// Checks if the stick is pushed at least `threshold` in the direction, where the
+    /// threshold is in the same units as an `Axis1D`. Uses integer math so the result is
+    /// deterministic.
+    fn reaches(self, axis: Axis2D, threshold: i32) -> bool {
+        let (x, y) = (i32::from(axis.x.0), i32::from(axis.y.0));
+        match self {
+            Self::Any => x * x + y * y >= threshold * threshold,
+            Self::Up => y >= threshold,
+            Self::Down => -y >= threshold,
+            Self::Left => -x >= threshold,
+            Self::Right => x >= threshold,
+        }
+    }
+}
+
+/// A fixed size ring buffer of a player's most recent inputs.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
+pub struct InputHistory {
+    frames: [PlayerInputFrame; INPUT_HISTORY_FRAMES],
+    /// The index of the most recent frame.
+    head: usize,
+}
+
+impl InputHistory {
+    pub fn push(&mut self, frame: PlayerInputFrame) {
+        self.head = (self.head + 1) % INPUT_HISTORY_FRAMES;
+        self.frames[self.head] = frame;
+    }
+
+    /// Gets the inputs from a number of frames ago, where 0 is the current frame. Frames
+    /// older than the history are treated as neutral.
+    pub fn get(&self, age: usize) -> PlayerInputFrame {
+        if age >= INPUT_HISTORY_FRAMES {
+            return PlayerInputFrame::default();
+        }
+        self.frames[(self.head + INPUT_HISTORY_FRAMES - age) % INPUT_HISTORY_FRAMES]
+    }
+
+    /// Checks if all of the buttons went from released to held on the current frame or
+    /// any of the `frames` frames before it.
+    pub fn pressed_within(&self, buttons: Buttons, frames: usize) -> bool {
+        let frames = frames.min(INPUT_HISTORY_FRAMES - 2);
+        (0..=frames).any(|age| {
+            self.get(age).buttons.contains(buttons) && !self.get(age + 1).buttons.contains(buttons)
+        })
+    }
+
+    /// Checks if all of the buttons have been held for at least the last `frames` frames,
+    /// including the current one.
+    pub fn held_for(&self, buttons: Buttons, frames: usize) -> bool {
+        let frames = frames.min(INPUT_HISTORY_FRAMES);
+        (0..frames).all(|age| self.get(age).buttons.contains(buttons))
+    }
+
+    /// Checks if the stick was pushed past `threshold` in the given direction on the
+    /// current frame or any of the `frames` frames before it, having not been past it on
+    /// the frame before that.
+    pub fn stick_crossed_within(
+        &self,
+        stick: Stick,
+        direction: StickDirection,
+        threshold: f32,
+        frames: usize,
+    ) -> bool {
+        let threshold = i32::from(Axis1D::from(threshold).0);
+        let frames = frames.min(INPUT_HISTORY_FRAMES - 2);
+        (0..=frames).any(|age| {
+            direction.reaches(stick.get(&self.get(age)), threshold)
+                && !direction.reaches(stick.get(&self.get(age + 1)), threshold)
+        })
+    }
+
+    /// Checks if the stick is currently pushed between `min` and `max` in the given
+    /// direction, inclusive.
+    pub fn stick_within(
+        &self,
+        stick: Stick,
+        direction: StickDirection,
+        min: f32,
+        max: f32,
+    ) -> bool {
+        let threshold = |value| i32::from(Axis1D::from(value).0);
+        let axis = stick.get(&self.get(0));
+        direction.reaches(axis, threshold(min)) && !direction.reaches(axis, threshold(max) + 1)
+    }
+}
+
+/// Infers smash inputs from quick flicks of the movement stick, for players whose smash
+/// stick is not bound or left neutral.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SmashDetection {
+    /// Sticks closer to the center than this are considered neutral.
+    pub neutral_threshold: f32,
+    /// How far the stick must be pushed for a flick to count as a smash.
+    pub smash_threshold: f32,
+    /// The most frames the stick can take to go from neutral to past the smash threshold.
+    pub frames: usize,
+}
+
+impl Default for SmashDetection {
+    fn default() -> Self {
+        Self {
+            neutral_threshold: 0.25,
+            smash_threshold: 0.8,
+            frames: 3,
+        }
+    }
+}
+
+impl SmashDetection {
+    /// Checks if moving the stick to `movement` on the next frame would be a smash, given
+    /// the player's previous inputs. Uses integer math so the result is deterministic.
+    pub fn is_flick(&self, history: &InputHistory, movement: Axis2D) -> bool {
+        let threshold = |value| i32::from(Axis1D::from(value).0);
+        let neutral = threshold(self.neutral_threshold);
+        let smash = threshold(self.smash_threshold);
+        let frames = self.frames.clamp(1, INPUT_HISTORY_FRAMES);
+        let reaches = |axis, threshold| StickDirection::Any.reaches(axis, threshold);
+        reaches(movement, smash)
+            && !reaches(history.get(0).movement, smash)
+            && (0..frames).any(|age| !reaches(history.get(age).movement, neutral))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Hash)]
 pub struct PlayerInput {
     pub previous: PlayerInputFrame,
+    /// The inputs for the current frame. If the smash stick was left neutral, `smash` is
+    /// set to the movement stick when the movement stick was flicked.
     pub current: PlayerInputFrame,
+    /// The player's recent inputs, including the current frame. Saved alongside the rest
+    /// of the player's state so that it is restored on rollback.
+    pub history: InputHistory,
 }
 
 impl PlayerInput {
-    pub fn tick(&mut self) {
+    /// Advances to the next frame's inputs.
+    pub fn push(&mut self, mut frame: PlayerInputFrame, smash: &SmashDetection) {
+        if frame.smash == Axis2D::default() && smash.is_flick(&self.history, frame.movement) {
+            frame.smash = frame.movement;
+        }
         self.previous = self.current;
+        self.current = frame;
+        self.history.push(frame);
     }
 
     pub fn was_pressed(&self) -> Buttons {
@@ -231,22 +431,32 @@ impl PlayerInput {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum InputSource {
     /// This player does not require a local input source. Their inputs may be sourced from
     /// external sources (i.e. a replay or the network)
     None,
-    /// Reserved for when CPU players are available.
-    CPU,
+    /// The player is controlled by the computer. Their inputs are decided as part of the
+    /// simulation, so every peer controls them the same way.
+    CPU(CpuLevel),
     /// The player is sourcing their inputs from the local keyboard.
     Keyboard {
         movement: ButtonAxis2D<KeyCode>,
-        smash: ButtonAxis2D<KeyCode>,
+        /// Smash inputs are inferred from the movement keys if not bound.
+        #[serde(default)]
+        smash: Option<ButtonAxis2D<KeyCode>>,
         buttons: ButtonMapping<KeyCode>,
+        #[serde(default)]
+        analog: KeyboardAnalog,
     },
     /// The player is sourcing their inputs from the a local gamepad.
     Gamepad {
-        buttons: ButtonMapping<GamepadButton>,
+        gamepad: Gamepad,
+        movement: GamepadStick,
+        smash: GamepadStick,
+        buttons: ButtonMapping<GamepadButtonType>,
+        #[serde(default)]
+        analog: AnalogSettings,
     },
 }
 
@@ -256,6 +466,223 @@ impl Default for InputSource {
     }
 }
 
+/// The local devices player inputs are sampled from.
+pub struct InputDevices<'a> {
+    pub keyboard: &'a Input<KeyCode>,
+    pub gamepad_buttons: &'a Input<GamepadButton>,
+    /// The analog values of gamepad buttons, i.e. how far each trigger is pressed.
+    pub gamepad_button_axes: &'a Axis<GamepadButton>,
+    pub gamepad_axes: &'a Axis<GamepadAxis>,
+}
+
+impl<'a> InputDevices<'a> {
+    pub fn from_world(world: &'a World) -> Self {
+        Self {
+            keyboard: world.get_resource().unwrap(),
+            gamepad_buttons: world.get_resource().unwrap(),
+            gamepad_button_axes: world.get_resource().unwrap(),
+            gamepad_axes: world.get_resource().unwrap(),
+        }
+    }
+}
+
+impl InputSource {
+    /// Creates the default controls for a gamepad.
+    pub fn default_gamepad(gamepad: Gamepad) -> Self {
+        let mut buttons: HashMap<Buttons, Vec<GamepadButtonType>> = HashMap::new();
+        buttons.insert(Buttons::ATTACK, vec![GamepadButtonType::West]);
+        buttons.insert(Buttons::SPECIAL, vec![GamepadButtonType::East]);
+        buttons.insert(
+            Buttons::JUMP,
+            vec![GamepadButtonType::South, GamepadButtonType::North],
+        );
+        buttons.insert(
+            Buttons::SHIELD,
+            vec![
+                GamepadButtonType::LeftTrigger2,
+                GamepadButtonType::RightTrigger2,
+            ],
+        );
+        buttons.insert(
+            Buttons::GRAB,
+            vec![
+                GamepadButtonType::LeftTrigger,
+                GamepadButtonType::RightTrigger,
+            ],
+        );
+        Self::Gamepad {
+            gamepad,
+            movement: GamepadStick {
+                horizontal: GamepadAxisType::LeftStickX,
+                vertical: GamepadAxisType::LeftStickY,
+            },
+            smash: GamepadStick {
+                horizontal: GamepadAxisType::RightStickX,
+                vertical: GamepadAxisType::RightStickY,
+            },
+            buttons: ButtonMapping(buttons),
+            analog: Default::default(),
+        }
+    }
+
+    /// Samples the current local input for the source. Returns None if the source does
+    /// not sample its inputs locally.
+    pub fn sample(&self, devices: &InputDevices) -> Option<PlayerInputFrame> {
+        match self {
+            Self::None | Self::CPU(_) => None,
+            Self::Keyboard {
+                movement,
+                smash,
+                buttons,
+                analog,
+            } => Some(PlayerInputFrame {
+                movement: analog.apply(movement.sample(devices.keyboard), |key| {
+                    devices.keyboard.pressed(key)
+                }),
+                smash: smash
+                    .as_ref()
+                    .map(|smash| smash.sample(devices.keyboard))
+                    .unwrap_or_default(),
+                buttons: buttons.evaluate_all(devices.keyboard),
+            }),
+            Self::Gamepad {
+                gamepad,
+                movement,
+                smash,
+                buttons,
+                analog,
+            } => Some(PlayerInputFrame {
+                movement: movement.sample(*gamepad, devices.gamepad_axes, analog),
+                smash: smash.sample(*gamepad, devices.gamepad_axes, analog),
+                buttons: buttons.evaluate_all_with(|button| {
+                    let button = GamepadButton(*gamepad, button);
+                    // Analog triggers are held once pressed past the threshold.
+                    match devices.gamepad_button_axes.get(button) {
+                        Some(value) => value >= analog.trigger_threshold,
+                        None => devices.gamepad_buttons.pressed(button),
+                    }
+                }),
+            }),
+        }
+    }
+}
+
+/// How analog gamepad inputs are filtered before being quantized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalogSettings {
+    /// Sticks pushed less than this far from the center are treated as neutral. Inputs
+    /// outside of the deadzone are rescaled to cover the full range.
+    pub radial_deadzone: f32,
+    /// Each axis of a stick is snapped to zero when below this value, making it easier
+    /// to hold a stick in a cardinal direction.
+    pub axial_deadzone: f32,
+    /// How far an analog trigger must be pressed before it counts as held.
+    pub trigger_threshold: f32,
+}
+
+impl Default for AnalogSettings {
+    fn default() -> Self {
+        Self {
+            radial_deadzone: 0.2,
+            axial_deadzone: 0.1,
+            trigger_threshold: 0.4,
+        }
+    }
+}
+
+impl AnalogSettings {
+    /// Applies the radial and axial deadzones to a raw stick position.
+    pub fn apply_deadzones(&self, stick: Vec2) -> Vec2 {
+        let magnitude = stick.length();
+        if magnitude <= self.radial_deadzone || magnitude == 0.0 {
+            return Vec2::ZERO;
+        }
+        let scaled = ((magnitude - self.radial_deadzone) / (1.0 - self.radial_deadzone)).min(1.0);
+        let stick = stick * (scaled / magnitude);
+        let snap = |value: f32| {
+            if value.abs() < self.axial_deadzone {
+                0.0
+            } else {
+                value
+            }
+        };
+        Vec2::new(snap(stick.x), snap(stick.y))
+    }
+}
+
+/// The pair of gamepad axes that make up an analog stick.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GamepadStick {
+    pub horizontal: GamepadAxisType,
+    pub vertical: GamepadAxisType,
+}
+
+impl GamepadStick {
+    pub fn sample(
+        &self,
+        gamepad: Gamepad,
+        axes: &Axis<GamepadAxis>,
+        settings: &AnalogSettings,
+    ) -> Axis2D {
+        let value = |axis| axes.get(GamepadAxis(gamepad, axis)).unwrap_or(0.0);
+        let stick = Vec2::new(value(self.horizontal), value(self.vertical));
+        Axis2D::from(settings.apply_deadzones(stick))
+    }
+}
+
+/// A key that scales the keyboard's movement while held, i.e. to walk or tilt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyboardModifier {
+    pub key: KeyCode,
+    /// How far the stick is pushed while the key is held, from 0.0 to 1.0.
+    pub scale: f32,
+}
+
+/// How the movement keys are combined when two directions are held at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiagonalMode {
+    /// Both axes are pushed all the way, like the corner of a square gate.
+    Square,
+    /// Both axes are scaled down so the stick is pushed as far as it is in a cardinal
+    /// direction, like a gamepad stick in a circular gate.
+    Circle,
+}
+
+impl Default for DiagonalMode {
+    fn default() -> Self {
+        Self::Square
+    }
+}
+
+/// Simulates analog stick positions with the keyboard's digital movement keys.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeyboardAnalog {
+    /// If several modifiers are held, the one listed first is used.
+    pub modifiers: Vec<KeyboardModifier>,
+    pub diagonals: DiagonalMode,
+}
+
+impl KeyboardAnalog {
+    /// Applies the diagonal mode and the first held modifier to a digital stick position.
+    pub fn apply(&self, axis: Axis2D, pressed: impl Fn(KeyCode) -> bool) -> Axis2D {
+        if axis == Axis2D::default() {
+            return axis;
+        }
+        let mut stick = Vec2::from(axis);
+        if self.diagonals == DiagonalMode::Circle {
+            stick = stick.normalize();
+        }
+        let scale = self
+            .modifiers
+            .iter()
+            .find(|modifier| pressed(modifier.key))
+            .map(|modifier| modifier.scale.clamp(0.0, 1.0))
+            .unwrap_or(1.0);
+        Axis2D::from(stick * scale)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ButtonAxis1D<T> {
     pub pos: T,
@@ -288,32 +715,380 @@ impl<T: Copy + Eq + Hash> ButtonAxis2D<T> {
     }
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
+#[derive(Default, Debug, Clone)]
 pub struct ButtonMapping<T>(pub HashMap<Buttons, Vec<T>>);
 
+/// Serialized as a map from each button's name to its bindings.
+impl<T: Serialize> Serialize for ButtonMapping<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for button in Buttons::ALL {
+            if let Some(bindings) = self.0.get(button) {
+                map.serialize_entry(button.name(), bindings)?;
+            }
+        }
+        map.end()
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for ButtonMapping<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        BTreeMap::<String, Vec<T>>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(name, bindings)| match Buttons::from_name(&name) {
+                Some(button) => Ok((button, bindings)),
+                None => Err(D::Error::custom(format!("unknown button: '{}'", name))),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
 impl<T: Copy + Eq + Hash> ButtonMapping<T> {
     pub fn evaluate_all(&self, input: &Input<T>) -> Buttons {
+        self.evaluate_all_with(|button| input.pressed(button))
+    }
+
+    pub fn evaluate(&self, button: Buttons, input: &Input<T>) -> bool {
+        self.evaluate_with(button, |button| input.pressed(button))
+    }
+
+    /// Evaluates every button, using `pressed` to check if each bound input is held.
+    pub fn evaluate_all_with(&self, pressed: impl Fn(T) -> bool) -> Buttons {
         Buttons::ALL
             .iter()
             .cloned()
-            .reduce(|a, b| if self.evaluate(b, input) { a | b } else { a })
-            .unwrap_or(Buttons::empty())
+            .filter(|button| self.evaluate_with(*button, &pressed))
+            .fold(Buttons::empty(), |a, b| a | b)
     }
 
-    pub fn evaluate(&self, button: Buttons, input: &Input<T>) -> bool {
+    pub fn evaluate_with(&self, button: Buttons, pressed: impl Fn(T) -> bool) -> bool {
         self.0
             .get(&button)
-            .map(|buttons| buttons.iter().any(|button| input.pressed(*button)))
+            .map(|buttons| buttons.iter().any(|button| pressed(*button)))
             .unwrap_or(false)
     }
+
+    /// Binds a button to a single input, removing the input from any other buttons it
+    /// was bound to. Returns the buttons the input was removed from.
+    pub fn bind(&mut self, button: Buttons, input: T) -> Vec<Buttons> {
+        self.0.remove(&button);
+        let conflicts = self.unbind(input);
+        self.0.insert(button, vec![input]);
+        conflicts
+    }
+
+    /// Removes an input from every button it is bound to. Returns the buttons the input
+    /// was removed from.
+    pub fn unbind(&mut self, input: T) -> Vec<Buttons> {
+        let mut conflicts = Vec::new();
+        for button in Buttons::ALL.iter() {
+            if let Some(bindings) = self.0.get_mut(button) {
+                if bindings.contains(&input) {
+                    bindings.retain(|binding| *binding != input);
+                    conflicts.push(*button);
+                }
+            }
+        }
+        conflicts
+    }
+}
+
+/// Applies a player's inputs for the current frame. Players whose peer has disconnected
+/// no longer provide inputs: the disconnect is recorded in the match result and they are
+/// either left idle or handed to a CPU.
+fn apply_input(
+    player: &Player,
+    frame: Option<PlayerInputFrame>,
+    input: &mut PlayerInput,
+    config: &MatchConfig,
+    policy: &DisconnectPolicy,
+    state: &MatchState,
+    result: &mut MatchResult,
+) {
+    let id = player.id as usize;
+    if frame.is_none() {
+        disconnect::record_disconnect(result, id, state.frame, policy);
+        // CPU inputs are sampled later in the simulation.
+        if disconnect::replaced_by_cpu(result, id) {
+            return;
+        }
+    }
+    input.push(frame.unwrap_or_default(), &config.smash_detection);
 }
 
 pub(super) fn inject_input(
     input: Res<GameInput<PlayerInputFrame>>,
-    mut players: Query<(&PlayerHandle, &mut PlayerInput), With<Player>>,
+    config: Res<MatchConfig>,
+    session: Res<SessionConfig>,
+    state: Res<MatchState>,
+    mut result: ResMut<MatchResult>,
+    mut players: Query<(&Player, &PlayerHandle, &InputSource, &mut PlayerInput)>,
+) {
+    players.for_each_mut(|(player, handle, source, mut player_input)| {
+        // CPU inputs are sampled later in the simulation.
+        if let InputSource::CPU(_) = source {
+            return;
+        }
+        apply_input(
+            player,
+            input.get(*handle).ok().copied(),
+            &mut player_input,
+            &config,
+            &session.disconnect_policy,
+            &state,
+            &mut result,
+        );
+    });
+}
+
+/// The inputs for every player slot on the next frame when the match is not being driven
+/// by a backroll session.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FrameInputs {
+    pub inputs: [PlayerInputFrame; MAX_PLAYERS_PER_MATCH],
+    /// The players whose peer had disconnected by the frame.
+    pub disconnected: [bool; MAX_PLAYERS_PER_MATCH],
+}
+
+impl FrameInputs {
+    /// Creates the inputs for a frame on which every player is connected.
+    pub fn new(inputs: [PlayerInputFrame; MAX_PLAYERS_PER_MATCH]) -> Self {
+        Self {
+            inputs,
+            disconnected: Default::default(),
+        }
+    }
+}
+
+pub(super) fn inject_frame_inputs(
+    input: Res<FrameInputs>,
+    config: Res<MatchConfig>,
+    session: Res<SessionConfig>,
+    state: Res<MatchState>,
+    mut result: ResMut<MatchResult>,
+    mut players: Query<(&Player, &InputSource, &mut PlayerInput)>,
 ) {
-    players.for_each_mut(|(handle, mut player_input)| {
-        player_input.tick();
-        player_input.current = *input.get(*handle).unwrap();
+    players.for_each_mut(|(player, source, mut player_input)| {
+        // CPU inputs are sampled later in the simulation.
+        if let InputSource::CPU(_) = source {
+            return;
+        }
+        let id = player.id as usize;
+        apply_input(
+            player,
+            Some(input.inputs[id]).filter(|_| !input.disconnected[id]),
+            &mut player_input,
+            &config,
+            &session.disconnect_policy,
+            &state,
+            &mut result,
+        );
     });
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    pub fn test_radial_deadzone_zeroes_small_inputs() {
+        let settings = AnalogSettings::default();
+        assert_eq!(settings.apply_deadzones(Vec2::new(0.1, 0.1)), Vec2::ZERO);
+        assert_eq!(settings.apply_deadzones(Vec2::ZERO), Vec2::ZERO);
+    }
+
+    #[test]
+    pub fn test_radial_deadzone_rescales_to_full_range() {
+        let settings = AnalogSettings::default();
+        assert_eq!(
+            settings.apply_deadzones(Vec2::new(1.0, 0.0)),
+            Vec2::new(1.0, 0.0)
+        );
+        let stick = settings.apply_deadzones(Vec2::new(0.6, 0.0));
+        assert!((stick.x - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    pub fn test_axial_deadzone_snaps_to_cardinals() {
+        let settings = AnalogSettings::default();
+        let stick = settings.apply_deadzones(Vec2::new(0.9, 0.05));
+        assert_eq!(stick.y, 0.0);
+        assert!(stick.x > 0.8);
+    }
+
+    fn history(frames: &[PlayerInputFrame]) -> InputHistory {
+        let mut history = InputHistory::default();
+        for frame in frames {
+            history.push(*frame);
+        }
+        history
+    }
+
+    fn buttons(buttons: Buttons) -> PlayerInputFrame {
+        PlayerInputFrame {
+            buttons,
+            ..Default::default()
+        }
+    }
+
+    fn movement(x: f32, y: f32) -> PlayerInputFrame {
+        PlayerInputFrame {
+            movement: Axis2D::from(Vec2::new(x, y)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_history_wraps_around() {
+        let mut history = InputHistory::default();
+        for _ in 0..INPUT_HISTORY_FRAMES + 3 {
+            history.push(buttons(Buttons::empty()));
+        }
+        history.push(buttons(Buttons::JUMP));
+        assert!(history.get(0).buttons.jump());
+        assert!(!history.get(1).buttons.jump());
+        assert_eq!(history.get(INPUT_HISTORY_FRAMES), Default::default());
+    }
+
+    #[test]
+    pub fn test_history_pressed_within() {
+        let idle = buttons(Buttons::empty());
+        let jump = buttons(Buttons::JUMP);
+        let history = history(&[idle, jump, jump, idle, idle]);
+        assert!(!history.pressed_within(Buttons::JUMP, 2));
+        assert!(history.pressed_within(Buttons::JUMP, 3));
+        assert!(!history.pressed_within(Buttons::ATTACK, 10));
+    }
+
+    #[test]
+    pub fn test_history_held_for() {
+        let idle = buttons(Buttons::empty());
+        let shield = buttons(Buttons::SHIELD);
+        let history = history(&[idle, shield, shield, shield]);
+        assert!(history.held_for(Buttons::SHIELD, 3));
+        assert!(!history.held_for(Buttons::SHIELD, 4));
+    }
+
+    #[test]
+    pub fn test_history_stick_crossed_within() {
+        let history = history(&[movement(0.0, 0.0), movement(0.9, 0.0), movement(0.9, 0.0)]);
+        let crossed = |direction, frames| {
+            history.stick_crossed_within(Stick::Movement, direction, 0.8, frames)
+        };
+        assert!(!crossed(StickDirection::Right, 0));
+        assert!(crossed(StickDirection::Right, 1));
+        assert!(crossed(StickDirection::Any, 1));
+        assert!(!crossed(StickDirection::Left, 5));
+        assert!(!history.stick_crossed_within(Stick::Smash, StickDirection::Any, 0.8, 5));
+    }
+
+    fn push_movement(input: &mut PlayerInput, positions: &[(f32, f32)]) {
+        for (x, y) in positions {
+            input.push(movement(*x, *y), &SmashDetection::default());
+        }
+    }
+
+    #[test]
+    pub fn test_smash_detects_flicks() {
+        let mut input = PlayerInput::default();
+        push_movement(&mut input, &[(0.0, 0.0), (0.5, 0.0), (1.0, 0.0)]);
+        assert_eq!(input.current.smash, input.current.movement);
+        // Holding the stick after a flick is not another smash.
+        push_movement(&mut input, &[(1.0, 0.0)]);
+        assert_eq!(input.current.smash, Axis2D::default());
+    }
+
+    #[test]
+    pub fn test_smash_ignores_slow_tilts() {
+        let mut input = PlayerInput::default();
+        push_movement(
+            &mut input,
+            &[(0.0, 0.0), (0.3, 0.0), (0.5, 0.0), (0.6, 0.0), (0.9, 0.0)],
+        );
+        assert_eq!(input.current.smash, Axis2D::default());
+    }
+
+    #[test]
+    pub fn test_smash_ignores_flicks_from_the_opposite_direction() {
+        let mut input = PlayerInput::default();
+        push_movement(&mut input, &[(0.0, 0.0), (-1.0, 0.0), (-1.0, 0.0)]);
+        push_movement(&mut input, &[(-1.0, 0.0), (1.0, 0.0)]);
+        assert_eq!(input.current.smash, Axis2D::default());
+    }
+
+    #[test]
+    pub fn test_smash_prefers_the_smash_stick() {
+        let mut input = PlayerInput::default();
+        push_movement(&mut input, &[(0.0, 0.0)]);
+        let frame = PlayerInputFrame {
+            movement: Axis2D::from(Vec2::new(1.0, 0.0)),
+            smash: Axis2D::from(Vec2::new(0.0, -1.0)),
+            ..Default::default()
+        };
+        input.push(frame, &SmashDetection::default());
+        assert_eq!(input.current.smash, frame.smash);
+    }
+
+    #[test]
+    pub fn test_button_mapping_bind_removes_conflicts() {
+        let mut mapping = ButtonMapping::<KeyCode>::default();
+        mapping
+            .0
+            .insert(Buttons::JUMP, vec![KeyCode::W, KeyCode::Space]);
+        mapping.0.insert(Buttons::SHIELD, vec![KeyCode::Q]);
+        let conflicts = mapping.bind(Buttons::ATTACK, KeyCode::Space);
+        assert_eq!(conflicts, vec![Buttons::JUMP]);
+        assert_eq!(mapping.0[&Buttons::JUMP], vec![KeyCode::W]);
+        assert_eq!(mapping.0[&Buttons::ATTACK], vec![KeyCode::Space]);
+        assert!(mapping.bind(Buttons::SHIELD, KeyCode::E).is_empty());
+        assert_eq!(mapping.0[&Buttons::SHIELD], vec![KeyCode::E]);
+    }
+
+    #[test]
+    pub fn test_keyboard_modifiers_scale_movement() {
+        let analog = KeyboardAnalog {
+            modifiers: vec![
+                KeyboardModifier {
+                    key: KeyCode::LShift,
+                    scale: 0.5,
+                },
+                KeyboardModifier {
+                    key: KeyCode::LControl,
+                    scale: 0.25,
+                },
+            ],
+            diagonals: DiagonalMode::Square,
+        };
+        let right = Axis2D::from(Vec2::new(1.0, 0.0));
+        assert_eq!(analog.apply(right, |_| false), right);
+        let walk = analog.apply(right, |key| key == KeyCode::LShift);
+        assert_eq!(walk.x, Axis1D::from(0.5));
+        let both = analog.apply(right, |_| true);
+        assert_eq!(both.x, Axis1D::from(0.5));
+    }
+
+    #[test]
+    pub fn test_keyboard_diagonal_modes() {
+        let diagonal = Axis2D::from(Vec2::new(1.0, 1.0));
+        let square = KeyboardAnalog::default();
+        assert_eq!(square.apply(diagonal, |_| false), diagonal);
+        let circle = KeyboardAnalog {
+            diagonals: DiagonalMode::Circle,
+            ..Default::default()
+        };
+        let value = circle.apply(diagonal, |_| false);
+        assert_eq!(value.x, Axis1D::from(std::f32::consts::FRAC_1_SQRT_2));
+        assert_eq!(value.y, value.x);
+    }
+
+    #[test]
+    pub fn test_button_mapping_only_reports_held_buttons() {
+        let mut buttons = HashMap::new();
+        buttons.insert(Buttons::ATTACK, vec![1]);
+        buttons.insert(Buttons::JUMP, vec![2, 3]);
+        let mapping = ButtonMapping(buttons);
+        assert_eq!(mapping.evaluate_all_with(|_| false), Buttons::empty());
+        assert_eq!(mapping.evaluate_all_with(|b| b == 3), Buttons::JUMP);
+    }
+}
@@ -1,6 +1,6 @@
 use self::{player::*, stage::*};
 use crate::{
-    character::{frame_data::*, state::*},
+    character::{frame_data::*, state::*, CharacterAsset},
     geo::*,
     player::Player,
     AppState,
@@ -17,14 +17,22 @@ use bevy_backroll::*;
 use serde::{Deserialize, Serialize};
 use std::ops::Deref;
 
+pub mod action;
 pub mod backroll;
+pub mod cpu;
+pub mod desync;
+pub mod disconnect;
 pub mod events;
+pub mod headless;
 pub mod hitbox;
 pub mod input;
 pub mod physics;
 pub mod player;
+pub mod replay;
 pub mod rule;
+pub mod spectator;
 pub mod stage;
+pub mod sync_test;
 
 pub const MAX_PLAYERS_PER_MATCH: usize = 4;
 
@@ -35,6 +43,9 @@ pub struct MatchConfig {
     /// match will prematurely end if time reaches zero.
     pub time: Option<u32>,
     pub players: [Option<PlayerConfig>; MAX_PLAYERS_PER_MATCH],
+    /// Shared by all players so that every peer infers the same smash inputs.
+    #[serde(default)]
+    pub smash_detection: input::SmashDetection,
 }
 
 impl MatchConfig {
@@ -55,15 +66,17 @@ pub enum MatchConfigValidationError {
     NotEnoughPlayers,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
 pub struct MatchState {
+    /// The number of frames that have been simulated since the start of the match.
+    pub frame: u32,
     /// The number of frames remaining before the end of the match.
     /// If None, the match has no set time limit.
     pub time_remaining: Option<u32>,
     pub players: [Option<Entity>; MAX_PLAYERS_PER_MATCH],
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct MatchResult {
     pub winner: rule::MatchWinner,
     pub players: [Option<PlayerResult>; MAX_PLAYERS_PER_MATCH],
@@ -84,13 +97,20 @@ impl MatchResult {
     }
 }
 
-#[derive(Debug, Default)]
-pub struct PlayerResult {}
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct PlayerResult {
+    /// How the player's disconnect was resolved, if they disconnected during the match.
+    pub disconnect: Option<disconnect::PlayerDisconnect>,
+}
 
 fn init_match(
     config: Res<MatchConfig>,
     spawn_points: Query<&SpawnPoint>,
     task_pool: Res<IoTaskPool>,
+    session: Res<backroll::SessionConfig>,
+    frame_inputs: Option<Res<input::FrameInputs>>,
+    sync_test: Option<Res<sync_test::SyncTestConfig>>,
+    characters: Res<Assets<CharacterAsset>>,
     mut result: ResMut<MatchResult>,
     mut commands: Commands,
 ) {
@@ -107,7 +127,10 @@ fn init_match(
     // Systems need to be properly ordered to ensure that spawn points are added before players
     // are spawned.
     let mut spawn_points = spawn_points.iter();
-    let mut builder = backroll::P2PSession::build();
+    let mut builder = backroll::P2PSession::build().with_input_delay(session.input_delay);
+    if let disconnect::DisconnectPolicy::ReconnectGrace(grace) = session.disconnect_policy {
+        builder = builder.with_disconnect_timeout(grace);
+    }
     for (id, player_config) in config.players.iter().enumerate() {
         state.players[id] = player_config.as_ref().map(|cfg| {
             info!("Spawning player {}", id);
@@ -115,6 +138,19 @@ fn init_match(
                 .next()
                 .expect("Stage does not have enough spawn points");
             let transform = Transform::from_translation(Vec3::from((spawn_point.position, 0.0)));
+            let character = characters
+                .iter()
+                .map(|(_, character)| character)
+                .find(|character| character.id == cfg.character_id);
+            if character.is_none() {
+                warn!(
+                    "Unknown character {} for player {}, using default stats",
+                    cfg.character_id, id
+                );
+            }
+            let movement = character
+                .map(|character| character.movement.clone())
+                .unwrap_or_default();
             let bundle = player::PlayerBundle {
                 player: Player { id: id as u8 },
                 handle: builder.add_player(cfg.player.clone()),
@@ -125,14 +161,15 @@ fn init_match(
                         extents: Vec2::new(0.25, 0.5),
                     }),
                     location: physics::Location::Airborne(transform.translation.xy()),
-                    gravity: 1.0,
+                    weight: movement.weight,
+                    gravity: movement.gravity,
                     ..Default::default()
                 },
-                movement: PlayerMovement {
-                    jump_power: vec![2.5, 1.5],
-                    short_jump_power: 0.9,
-                    max_fall_speed: 2.0,
-                    fast_fall_speed: 5.0,
+                movement: PlayerMovement::from(&movement),
+                character: player::CharacterBundle {
+                    state_machine: character
+                        .map(|character| character.state_machine.clone())
+                        .unwrap_or_default(),
                     ..Default::default()
                 },
                 transform,
@@ -148,9 +185,16 @@ fn init_match(
             player::spawn_player(&mut commands, bundle)
         });
     }
-    let pool = task_pool.deref().deref().clone();
     commands.insert_resource(state);
-    commands.start_backroll_session(builder.start(pool).unwrap());
+    // Matches driven directly through FrameInputs (i.e. spectators and replays) do not use
+    // a backroll session.
+    if let Some(sync_test) = sync_test {
+        let players = config.players.iter().flatten().count();
+        commands.start_backroll_session(sync_test.build_session(players));
+    } else if frame_inputs.is_none() {
+        let pool = task_pool.deref().deref().clone();
+        commands.start_backroll_session(builder.start(pool).unwrap());
+    }
 }
 
 fn cleanup_match(state: Res<MatchState>, mut commands: Commands) {
@@ -161,13 +205,93 @@ fn cleanup_match(state: Res<MatchState>, mut commands: Commands) {
     commands.remove_resource::<MatchState>();
 }
 
-fn sample_frames(mut players: Query<(&mut CharacterFrame, &mut PlayerState, &StateMachine)>) {
-    players.for_each_mut(|(mut frame, mut state, state_machine)| {
-        state.tick();
-        if let Some(sampled) = state_machine.sample_frame(&state) {
-            *frame = sampled.clone();
-        }
-    });
+/// How close the top of a player must be to a grabbable ledge to grab onto it.
+// TODO(james7132): Use the character's Ledge_Grab_Check bone instead.
+const LEDGE_GRAB_DISTANCE: f32 = 0.5;
+
+fn update_states(
+    surfaces: Query<&Surface>,
+    hurtboxes: Query<&hurtbox::Hurtbox>,
+    mut players: Query<(
+        &Player,
+        &Transform,
+        &mut physics::Body,
+        &PlayerMovement,
+        &input::PlayerInput,
+        &StateMachine,
+        &mut PlayerState,
+    )>,
+) {
+    players.for_each_mut(
+        |(player, transform, mut body, movement, input, state_machine, mut state)| {
+            let grounded = body.location.is_grounded();
+            let grab_point = transform.translation.xy() + body.ecb.top();
+            let context = TransitionContext {
+                grounded,
+                just_landed: grounded && !state.grounded,
+                velocity: body.velocity,
+                jumps_remaining: movement
+                    .jump_power
+                    .len()
+                    .saturating_sub(movement.jump_count),
+                was_hit: state.hit,
+                shielding: hurtboxes.iter().any(|hurtbox| {
+                    hurtbox.player == player.id && hurtbox.r#type == hurtbox::HurtboxType::Shield
+                }),
+                ledge_nearby: !grounded
+                    && surfaces.iter().any(|surface| {
+                        [&surface.start, &surface.end].iter().any(|point| {
+                            point.grabbable
+                                && point.point.distance_squared(grab_point)
+                                    <= LEDGE_GRAB_DISTANCE * LEDGE_GRAB_DISTANCE
+                        })
+                    }),
+            };
+            state.grounded = grounded;
+            state.hit = false;
+            if let Some(next) = state_machine.next_state(&state, input, &context) {
+                state.state_id = next;
+                state.frame = 0;
+                // Gravity multipliers only last for the state that set them.
+                body.gravity = movement.gravity;
+            }
+        },
+    );
+}
+
+fn sample_frames(
+    mut players: Query<(
+        &Player,
+        &Transform,
+        &mut physics::Body,
+        &mut PlayerMovement,
+        &mut CharacterFrame,
+        &mut PlayerState,
+        &StateMachine,
+    )>,
+    mut cues: EventWriter<action::FrameCue>,
+) {
+    players.for_each_mut(
+        |(player, transform, mut body, mut movement, mut frame, mut state, state_machine)| {
+            if let Some(sampled) = state_machine.sample_frame(&state) {
+                *frame = sampled.clone();
+                for frame_action in frame.actions.iter() {
+                    let cue = action::run_action(
+                        frame_action,
+                        player.id,
+                        transform.translation.xy(),
+                        &mut body,
+                        &mut movement,
+                        &mut state,
+                    );
+                    if let Some(cue) = cue {
+                        cues.send(cue);
+                    }
+                }
+            }
+            state.tick();
+        },
+    );
 }
 
 fn update_camera(
@@ -226,6 +350,133 @@ fn update_camera(
     });
 }
 
+/// Creates the set of systems that advance the match simulation by a single frame.
+///
+/// Every system in the set runs after the "SAMPLE_INPUT" label, which must be provided by
+/// the caller and is responsible for updating each player's `PlayerInput`.
+pub(crate) fn simulation_systems() -> SystemSet {
+    SystemSet::new()
+        .with_system(
+            replay::record_inputs
+                .system()
+                .label("RECORD_INPUTS")
+                .after("SAMPLE_CPU_INPUT")
+                .before("UPDATE_MATCH_STATE"),
+        )
+        .with_system(
+            cpu::sample_cpu_input
+                .system()
+                .label("SAMPLE_CPU_INPUT")
+                .after("SAMPLE_INPUT"),
+        )
+        // Run physics updates
+        .with_system(
+            physics::move_players
+                .system()
+                .label("MOVE_PLAYERS")
+                .after("SAMPLE_CPU_INPUT"),
+        )
+        .with_system(
+            physics::update_bodies
+                .system()
+                .label("UPDATE_BODIES")
+                .after("MOVE_PLAYERS"),
+        )
+        // Update animations
+        .with_system(
+            update_states
+                .system()
+                .label("UPDATE_STATES")
+                .after("UPDATE_BODIES")
+                .before("SAMPLE_FRAMES"),
+        )
+        .with_system(
+            sample_frames
+                .system()
+                .label("SAMPLE_FRAMES")
+                .after("UPDATE_BODIES"),
+        )
+        // Updated hitboxes and players
+        .with_system(
+            hitbox::update_hitboxes
+                .system()
+                .label("UPDATE_HITBOXES")
+                .after("SAMPLE_FRAMES"),
+        )
+        .with_system(
+            hitbox::collide_hitboxes
+                .system()
+                .label("COLLIDE_HITBOXES")
+                .after("UPDATE_HITBOXES"),
+        )
+        .with_system(
+            hitbox::hit_players
+                .system()
+                .label("HIT_PLAYERS")
+                .after("COLLIDE_HITBOXES"),
+        )
+        .with_system(
+            stage::kill_players
+                .system()
+                .label("KILL_PLAYERS")
+                .after("HIT_PLAYERS"),
+        )
+        // Record events for systems outside of the simulation
+        .with_system(
+            events::record_events::<hitbox::HitCollision>
+                .system()
+                .after("COLLIDE_HITBOXES")
+                .before("UPDATE_MATCH_STATE"),
+        )
+        .with_system(
+            events::record_events::<action::FrameCue>
+                .system()
+                .after("SAMPLE_FRAMES")
+                .before("UPDATE_MATCH_STATE"),
+        )
+        .with_system(
+            events::record_events::<events::PlayerDied>
+                .system()
+                .after("KILL_PLAYERS")
+                .before("UPDATE_MATCH_STATE"),
+        )
+        // Evaluate the match state
+        .with_system(
+            rule::update_match_state
+                .system()
+                .label("UPDATE_MATCH_STATE")
+                .after("KILL_PLAYERS"),
+        )
+        .with_system(
+            rule::on_player_died
+                .system()
+                .label("ON_PLAYER_DIED")
+                .after("UPDATE_MATCH_STATE"),
+        )
+        .with_system(
+            disconnect::apply_disconnects
+                .system()
+                .label("APPLY_DISCONNECTS")
+                .after("ON_PLAYER_DIED"),
+        )
+        .with_system(
+            rule::finish_match
+                .system()
+                .label("FINISH_MATCH")
+                .after("APPLY_DISCONNECTS"),
+        )
+}
+
+/// Creates a stage that advances the match simulation by a single frame using the inputs
+/// in the `FrameInputs` resource instead of a backroll session.
+pub(crate) fn frame_input_stage() -> SystemStage {
+    let mut stage = SystemStage::parallel();
+    stage.add_system_set(
+        simulation_systems().with_system(input::inject_frame_inputs.system().label("SAMPLE_INPUT")),
+    );
+    stage
+}
+
 #[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
 struct MatchUpdateStage;
 
@@ -234,85 +485,132 @@ pub struct FcMatchPlugin;
 impl Plugin for FcMatchPlugin {
     fn build(&self, builder: &mut AppBuilder) {
         builder
-            .insert_resource(MatchConfig::default())
-            .insert_resource(MatchResult::default())
+            .init_resource::<MatchConfig>()
+            .init_resource::<MatchResult>()
             .add_plugin(backroll::FcBackrollPlugin)
-            .add_system_set(SystemSet::on_enter(AppState::MATCH).with_system(init_match.system()))
-            .add_system_set(SystemSet::on_exit(AppState::MATCH).with_system(cleanup_match.system()))
             .add_system_set(
-                SystemSet::on_update(AppState::MATCH).with_system(update_camera.system()),
+                SystemSet::on_enter(AppState::MATCH)
+                    .with_system(init_match.system().label("INIT_MATCH")),
             )
+            .add_system_set(SystemSet::on_exit(AppState::MATCH).with_system(cleanup_match.system()))
             .with_rollback_system_set::<backroll::BackrollConfig>(
-                SystemSet::new()
-                    // Update inputs
-                    .with_system(input::inject_input.system().label("SAMPLE_INPUT"))
-                    // Run physics updates
-                    .with_system(
-                        physics::move_players
-                            .system()
-                            .label("MOVE_PLAYERS")
-                            .after("SAMPLE_INPUT"),
-                    )
-                    .with_system(
-                        physics::update_bodies
-                            .system()
-                            .label("UPDATE_BODIES")
-                            .after("MOVE_PLAYERS"),
-                    )
-                    // Update animations
-                    .with_system(
-                        sample_frames
-                            .system()
-                            .label("SAMPLE_FRAMES")
-                            .after("UPDATE_BODIES"),
-                    )
-                    // Updated hitboxes and players
-                    .with_system(
-                        hitbox::update_hitboxes
-                            .system()
-                            .label("UPDATE_HITBOXES")
-                            .after("SAMPLE_FRAMES"),
-                    )
-                    .with_system(
-                        hitbox::collide_hitboxes
-                            .system()
-                            .label("COLLIDE_HITBOXES")
-                            .after("UPDATE_HITBOXES"),
-                    )
-                    .with_system(
-                        hitbox::hit_players
-                            .system()
-                            .label("HIT_PLAYERS")
-                            .after("COLLIDE_HITBOXES"),
-                    )
-                    .with_system(
-                        stage::kill_players
-                            .system()
-                            .label("KILL_PLAYERS")
-                            .after("HIT_PLAYERS"),
-                    )
-                    // Evaluate the match state
-                    .with_system(
-                        rule::update_match_state
-                            .system()
-                            .label("UPDATE_MATCH_STATE")
-                            .after("KILL_PLAYERS"),
-                    )
-                    .with_system(
-                        rule::on_player_died
-                            .system()
-                            .label("ON_PLAYER_DIED")
-                            .after("UPDATE_MATCH_STATE"),
-                    )
-                    .with_system(
-                        rule::finish_match
-                            .system()
-                            .label("FINISH_MATCH")
-                            .after("ON_PLAYER_DIED"),
-                    ),
+                simulation_systems()
+                    .with_system(input::inject_input.system().label("SAMPLE_INPUT")),
             );
         stage::build(builder);
         hitbox::build(builder);
+        action::build(builder);
         events::build(builder);
+        desync::build(builder);
+        disconnect::build(builder);
+        spectator::build(builder);
+    }
+}
+
+/// Presents the match to the local players. Requires a window to render to.
+pub struct FcMatchViewPlugin;
+
+impl Plugin for FcMatchViewPlugin {
+    fn build(&self, builder: &mut AppBuilder) {
+        builder.add_system_set(
+            SystemSet::on_update(AppState::MATCH).with_system(update_camera.system()),
+        );
+        disconnect::build_view(builder);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::character::{frame_data::action::FrameAction, state::State};
+
+    fn state(name: &str, frames: usize, first_resistance: f32) -> State {
+        State {
+            name: name.to_string(),
+            transitions: Vec::new(),
+            frame_data: StateFrameData {
+                hitboxes: Vec::new(),
+                frames: (0..frames)
+                    .map(|frame| CharacterFrame {
+                        damage_resistance: first_resistance + frame as f32,
+                        ..Default::default()
+                    })
+                    .collect(),
+            },
+        }
+    }
+
+    fn spawn_player(world: &mut World, state_machine: StateMachine) -> Entity {
+        world
+            .spawn()
+            .insert(Player { id: 0 })
+            .insert(Transform::default())
+            .insert(physics::Body::default())
+            .insert(PlayerMovement::default())
+            .insert(input::PlayerInput::default())
+            .insert(CharacterFrame::default())
+            .insert(PlayerState::default())
+            .insert(state_machine)
+            .id()
+    }
+
+    fn state_stage() -> SystemStage {
+        SystemStage::single_threaded()
+            .with_system(update_states.system().label("UPDATE_STATES"))
+            .with_system(sample_frames.system().after("UPDATE_STATES"))
+    }
+
+    #[test]
+    pub fn test_states_sample_every_frame_before_ending() {
+        let mut machine = StateMachine::default();
+        let attack = machine.add_state(state("attack", 3, 0.0));
+        let idle = machine.add_state(state("idle", 2, 10.0));
+        machine
+            .create_transition(attack, idle)
+            .unwrap()
+            .transition
+            .push(TransitionCondition::StateEnd);
+
+        let mut world = World::default();
+        world.insert_resource(Events::<action::FrameCue>::default());
+        let player = spawn_player(&mut world, machine);
+        let mut stage = state_stage();
+
+        let mut sampled = Vec::new();
+        for _ in 0..5 {
+            stage.run(&mut world);
+            let frame = world.get::<CharacterFrame>(player).unwrap();
+            sampled.push(frame.damage_resistance);
+        }
+        assert_eq!(sampled, vec![0.0, 1.0, 2.0, 10.0, 11.0]);
+        assert_eq!(world.get::<PlayerState>(player).unwrap().state_id, idle);
+    }
+
+    #[test]
+    pub fn test_first_frame_actions_run() {
+        let mut jump = state("jump", 2, 0.0);
+        jump.frame_data.frames[0].actions = vec![
+            FrameAction::SetVelocity(Vec2::new(0.0, 2.0)),
+            FrameAction::PlaySound("jump".to_string()),
+        ];
+        let mut machine = StateMachine::default();
+        machine.add_state(jump);
+
+        let mut world = World::default();
+        world.insert_resource(Events::<action::FrameCue>::default());
+        let player = spawn_player(&mut world, machine);
+        state_stage().run(&mut world);
+
+        let body = world.get::<physics::Body>(player).unwrap();
+        assert_eq!(body.velocity, Vec2::new(0.0, 2.0));
+        let events = world.get_resource::<Events<action::FrameCue>>().unwrap();
+        let cues: Vec<_> = events.get_reader().iter(events).cloned().collect();
+        assert_eq!(
+            cues,
+            vec![action::FrameCue::Sound {
+                player: 0,
+                sound: "jump".to_string(),
+            }]
+        );
     }
 }
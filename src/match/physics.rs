@@ -17,7 +17,7 @@ bitflags! {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Location {
     Airborne(Vec2),
     Respawning {
@@ -91,7 +91,7 @@ impl Facing {
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Body {
     pub weight: f32,
     pub facing: Facing,
@@ -201,7 +201,7 @@ impl Body {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct EnvironmentCollisionBox(pub Bounds2D);
 
 impl EnvironmentCollisionBox {
@@ -3,7 +3,7 @@ use super::{
     input::{InputSource, PlayerInput},
     physics,
 };
-use crate::character::{frame_data::*, state::*};
+use crate::character::{frame_data::*, state::*, CharacterMovement};
 use bevy::prelude::*;
 use bevy_backroll::backroll;
 use serde::{Deserialize, Serialize};
@@ -12,7 +12,7 @@ const PLAYER_COLORS: &[Color] = &[Color::RED, Color::BLUE, Color::YELLOW, Color:
 
 pub type PlayerId = u8;
 
-#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Player {
     pub id: PlayerId,
 }
@@ -28,13 +28,16 @@ pub struct PlayerConfig {
     pub pallete: u8,
     /// The default damage the player starts with upon respawning.
     pub default_damage: f32,
-    /// The default damage the player starts with upon respawning.
+    /// Where the player's inputs are sampled from. Local controls are not shared with
+    /// peers or saved to replays.
     #[serde(skip)]
     pub input: InputSource,
 }
 
-#[derive(Default, Debug, Clone)]
+#[derive(Default, Debug, Clone, PartialEq)]
 pub(super) struct PlayerMovement {
+    /// The character's gravity, before any multipliers from frame actions.
+    pub gravity: f32,
     pub jump_count: usize,
     pub jump_power: Vec<f32>,
     pub short_jump_power: f32,
@@ -43,6 +46,19 @@ pub(super) struct PlayerMovement {
     pub max_fall_speed: f32,
 }
 
+impl From<&CharacterMovement> for PlayerMovement {
+    fn from(movement: &CharacterMovement) -> Self {
+        Self {
+            gravity: movement.gravity,
+            jump_power: movement.jump_power.clone(),
+            short_jump_power: movement.short_jump_power,
+            fast_fall_speed: movement.fast_fall_speed,
+            max_fall_speed: movement.max_fall_speed,
+            ..Default::default()
+        }
+    }
+}
+
 impl PlayerMovement {
     pub fn next_jump_power(&mut self) -> Option<f32> {
         let power = self.jump_power.get(self.jump_count);
@@ -65,7 +81,7 @@ impl PlayerMovement {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub(super) enum PlayerDamage {
     Score {
         score: i16,
@@ -0,0 +1,466 @@
+use super::{
+    frame_input_stage,
+    input::{FrameInputs, PlayerInput, PlayerInputFrame},
+    MatchConfig, MatchState, MAX_PLAYERS_PER_MATCH,
+};
+use crate::{assets::FcMetadata, player::Player, time::DELTA_TIME};
+use anyhow::{bail, Context, Result};
+use bevy::{app::AppExit, asset::Asset, prelude::*};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+const REPLAY_MAGIC: &[u8; 4] = b"FCRP";
+const REPLAY_VERSION: u16 = 1;
+
+/// The number of frames simulated per tick while fast-forwarding.
+const FAST_FORWARD_SPEED: usize = 4;
+
+const PAUSE_KEY: KeyCode = KeyCode::Space;
+const STEP_KEY: KeyCode = KeyCode::Period;
+const FAST_FORWARD_KEY: KeyCode = KeyCode::Tab;
+
+/// Everything needed to reproduce a match besides its inputs.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    /// The version of the game the replay was recorded with.
+    pub game_version: String,
+    /// The paths of every character asset loaded when the match was recorded.
+    pub characters: Vec<String>,
+    /// The paths of every stage asset loaded when the match was recorded.
+    pub stages: Vec<String>,
+    pub config: MatchConfig,
+    pub frames: u32,
+}
+
+/// A recording of a match: its configuration and the confirmed inputs of each player.
+///
+/// Replays are stored as a JSON header followed by a run-length encoded stream of inputs
+/// for each player, as inputs rarely change from one frame to the next.
+pub struct Replay {
+    pub header: ReplayHeader,
+    /// The inputs for every frame of the match, indexed by frame.
+    pub inputs: Vec<[PlayerInputFrame; MAX_PLAYERS_PER_MATCH]>,
+}
+
+impl Replay {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("failed to open replay {}", path.display()))?;
+        Self::read(std::io::BufReader::new(file))
+            .with_context(|| format!("failed to read replay {}", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("failed to create replay {}", path.display()))?;
+        let mut writer = std::io::BufWriter::new(file);
+        self.write(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write(&self, mut writer: impl Write) -> Result<()> {
+        let header = serde_json::to_vec(&self.header)?;
+        writer.write_all(REPLAY_MAGIC)?;
+        writer.write_all(&REPLAY_VERSION.to_le_bytes())?;
+        writer.write_all(&(header.len() as u32).to_le_bytes())?;
+        writer.write_all(&header)?;
+
+        for (slot, player) in self.header.config.players.iter().enumerate() {
+            if player.is_none() {
+                continue;
+            }
+            let mut runs: Vec<(u16, PlayerInputFrame)> = Vec::new();
+            for frame in self.inputs.iter().map(|inputs| inputs[slot]) {
+                match runs.last_mut() {
+                    Some((count, input)) if *input == frame && *count < u16::MAX => *count += 1,
+                    _ => runs.push((1, frame)),
+                }
+            }
+            writer.write_all(&(runs.len() as u32).to_le_bytes())?;
+            for (count, input) in runs {
+                writer.write_all(&count.to_le_bytes())?;
+                writer.write_all(bytemuck::bytes_of(&input))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read(mut reader: impl Read) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != REPLAY_MAGIC {
+            bail!("not a replay file");
+        }
+        let version = u16::from_le_bytes(read_array(&mut reader)?);
+        if version != REPLAY_VERSION {
+            bail!("unsupported replay version: {}", version);
+        }
+        // Lengths read from the file are not trusted for allocations: buffers only grow as
+        // data is actually read.
+        let header_len = u32::from_le_bytes(read_array(&mut reader)?);
+        let mut header = Vec::new();
+        (&mut reader)
+            .take(header_len as u64)
+            .read_to_end(&mut header)?;
+        if header.len() != header_len as usize {
+            bail!("replay header is truncated");
+        }
+        let header: ReplayHeader = serde_json::from_slice(&header)?;
+
+        let frames = header.frames as usize;
+        let mut inputs: Vec<[PlayerInputFrame; MAX_PLAYERS_PER_MATCH]> = Vec::new();
+        for (slot, player) in header.config.players.iter().enumerate() {
+            if player.is_none() {
+                continue;
+            }
+            let runs = u32::from_le_bytes(read_array(&mut reader)?);
+            let mut frame = 0;
+            for _ in 0..runs {
+                let count = u16::from_le_bytes(read_array(&mut reader)?) as usize;
+                let mut input = PlayerInputFrame::default();
+                reader.read_exact(bytemuck::bytes_of_mut(&mut input))?;
+                if frame + count > frames {
+                    bail!("player {} has more inputs than the replay has frames", slot);
+                }
+                if inputs.len() < frame + count {
+                    inputs.resize(frame + count, Default::default());
+                }
+                for inputs in inputs[frame..frame + count].iter_mut() {
+                    inputs[slot] = input;
+                }
+                frame += count;
+            }
+            if frame != frames {
+                bail!("player {} is missing inputs", slot);
+            }
+        }
+        if inputs.len() != frames {
+            bail!("replay has no inputs for its {} frames", frames);
+        }
+        Ok(Self { header, inputs })
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn asset_paths<T: Asset>(handles: &[Handle<T>], asset_server: &AssetServer) -> Vec<String> {
+    let mut paths: Vec<String> = handles
+        .iter()
+        .filter_map(|handle| asset_server.get_handle_path(handle))
+        .map(|path| path.path().to_string_lossy().into_owned())
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Records the inputs of every frame of the match, and saves them as a replay when the
+/// game exits.
+pub struct ReplayRecorder {
+    path: PathBuf,
+    inputs: Vec<[PlayerInputFrame; MAX_PLAYERS_PER_MATCH]>,
+}
+
+impl ReplayRecorder {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            inputs: Vec::new(),
+        }
+    }
+}
+
+/// Records the inputs applied on the current frame.
+///
+/// Runs as part of the simulation, so re-simulated frames overwrite any mispredicted
+/// inputs that were recorded before a rollback.
+pub(super) fn record_inputs(
+    recorder: Option<ResMut<ReplayRecorder>>,
+    state: Res<MatchState>,
+    players: Query<(&Player, &PlayerInput)>,
+) {
+    let mut recorder = match recorder {
+        Some(recorder) => recorder,
+        None => return,
+    };
+    let mut inputs: [PlayerInputFrame; MAX_PLAYERS_PER_MATCH] = Default::default();
+    for (player, input) in players.iter() {
+        inputs[player.id as usize] = input.current;
+    }
+    let frame = state.frame as usize;
+    recorder.inputs.truncate(frame);
+    if recorder.inputs.len() != frame {
+        error!("Replay is missing inputs before frame {}", frame);
+        return;
+    }
+    recorder.inputs.push(inputs);
+}
+
+fn reset_recorder(recorder: Option<ResMut<ReplayRecorder>>) {
+    if let Some(mut recorder) = recorder {
+        recorder.inputs.clear();
+    }
+}
+
+fn save_replay(
+    mut exits: EventReader<AppExit>,
+    recorder: Option<Res<ReplayRecorder>>,
+    config: Res<MatchConfig>,
+    metadata: Option<Res<FcMetadata>>,
+    asset_server: Res<AssetServer>,
+) {
+    let recorder = match recorder {
+        Some(recorder) => recorder,
+        None => return,
+    };
+    if exits.iter().count() == 0 || recorder.inputs.is_empty() {
+        return;
+    }
+    let (characters, stages) = match metadata {
+        Some(metadata) => (
+            asset_paths(&metadata.characters, &asset_server),
+            asset_paths(&metadata.stages, &asset_server),
+        ),
+        None => Default::default(),
+    };
+    let replay = Replay {
+        header: ReplayHeader {
+            game_version: env!("CARGO_PKG_VERSION").to_string(),
+            characters,
+            stages,
+            config: config.clone(),
+            frames: recorder.inputs.len() as u32,
+        },
+        inputs: recorder.inputs.clone(),
+    };
+    match replay.save(&recorder.path) {
+        Ok(()) => info!("Saved replay to {}", recorder.path.display()),
+        Err(err) => error!("Failed to save replay: {:#}", err),
+    }
+}
+
+/// Records the match to a replay file at the given path.
+pub struct FcReplayRecordPlugin {
+    pub path: PathBuf,
+}
+
+impl Plugin for FcReplayRecordPlugin {
+    fn build(&self, builder: &mut AppBuilder) {
+        builder
+            .insert_resource(ReplayRecorder::new(self.path.clone()))
+            .add_system_set(
+                SystemSet::on_enter(crate::AppState::MATCH).with_system(reset_recorder.system()),
+            )
+            .add_system_to_stage(CoreStage::Last, save_replay.system());
+    }
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+struct ReplayStageLabel;
+
+/// Plays back a replay's inputs, with support for pausing, stepping and fast-forwarding.
+struct ReplayStage {
+    inputs: Vec<[PlayerInputFrame; MAX_PLAYERS_PER_MATCH]>,
+    simulation: SystemStage,
+    accumulator: f32,
+    paused: bool,
+}
+
+impl ReplayStage {
+    fn advance(&mut self, world: &mut World) {
+        let frame = world.get_resource::<MatchState>().unwrap().frame as usize;
+        match self.inputs.get(frame) {
+            Some(inputs) => {
+                world.insert_resource(FrameInputs::new(*inputs));
+                self.simulation.run(world);
+            }
+            None => {
+                if !self.paused {
+                    info!("Replay finished after {} frames", frame);
+                }
+                self.paused = true;
+            }
+        }
+    }
+}
+
+impl Stage for ReplayStage {
+    fn run(&mut self, world: &mut World) {
+        // Only run while there is a match in progress.
+        if world.get_resource::<MatchState>().is_none() {
+            self.accumulator = 0.0;
+            return;
+        }
+
+        let keyboard = world.get_resource::<Input<KeyCode>>().unwrap();
+        let fast_forward = keyboard.pressed(FAST_FORWARD_KEY);
+        let step = keyboard.just_pressed(STEP_KEY);
+        if keyboard.just_pressed(PAUSE_KEY) {
+            self.paused = !self.paused;
+        }
+
+        if self.paused {
+            self.accumulator = 0.0;
+            if step {
+                self.advance(world);
+            }
+            return;
+        }
+
+        self.accumulator += world
+            .get_resource::<Time>()
+            .map(|time| time.delta_seconds())
+            .unwrap_or(DELTA_TIME);
+        while self.accumulator >= DELTA_TIME && !self.paused {
+            self.accumulator -= DELTA_TIME;
+            let speed = if fast_forward { FAST_FORWARD_SPEED } else { 1 };
+            for _ in 0..speed {
+                self.advance(world);
+            }
+        }
+    }
+}
+
+fn check_replay_assets(
+    header: Res<ReplayHeader>,
+    metadata: Res<FcMetadata>,
+    asset_server: Res<AssetServer>,
+) {
+    if header.game_version != env!("CARGO_PKG_VERSION") {
+        warn!(
+            "Replay was recorded with version {}, playback may desync.",
+            header.game_version
+        );
+    }
+    if header.characters != asset_paths(&metadata.characters, &asset_server)
+        || header.stages != asset_paths(&metadata.stages, &asset_server)
+    {
+        warn!("Replay was recorded with different assets, playback may desync.");
+    }
+}
+
+/// Plays back a replay instead of taking part in a backroll session.
+pub struct FcReplayPlaybackPlugin {
+    pub header: ReplayHeader,
+    pub inputs: Vec<[PlayerInputFrame; MAX_PLAYERS_PER_MATCH]>,
+}
+
+impl FcReplayPlaybackPlugin {
+    pub fn new(replay: Replay) -> Self {
+        Self {
+            header: replay.header,
+            inputs: replay.inputs,
+        }
+    }
+}
+
+impl Plugin for FcReplayPlaybackPlugin {
+    fn build(&self, builder: &mut AppBuilder) {
+        builder
+            .insert_resource(self.header.config.clone())
+            .insert_resource(self.header.clone())
+            .insert_resource(FrameInputs::default())
+            .add_system_set(
+                SystemSet::on_enter(crate::AppState::MATCH)
+                    .with_system(check_replay_assets.system()),
+            )
+            .add_stage_after(
+                CoreStage::Update,
+                ReplayStageLabel,
+                ReplayStage {
+                    inputs: self.inputs.clone(),
+                    simulation: frame_input_stage(),
+                    accumulator: 0.0,
+                    paused: false,
+                },
+            );
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::r#match::{input::Buttons, player::PlayerConfig};
+    use bevy_backroll::backroll;
+
+    fn player_config() -> Option<PlayerConfig> {
+        Some(PlayerConfig {
+            player: backroll::Player::Local,
+            character_id: 0,
+            pallete: 0,
+            default_damage: 0.0,
+            input: Default::default(),
+        })
+    }
+
+    #[test]
+    pub fn test_replay_round_trip() {
+        let mut config = MatchConfig::default();
+        config.players[0] = player_config();
+        config.players[2] = player_config();
+
+        let mut inputs = vec![<[PlayerInputFrame; MAX_PLAYERS_PER_MATCH]>::default(); 300];
+        for (frame, inputs) in inputs.iter_mut().enumerate() {
+            inputs[0].movement = Vec2::new((frame % 7) as f32 / 7.0, 0.0).into();
+            if frame > 100 {
+                inputs[2].buttons = Buttons::JUMP;
+            }
+        }
+        let replay = Replay {
+            header: ReplayHeader {
+                game_version: "0.1.0".to_string(),
+                characters: vec!["characters/test.chr".to_string()],
+                stages: Vec::new(),
+                config,
+                frames: inputs.len() as u32,
+            },
+            inputs,
+        };
+
+        let mut bytes = Vec::new();
+        replay.write(&mut bytes).unwrap();
+        let read = Replay::read(bytes.as_slice()).unwrap();
+        assert_eq!(read.header.frames, 300);
+        assert_eq!(read.header.characters, replay.header.characters);
+        assert_eq!(read.inputs, replay.inputs);
+    }
+
+    #[test]
+    pub fn test_replay_rejects_truncated_files() {
+        let mut config = MatchConfig::default();
+        config.players[0] = player_config();
+        let replay = Replay {
+            header: ReplayHeader {
+                game_version: "0.1.0".to_string(),
+                characters: Vec::new(),
+                stages: Vec::new(),
+                config,
+                frames: 1,
+            },
+            inputs: vec![Default::default()],
+        };
+        let mut bytes = Vec::new();
+        replay.write(&mut bytes).unwrap();
+        bytes.pop();
+        assert!(Replay::read(bytes.as_slice()).is_err());
+        assert!(Replay::read(&b"not a replay"[..]).is_err());
+    }
+
+    #[test]
+    pub fn test_replay_rejects_oversized_header() {
+        let mut bytes = REPLAY_MAGIC.to_vec();
+        bytes.extend_from_slice(&REPLAY_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(b"{}");
+        assert!(Replay::read(bytes.as_slice()).is_err());
+    }
+}
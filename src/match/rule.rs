@@ -7,7 +7,7 @@ use crate::player::Player;
 use bevy::{app::AppExit, prelude::*};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum MatchWinner {
     /// No winner has been decided yet.
     Undecided,
@@ -120,6 +120,7 @@ pub(super) fn update_match_state(
     mut results: ResMut<MatchResult>,
     players: Query<(&Player, &PlayerDamage)>,
 ) {
+    state.frame += 1;
     if let Some(ref mut time) = state.time_remaining {
         if *time == 0 {
             results.winner = config.rule.find_winner(players.iter(), /*force=*/ true);
@@ -135,12 +136,7 @@ pub(super) fn on_player_died(
     mut results: ResMut<MatchResult>,
     players: Query<(&Player, &PlayerDamage)>,
 ) {
-    let mut count = 0_u32;
-    for event in events.iter() {
-        info!("Player {} died: {:?}", event.player.id, event.damage);
-        count += 1;
-    }
-    if count != 0 {
+    if events.iter().count() != 0 {
         results.winner = config.rule.find_winner(players.iter(), /*force=*/ false);
     }
 }
@@ -0,0 +1,438 @@
+use super::{
+    backroll::{P2PSession, SessionConfig},
+    desync::StateHistory,
+    disconnect::DisconnectPolicy,
+    frame_input_stage,
+    input::FrameInputs,
+    MatchConfig, MatchState,
+};
+use crate::{time::DELTA_TIME, AppState};
+use backroll_transport_udp::UdpManager;
+use bevy::prelude::*;
+use bevy_backroll::backroll::transport::Peer;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The maximum number of frames of inputs sent in a single message.
+const FRAMES_PER_MESSAGE: usize = 32;
+
+/// The maximum number of input messages sent to a spectator per update. Anything beyond
+/// this will be sent once the spectator acknowledges the earlier frames.
+const MESSAGES_PER_UPDATE: usize = 4;
+
+#[derive(Serialize, Deserialize)]
+enum SpectatorMessage {
+    /// Sent by a spectator until it has received the match's configuration.
+    Join,
+    /// Sent by a spectator to acknowledge every frame before `next_frame`.
+    Ack { next_frame: u32 },
+    /// Sent by the host in response to a join request. Spectators need the host's
+    /// disconnect policy to resolve disconnects the same way the players do.
+    Config {
+        config: MatchConfig,
+        disconnect_policy: DisconnectPolicy,
+    },
+    /// Confirmed inputs for a run of consecutive frames starting at `start`, including
+    /// which players had disconnected by each frame.
+    Inputs {
+        start: u32,
+        inputs: Vec<FrameInputs>,
+    },
+}
+
+fn send(peer: &Peer, message: &SpectatorMessage) {
+    match serde_json::to_vec(message) {
+        Ok(bytes) => {
+            if peer.send(bytes.into_boxed_slice()).is_err() {
+                warn!("Failed to send spectator message");
+            }
+        }
+        Err(err) => error!("Failed to serialize spectator message: {}", err),
+    }
+}
+
+fn receive(peer: &Peer) -> Vec<SpectatorMessage> {
+    let mut messages = Vec::new();
+    while let Ok(message) = peer.try_recv() {
+        match serde_json::from_slice(&message) {
+            Ok(message) => messages.push(message),
+            Err(err) => warn!("Received malformed spectator message: {}", err),
+        }
+    }
+    messages
+}
+
+struct SpectatorConnection {
+    peer: Peer,
+    /// The first frame the spectator has not acknowledged. `None` if the spectator has not
+    /// joined yet.
+    next_frame: Option<u32>,
+}
+
+/// Streams the confirmed inputs of a match to spectators.
+///
+/// Spectators can join at any point during the match: every confirmed input is kept for
+/// the duration of the match and sent from the start, so they can catch up.
+pub struct SpectatorHost {
+    // Kept alive for as long as the peers are in use.
+    _socket: UdpManager,
+    spectators: Vec<SpectatorConnection>,
+    /// The confirmed inputs for every frame of the match, indexed by frame.
+    inputs: Vec<FrameInputs>,
+}
+
+impl SpectatorHost {
+    pub fn new(socket: UdpManager, peers: Vec<Peer>) -> Self {
+        Self {
+            _socket: socket,
+            spectators: peers
+                .into_iter()
+                .map(|peer| SpectatorConnection {
+                    peer,
+                    next_frame: None,
+                })
+                .collect(),
+            inputs: Vec::new(),
+        }
+    }
+
+    fn record_confirmed_inputs(&mut self, history: &StateHistory, session: Option<&P2PSession>) {
+        let confirmed = match history.confirmed_frame(session) {
+            Some(confirmed) => confirmed,
+            None => return,
+        };
+        // The inputs for a frame are captured by the state saved on the following frame.
+        while (self.inputs.len() as u32) < confirmed {
+            match history.get(self.inputs.len() as u32 + 1) {
+                Some(state) => self.inputs.push(state.inputs()),
+                None => {
+                    error!(
+                        "Missing confirmed state for frame {}, spectators will stall.",
+                        self.inputs.len() + 1
+                    );
+                    return;
+                }
+            }
+        }
+    }
+
+    fn update_spectator(
+        spectator: &mut SpectatorConnection,
+        config: &MatchConfig,
+        session: &SessionConfig,
+        inputs: &[FrameInputs],
+    ) {
+        for message in receive(&spectator.peer) {
+            match message {
+                SpectatorMessage::Join => {
+                    let message = SpectatorMessage::Config {
+                        config: config.clone(),
+                        disconnect_policy: session.disconnect_policy.clone(),
+                    };
+                    send(&spectator.peer, &message);
+                    spectator.next_frame.get_or_insert(0);
+                }
+                SpectatorMessage::Ack { next_frame } => {
+                    let acked = spectator.next_frame.get_or_insert(0);
+                    *acked = (*acked).max(next_frame);
+                }
+                _ => warn!("Received unexpected message from spectator"),
+            }
+        }
+
+        // Resend everything past the last acknowledged frame, as messages may be dropped.
+        let start = match spectator.next_frame {
+            Some(start) => start as usize,
+            None => return,
+        };
+        let pending = inputs.get(start..).unwrap_or(&[]);
+        for (idx, chunk) in pending
+            .chunks(FRAMES_PER_MESSAGE)
+            .take(MESSAGES_PER_UPDATE)
+            .enumerate()
+        {
+            send(
+                &spectator.peer,
+                &SpectatorMessage::Inputs {
+                    start: (start + idx * FRAMES_PER_MESSAGE) as u32,
+                    inputs: chunk.to_vec(),
+                },
+            );
+        }
+    }
+}
+
+/// Reassembles runs of inputs received in any order into consecutive frames.
+#[derive(Default)]
+struct InputBuffer {
+    /// The inputs of every frame up to the first one that has not been received, indexed
+    /// by frame.
+    inputs: Vec<FrameInputs>,
+    /// Runs of inputs received ahead of a missing frame, keyed by their first frame.
+    pending: BTreeMap<usize, Vec<FrameInputs>>,
+}
+
+impl InputBuffer {
+    fn insert(&mut self, start: usize, inputs: Vec<FrameInputs>) {
+        if start > self.inputs.len() {
+            self.pending.insert(start, inputs);
+            return;
+        }
+        let received = self.inputs.len();
+        self.inputs
+            .extend(inputs.into_iter().skip(received - start));
+        // Append any pending runs that are now contiguous with the received inputs.
+        while let Some((&start, _)) = self.pending.iter().next() {
+            if start > self.inputs.len() {
+                break;
+            }
+            let inputs = self.pending.remove(&start).unwrap();
+            let received = self.inputs.len();
+            self.inputs
+                .extend(inputs.into_iter().skip(received - start));
+        }
+    }
+
+    /// Gets the number of frames that have been received but not played back yet.
+    fn buffered_frames(&self, frame: usize) -> usize {
+        self.inputs.len().saturating_sub(frame)
+    }
+}
+
+/// Receives the confirmed inputs of a match from a host and plays them back.
+pub struct SpectatorClient {
+    // Kept alive for as long as the peer is in use.
+    _socket: UdpManager,
+    host: Peer,
+    configured: bool,
+    inputs: InputBuffer,
+}
+
+impl SpectatorClient {
+    pub fn new(socket: UdpManager, host: Peer) -> Self {
+        Self {
+            _socket: socket,
+            host,
+            configured: false,
+            inputs: InputBuffer::default(),
+        }
+    }
+
+    /// Gets the number of frames that have been received but not played back yet.
+    pub fn buffered_frames(&self, frame: u32) -> usize {
+        self.inputs.buffered_frames(frame as usize)
+    }
+}
+
+fn reset_spectator_host(host: Option<ResMut<SpectatorHost>>) {
+    if let Some(mut host) = host {
+        host.inputs.clear();
+        for spectator in host.spectators.iter_mut() {
+            spectator.next_frame = None;
+        }
+    }
+}
+
+fn update_spectator_host(
+    host: Option<ResMut<SpectatorHost>>,
+    history: Res<StateHistory>,
+    session: Option<Res<P2PSession>>,
+    session_config: Res<SessionConfig>,
+    config: Res<MatchConfig>,
+) {
+    let host = match host {
+        Some(host) => host,
+        None => return,
+    };
+    let host = host.into_inner();
+    host.record_confirmed_inputs(&history, session.as_deref());
+    for spectator in host.spectators.iter_mut() {
+        SpectatorHost::update_spectator(spectator, &config, &session_config, &host.inputs);
+    }
+}
+
+fn update_spectator_client(
+    client: Option<ResMut<SpectatorClient>>,
+    mut config: ResMut<MatchConfig>,
+    mut session: ResMut<SessionConfig>,
+) {
+    let mut client = match client {
+        Some(client) => client,
+        None => return,
+    };
+    for message in receive(&client.host) {
+        match message {
+            SpectatorMessage::Config {
+                config: received,
+                disconnect_policy,
+            } => {
+                if !client.configured {
+                    // Spectators never provide inputs to the match.
+                    *config = received;
+                    session.disconnect_policy = disconnect_policy;
+                    client.configured = true;
+                }
+            }
+            SpectatorMessage::Inputs { start, inputs } => {
+                client.inputs.insert(start as usize, inputs);
+            }
+            _ => warn!("Received unexpected message from spectated host"),
+        }
+    }
+
+    let message = if client.configured {
+        SpectatorMessage::Ack {
+            next_frame: client.inputs.inputs.len() as u32,
+        }
+    } else {
+        SpectatorMessage::Join
+    };
+    send(&client.host, &message);
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, StageLabel)]
+struct SpectatorStageLabel;
+
+/// Plays back received inputs, staying `delay` frames behind the latest received frame.
+struct SpectatorStage {
+    delay: usize,
+    simulation: SystemStage,
+    accumulator: f32,
+}
+
+/// Gets the number of frames to play back on a tick given the number of frames buffered.
+///
+/// Advances a frame once the buffer is full, and catches up if it has grown past twice
+/// the delay (i.e. after joining a match in progress).
+fn frames_to_play(buffered: usize, delay: usize) -> usize {
+    if buffered > 2 * delay {
+        buffered - delay
+    } else if buffered > delay {
+        1
+    } else {
+        0
+    }
+}
+
+impl SpectatorStage {
+    fn tick(&mut self, world: &mut World) {
+        let frame = match world.get_resource::<MatchState>() {
+            Some(state) => state.frame as usize,
+            None => return,
+        };
+        let client = match world.get_resource::<SpectatorClient>() {
+            Some(client) => client,
+            None => {
+                error!("Spectating without a SpectatorClient, no inputs will be played");
+                return;
+            }
+        };
+        let frames = frames_to_play(client.buffered_frames(frame as u32), self.delay);
+        let inputs = client.inputs.inputs[frame..frame + frames].to_vec();
+        for inputs in inputs {
+            world.insert_resource(inputs);
+            self.simulation.run(world);
+        }
+    }
+}
+
+impl Stage for SpectatorStage {
+    fn run(&mut self, world: &mut World) {
+        // Only run while there is a match in progress.
+        if world.get_resource::<MatchState>().is_none() {
+            self.accumulator = 0.0;
+            return;
+        }
+
+        self.accumulator += world
+            .get_resource::<Time>()
+            .map(|time| time.delta_seconds())
+            .unwrap_or(DELTA_TIME);
+        while self.accumulator >= DELTA_TIME {
+            self.accumulator -= DELTA_TIME;
+            self.tick(world);
+        }
+    }
+}
+
+/// Spectates a match hosted elsewhere instead of taking part in a backroll session.
+///
+/// Requires a `SpectatorClient` resource to be inserted before the match starts.
+pub struct FcSpectatorPlugin {
+    /// The number of received frames buffered before they are played back.
+    pub delay: usize,
+}
+
+impl Plugin for FcSpectatorPlugin {
+    fn build(&self, builder: &mut AppBuilder) {
+        builder
+            .insert_resource(FrameInputs::default())
+            .add_system(update_spectator_client.system())
+            .add_stage_after(
+                CoreStage::Update,
+                SpectatorStageLabel,
+                SpectatorStage {
+                    delay: self.delay,
+                    simulation: frame_input_stage(),
+                    accumulator: 0.0,
+                },
+            );
+    }
+}
+
+pub(super) fn build(builder: &mut AppBuilder) {
+    builder
+        .add_system_set(
+            SystemSet::on_enter(AppState::MATCH).with_system(reset_spectator_host.system()),
+        )
+        .add_system_set(
+            SystemSet::on_update(AppState::MATCH).with_system(update_spectator_host.system()),
+        );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::r#match::input::{Buttons, PlayerInputFrame};
+
+    fn run(start: u8, frames: u8) -> Vec<FrameInputs> {
+        (start..start + frames)
+            .map(|frame| {
+                let mut inputs = FrameInputs::default();
+                inputs.inputs[0] = PlayerInputFrame {
+                    buttons: Buttons::from_bits_truncate(frame),
+                    ..Default::default()
+                };
+                inputs.disconnected[1] = frame >= 4;
+                inputs
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn test_frames_to_play_waits_for_the_delay() {
+        assert_eq!(frames_to_play(0, 3), 0);
+        assert_eq!(frames_to_play(3, 3), 0);
+        assert_eq!(frames_to_play(4, 3), 1);
+        assert_eq!(frames_to_play(6, 3), 1);
+        // Catches up to the delay after falling far behind.
+        assert_eq!(frames_to_play(10, 3), 7);
+        assert_eq!(frames_to_play(1, 0), 1);
+    }
+
+    #[test]
+    pub fn test_input_buffer_reassembles_out_of_order_runs() {
+        let mut buffer = InputBuffer::default();
+        buffer.insert(6, run(6, 2));
+        buffer.insert(3, run(3, 2));
+        assert_eq!(buffer.buffered_frames(0), 0);
+
+        buffer.insert(0, run(0, 4));
+        assert_eq!(buffer.inputs, run(0, 5));
+        // Runs overlapping frames that were already received are trimmed.
+        buffer.insert(2, run(2, 4));
+        assert_eq!(buffer.inputs, run(0, 8));
+        assert!(buffer.pending.is_empty());
+        assert_eq!(buffer.buffered_frames(5), 3);
+    }
+}
@@ -25,6 +25,14 @@ pub struct SpawnPoint {
     pub facing: Facing,
 }
 
+/// Identifies a surface or respawn point by the order it was added to the stage, among
+/// others of its kind.
+///
+/// Entity IDs are allocated per process, so state compared between peers refers to stage
+/// geometry by its index instead.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StageIndex(pub u16);
+
 #[derive(Debug, Clone)]
 pub struct RespawnPoint {
     pub position: Vec2,
@@ -227,56 +235,52 @@ fn setup_stage(mut commands: Commands) {
         facing: Facing::Right,
     });
 
-    commands
-        .spawn()
-        .insert(Surface::floor(Vec2::new(-10.0, 0.0), Vec2::new(0.0, -1.0)));
-    commands
-        .spawn()
-        .insert(Surface::floor(Vec2::new(0.0, -1.0), Vec2::new(10.0, 0.0)));
-    commands
-        .spawn()
-        .insert(Surface::floor(Vec2::new(-7.0, 3.0), Vec2::new(-3.0, 3.0)));
-    commands
-        .spawn()
-        .insert(Surface::floor(Vec2::new(3.0, 3.0), Vec2::new(7.0, 3.0)));
-    commands
-        .spawn()
-        .insert(Surface::floor(Vec2::new(-1.5, 6.0), Vec2::new(1.5, 6.0)));
+    let surfaces = vec![
+        Surface::floor(Vec2::new(-10.0, 0.0), Vec2::new(0.0, -1.0)),
+        Surface::floor(Vec2::new(0.0, -1.0), Vec2::new(10.0, 0.0)),
+        Surface::floor(Vec2::new(-7.0, 3.0), Vec2::new(-3.0, 3.0)),
+        Surface::floor(Vec2::new(3.0, 3.0), Vec2::new(7.0, 3.0)),
+        Surface::floor(Vec2::new(-1.5, 6.0), Vec2::new(1.5, 6.0)),
+    ];
+    for (index, surface) in surfaces.into_iter().enumerate() {
+        commands
+            .spawn()
+            .insert(surface)
+            .insert(StageIndex(index as u16));
+    }
 
     // Add respawn points.
-    commands.spawn().insert(RespawnPoint {
-        position: Vec2::new(-6.0, 4.0),
-        facing: Facing::Right,
-        occupied_by: None,
-    });
-    commands.spawn().insert(RespawnPoint {
-        position: Vec2::new(-2.0, 4.0),
-        facing: Facing::Right,
-        occupied_by: None,
-    });
-    commands.spawn().insert(RespawnPoint {
-        position: Vec2::new(2.0, 4.0),
-        facing: Facing::Right,
-        occupied_by: None,
-    });
-    commands.spawn().insert(RespawnPoint {
-        position: Vec2::new(6.0, 4.0),
-        facing: Facing::Right,
-        occupied_by: None,
-    });
+    let respawn_points = vec![
+        Vec2::new(-6.0, 4.0),
+        Vec2::new(-2.0, 4.0),
+        Vec2::new(2.0, 4.0),
+        Vec2::new(6.0, 4.0),
+    ];
+    for (index, position) in respawn_points.into_iter().enumerate() {
+        commands
+            .spawn()
+            .insert(RespawnPoint {
+                position,
+                facing: Facing::Right,
+                occupied_by: None,
+            })
+            .insert(StageIndex(index as u16));
+    }
 }
 
 // TODO(james7132): This is fucknormous, simplify or split this system.
 pub(super) fn kill_players(
     blast_zones: Query<&BlastZone>,
-    mut respawn_points: Query<(Entity, &mut RespawnPoint)>,
+    mut respawn_points: Query<(Entity, &StageIndex, &mut RespawnPoint)>,
     mut players: Query<(&mut PlayerDamage, &mut Body, &Transform, &Player)>,
     mut died: EventWriter<PlayerDied>,
 ) {
-    let mut respawn_points: Vec<(Entity, Mut<RespawnPoint>)> = respawn_points
+    let mut respawn_points: Vec<(Entity, &StageIndex, Mut<RespawnPoint>)> = respawn_points
         .iter_mut()
-        .filter(|p| p.1.occupied_by.is_none())
+        .filter(|p| p.2.occupied_by.is_none())
         .collect();
+    // Query order is not guaranteed to match between peers.
+    respawn_points.sort_by_key(|(_, index, _)| **index);
     let bounds: Vec<&Bounds2D> = blast_zones.iter().map(|bz| &bz.0).collect();
     players.for_each_mut(|(mut damage, mut body, transform, player)| {
         let position = transform.translation.xy();
@@ -285,7 +289,7 @@ pub(super) fn kill_players(
             let revive = damage.can_revive();
             if revive {
                 damage.revive();
-                let (respawn_entity, mut respawn_point) = respawn_points
+                let (respawn_entity, _, mut respawn_point) = respawn_points
                     .pop()
                     .expect("Player died and no available respawn points!");
                 respawn_point.occupied_by = Some(player.clone());
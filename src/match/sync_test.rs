@@ -0,0 +1,78 @@
+use super::backroll::{GameState, SyncTestSession};
+use bevy::prelude::*;
+use bevy_backroll::backroll;
+use std::path::Path;
+
+/// The directory mismatched sync test states are written to.
+const SYNC_TEST_DUMP_DIR: &str = "synctest";
+
+/// Configures the match to run under a backroll sync test session instead of a P2P
+/// session.
+///
+/// Every tick, the session rolls the simulation back by `check_distance` frames and
+/// re-simulates it with the same inputs. If the re-simulated state does not exactly match
+/// the state that was originally simulated, the simulation is not deterministic.
+#[derive(Clone, Debug)]
+pub struct SyncTestConfig {
+    pub check_distance: usize,
+}
+
+impl SyncTestConfig {
+    /// Creates a session running every player locally. Players must be added in the same
+    /// order they were given handles in.
+    pub(super) fn build_session(&self, players: usize) -> SyncTestSession {
+        let mut builder = SyncTestSession::build().with_check_distance(self.check_distance);
+        for _ in 0..players {
+            builder.add_player(backroll::Player::Local);
+        }
+        builder.start().unwrap()
+    }
+}
+
+/// Checks a state re-saved while re-simulating a frame against the state originally saved
+/// for it, dumping both and panicking if they differ.
+pub(super) fn check(expected: &GameState, actual: &GameState) {
+    if expected == actual {
+        return;
+    }
+    let frame = expected.frame();
+    let components = expected.checksum().diff(&actual.checksum());
+    error!(
+        "Sync test failed on frame {}. Mismatched components: {:?}",
+        frame, components
+    );
+    let dir = Path::new(SYNC_TEST_DUMP_DIR);
+    let result = std::fs::create_dir_all(dir)
+        .and_then(|_| {
+            std::fs::write(
+                dir.join(format!("frame-{}-expected.txt", frame)),
+                format!("{:#?}", expected),
+            )
+        })
+        .and_then(|_| {
+            std::fs::write(
+                dir.join(format!("frame-{}-actual.txt", frame)),
+                format!("{:#?}", actual),
+            )
+        });
+    if let Err(err) = result {
+        error!("Failed to dump sync test states: {}", err);
+    }
+    panic!(
+        "Simulation is not deterministic: desynced on frame {}",
+        frame
+    );
+}
+
+/// Runs the match simulation under a backroll sync test session.
+pub struct FcSyncTestPlugin {
+    pub check_distance: usize,
+}
+
+impl Plugin for FcSyncTestPlugin {
+    fn build(&self, builder: &mut AppBuilder) {
+        builder.insert_resource(SyncTestConfig {
+            check_distance: self.check_distance,
+        });
+    }
+}
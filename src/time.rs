@@ -3,7 +3,7 @@ pub const DELTA_TIME: f32 = 1.0 / 60.0;
 
 /// A simple timer for keeping track of countdowns in the number of game ticks
 /// that pass. Supports
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct FrameTimer(u16);
 
 impl FrameTimer {